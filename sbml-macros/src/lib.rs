@@ -16,7 +16,6 @@ pub fn attach(input: TokenStream) -> TokenStream {
     //println!("{:?}", input);
 
     let tag = &input.tag;
-    let tag_str = input.tag.to_string();
     let parents = &input.parents;
     let mut parent_field = input.tag.to_string().to_case(Case::Snake);
     let parent_field_ident: Ident;
@@ -47,8 +46,14 @@ pub fn attach(input: TokenStream) -> TokenStream {
     // also need strings for matching tokens
     let mut attr_str: Vec<String> = Vec::new();
     for ident in &attr_idents {
-        attr_str.push(ident.to_string().to_case(Case::Camel));
+        // the metaid attribute is not camel case
+        match ident.to_string().as_str() {
+            "meta_id" => attr_str.push("metaid".to_string()),
+            name => attr_str.push(name.to_case(Case::Camel)),
+        }
     }
+    // and type names for error messages
+    let attr_type_str: Vec<String> = attr_types.iter().map(|t| quote!(#t).to_string()).collect();
 
     let tokens = quote! {
         {
@@ -60,19 +65,29 @@ pub fn attach(input: TokenStream) -> TokenStream {
             // instantiate object of the tag that was found
             let mut #parent_field_ident = #tag::default();
             // parse any attributes, keeping their types in mind
-            let attributes = e.attributes().map(|a| a.unwrap()).collect::<Vec<_>>();
-            //println!("{:?}", attributes);
-            for attribute in attributes {
-                let key = str::from_utf8(attribute.key).unwrap();
-                let value = attribute.unescape_and_decode_value(&reader).unwrap();
+            for attribute in e.attributes() {
+                let attribute = attribute.map_err(|err| SbmlError::xml_syntax(err, &location))?;
+                let key = str::from_utf8(attribute.key)
+                    .map_err(|err| SbmlError::xml_syntax(err, &location))?;
+                let value = attribute
+                    .unescape_and_decode_value(&reader)
+                    .map_err(|err| SbmlError::xml_syntax(err, &location))?;
+                // in lenient mode, attributes that cannot be used are skipped
                 match key {
-                    #(#attr_str => {
-                        #parent_field_ident.#attr_idents =
-                            Some(value.parse::<#attr_types>().expect("Incorrect type"));
+                    #(#attr_str => match value.parse::<#attr_types>() {
+                        Ok(parsed) => #parent_field_ident.#attr_idents = Some(parsed),
+                        Err(_) => diagnostics.report(SbmlError::InvalidAttributeValue {
+                            attribute: key.to_string(),
+                            value: value.clone(),
+                            expected: #attr_type_str,
+                            location: location.clone(),
+                        })?,
                     })*
                     _ => {
-                        //errors.push(format!("Attribute '{}' not parsed for '{}'", key, #tag_str));
-                        panic!("Attribute '{}' not parsed for '{}'", key, #tag_str);
+                        diagnostics.report(SbmlError::UnknownAttribute {
+                            attribute: key.to_string(),
+                            location: location.clone(),
+                        })?;
                     }
                 }
             }
@@ -90,7 +105,14 @@ pub fn attach(input: TokenStream) -> TokenStream {
                     // push current pointer to stack
                     stack.push(current.clone());
                 })*
-                _ => {}
+                // the element is known but not allowed here
+                _ => {
+                    diagnostics.report(SbmlError::UnknownElement {
+                        element: element.clone(),
+                        location: location.clone(),
+                    })?;
+                    skip_subtree = true;
+                }
             }
         }
     };
@@ -176,7 +198,7 @@ impl Parse for OpenInput {
 
 #[proc_macro]
 pub fn attach_math(input: TokenStream) -> TokenStream {
-    let input = parse_macro_input!(input as MathInput);
+    let input = parse_macro_input!(input as ParentsInput);
     let parents = &input.parents;
 
     let tokens = quote! {
@@ -194,18 +216,38 @@ pub fn attach_math(input: TokenStream) -> TokenStream {
     tokens.into()
 }
 
+#[proc_macro]
+pub fn attach_xml(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as ParentsInput);
+    let parents = &input.parents;
+
+    let tokens = quote! {
+        match nodes[current] {
+            #(Tag::#parents(ref mut parent) => {
+                if e.name() == b"notes" {
+                    parent.notes = Some(content);
+                } else {
+                    parent.annotation = Some(content);
+                }
+            })*
+            _ => {}
+        }
+    };
+    tokens.into()
+}
+
 #[derive(Debug)]
-struct MathInput {
+struct ParentsInput {
     parents: Vec<Ident>,
 }
 
-impl Parse for MathInput {
+impl Parse for ParentsInput {
     fn parse(input: ParseStream) -> Result<Self> {
         let parsed_input =
             syn::punctuated::Punctuated::<Ident, Token![,]>::parse_terminated(input)?;
         let parents: Vec<Ident> = parsed_input.into_iter().collect();
 
-        Ok(MathInput { parents })
+        Ok(ParentsInput { parents })
     }
 }
 
@@ -226,7 +268,10 @@ pub fn close(input: TokenStream) -> TokenStream {
                 //println!("Closing {}", #tag_str);
             }
             _ => {
-                panic!("Attempted to close {} but currently in {:?}", #tag_str, nodes[current]);
+                return Err(SbmlError::XmlSyntax {
+                    message: format!("Attempted to close {} but currently in {:?}", #tag_str, nodes[current]),
+                    location,
+                });
             }
         }
     };
@@ -0,0 +1,635 @@
+use std::collections::HashMap;
+
+use crate::{
+    AlgebraicRule, AssignmentRule, Compartment, Constraint, Delay, Event, EventAssignment,
+    FunctionDefinition, InitialAssignment, KineticLaw, ListOfCompartments, ListOfConstraints,
+    ListOfEventAssignments, ListOfEvents, ListOfFunctionDefinitions, ListOfInitialAssignments,
+    ListOfLocalParameters, ListOfModifiers, ListOfParameters, ListOfProducts, ListOfReactants,
+    ListOfReactions, ListOfRules, ListOfSpecies, ListOfUnitDefinitions, ListOfUnits,
+    LocalParameter, Location, MathTag, Model, ModifierSpeciesReference, Parameter, Priority,
+    RateRule, Reaction, Root, SbmlError, Species, SpeciesReference, Tag, TagIndex, Trigger, Unit,
+    UnitDefinition,
+};
+
+// Builds a model in code, taking care of the arena, the ListOf* containers
+// and the parent pointers, e.g.
+//
+// ModelBuilder::new("m")
+//     .compartment(Compartment { id: Some("cell".to_string()), ..Default::default() })
+//     .reaction("R1", |r| r.reactant("A", 1.0).product("B", 1.0).kinetic_law("k * A"))
+//     .build()
+pub struct ModelBuilder {
+    model: Model,
+    // the first problem found, reported by build
+    error: Option<SbmlError>,
+}
+
+impl ModelBuilder {
+    pub fn new(id: &str) -> Self {
+        let mut model = Model::new(vec![Tag::Root(Root::default())], HashMap::new());
+        model.id = Some(id.to_string());
+        ModelBuilder { model, error: None }
+    }
+
+    pub fn compartment(mut self, mut compartment: Compartment) -> Self {
+        let list = self.list(
+            |root| &mut root.list_of_compartments,
+            Tag::ListOfCompartments(ListOfCompartments {
+                parent: Some(0),
+                ..Default::default()
+            }),
+        );
+        compartment.parent = Some(list);
+        let idx = self.model.add_node(Tag::Compartment(compartment));
+        if let Tag::ListOfCompartments(list) = &mut self.model.nodes[list] {
+            list.compartments.push(idx);
+        }
+        self
+    }
+
+    pub fn species(mut self, mut species: Species) -> Self {
+        let list = self.list(
+            |root| &mut root.list_of_species,
+            Tag::ListOfSpecies(ListOfSpecies {
+                parent: Some(0),
+                ..Default::default()
+            }),
+        );
+        species.parent = Some(list);
+        let idx = self.model.add_node(Tag::Species(species));
+        if let Tag::ListOfSpecies(list) = &mut self.model.nodes[list] {
+            list.species.push(idx);
+        }
+        self
+    }
+
+    pub fn parameter(mut self, mut parameter: Parameter) -> Self {
+        let list = self.list(
+            |root| &mut root.list_of_parameters,
+            Tag::ListOfParameters(ListOfParameters {
+                parent: Some(0),
+                ..Default::default()
+            }),
+        );
+        parameter.parent = Some(list);
+        let idx = self.model.add_node(Tag::Parameter(parameter));
+        if let Tag::ListOfParameters(list) = &mut self.model.nodes[list] {
+            list.parameters.push(idx);
+        }
+        self
+    }
+
+    // Adds a reaction described by the closure, irreversible unless it says
+    // otherwise
+    pub fn reaction<F>(mut self, id: &str, describe: F) -> Self
+    where
+        F: FnOnce(ReactionBuilder) -> ReactionBuilder,
+    {
+        let reaction = describe(ReactionBuilder::new(id));
+        let list = self.list(
+            |root| &mut root.list_of_reactions,
+            Tag::ListOfReactions(ListOfReactions {
+                parent: Some(0),
+                ..Default::default()
+            }),
+        );
+        let reaction_idx = self.model.add_node(Tag::Reaction(Reaction {
+            id: Some(reaction.id.clone()),
+            reversible: Some(reaction.reversible),
+            parent: Some(list),
+            ..Default::default()
+        }));
+        if let Tag::ListOfReactions(list) = &mut self.model.nodes[list] {
+            list.reactions.push(reaction_idx);
+        }
+
+        let reactants = self.species_references(reaction_idx, &reaction.reactants, true);
+        let products = self.species_references(reaction_idx, &reaction.products, false);
+        let modifiers = self.modifiers(reaction_idx, &reaction.modifiers);
+        let kinetic_law = match &reaction.kinetic_law {
+            Some(formula) => self.kinetic_law(reaction_idx, formula, &reaction.local_parameters),
+            None => None,
+        };
+        if let Tag::Reaction(r) = &mut self.model.nodes[reaction_idx] {
+            r.list_of_reactants = reactants;
+            r.list_of_products = products;
+            r.list_of_modifiers = modifiers;
+            r.kinetic_law = kinetic_law;
+        }
+        self
+    }
+
+    // Adds a unit definition made of the given units, e.g. per minute as a
+    // second with exponent -1 and multiplier 60
+    pub fn unit_definition(mut self, id: &str, units: Vec<Unit>) -> Self {
+        let list = self.list(
+            |root| &mut root.list_of_unit_definitions,
+            Tag::ListOfUnitDefinitions(ListOfUnitDefinitions {
+                parent: Some(0),
+                ..Default::default()
+            }),
+        );
+        let definition_idx = self.model.add_node(Tag::UnitDefinition(UnitDefinition {
+            id: Some(id.to_string()),
+            parent: Some(list),
+            ..Default::default()
+        }));
+        if let Tag::ListOfUnitDefinitions(list) = &mut self.model.nodes[list] {
+            list.unit_definitions.push(definition_idx);
+        }
+        let units_idx = self.model.add_node(Tag::ListOfUnits(ListOfUnits {
+            parent: Some(definition_idx),
+            ..Default::default()
+        }));
+        let mut indices = Vec::new();
+        for mut unit in units {
+            unit.parent = Some(units_idx);
+            indices.push(self.model.add_node(Tag::Unit(unit)));
+        }
+        if let Tag::ListOfUnits(list) = &mut self.model.nodes[units_idx] {
+            list.units = indices;
+        }
+        if let Tag::UnitDefinition(definition) = &mut self.model.nodes[definition_idx] {
+            definition.list_of_units = Some(units_idx);
+        }
+        self
+    }
+
+    // The function as a lambda formula, e.g. "lambda(x, y, x * y)"
+    pub fn function_definition(mut self, id: &str, formula: &str) -> Self {
+        let list = self.list(
+            |root| &mut root.list_of_function_definitions,
+            Tag::ListOfFunctionDefinitions(ListOfFunctionDefinitions {
+                parent: Some(0),
+                ..Default::default()
+            }),
+        );
+        let idx = self
+            .model
+            .add_node(Tag::FunctionDefinition(FunctionDefinition {
+                id: Some(id.to_string()),
+                parent: Some(list),
+                ..Default::default()
+            }));
+        if let Tag::ListOfFunctionDefinitions(list) = &mut self.model.nodes[list] {
+            list.function_definitions.push(idx);
+        }
+        let math = self.math(idx, formula);
+        if let Tag::FunctionDefinition(function_definition) = &mut self.model.nodes[idx] {
+            function_definition.math = math;
+        }
+        self
+    }
+
+    pub fn initial_assignment(mut self, symbol: &str, formula: &str) -> Self {
+        let list = self.list(
+            |root| &mut root.list_of_initial_assignments,
+            Tag::ListOfInitialAssignments(ListOfInitialAssignments {
+                parent: Some(0),
+                ..Default::default()
+            }),
+        );
+        let idx = self
+            .model
+            .add_node(Tag::InitialAssignment(InitialAssignment {
+                symbol: Some(symbol.to_string()),
+                parent: Some(list),
+                ..Default::default()
+            }));
+        if let Tag::ListOfInitialAssignments(list) = &mut self.model.nodes[list] {
+            list.initial_assignments.push(idx);
+        }
+        let math = self.math(idx, formula);
+        if let Tag::InitialAssignment(initial_assignment) = &mut self.model.nodes[idx] {
+            initial_assignment.math = math;
+        }
+        self
+    }
+
+    pub fn assignment_rule(mut self, variable: &str, formula: &str) -> Self {
+        let list = self.rules();
+        let idx = self.model.add_node(Tag::AssignmentRule(AssignmentRule {
+            variable: Some(variable.to_string()),
+            parent: Some(list),
+            ..Default::default()
+        }));
+        if let Tag::ListOfRules(list) = &mut self.model.nodes[list] {
+            list.assignment_rules.push(idx);
+        }
+        let math = self.math(idx, formula);
+        if let Tag::AssignmentRule(rule) = &mut self.model.nodes[idx] {
+            rule.math = math;
+        }
+        self
+    }
+
+    pub fn rate_rule(mut self, variable: &str, formula: &str) -> Self {
+        let list = self.rules();
+        let idx = self.model.add_node(Tag::RateRule(RateRule {
+            variable: Some(variable.to_string()),
+            parent: Some(list),
+            ..Default::default()
+        }));
+        if let Tag::ListOfRules(list) = &mut self.model.nodes[list] {
+            list.rate_rules.push(idx);
+        }
+        let math = self.math(idx, formula);
+        if let Tag::RateRule(rule) = &mut self.model.nodes[idx] {
+            rule.math = math;
+        }
+        self
+    }
+
+    // The formula is kept at zero
+    pub fn algebraic_rule(mut self, formula: &str) -> Self {
+        let list = self.rules();
+        let idx = self.model.add_node(Tag::AlgebraicRule(AlgebraicRule {
+            parent: Some(list),
+            ..Default::default()
+        }));
+        if let Tag::ListOfRules(list) = &mut self.model.nodes[list] {
+            list.algebraic_rules.push(idx);
+        }
+        let math = self.math(idx, formula);
+        if let Tag::AlgebraicRule(rule) = &mut self.model.nodes[idx] {
+            rule.math = math;
+        }
+        self
+    }
+
+    // Adds an event that fires when the trigger becomes true, e.g.
+    // .event("E1", "time > 10", |e| e.assignment("A", "0"))
+    pub fn event<F>(mut self, id: &str, trigger: &str, describe: F) -> Self
+    where
+        F: FnOnce(EventBuilder) -> EventBuilder,
+    {
+        let event = describe(EventBuilder::new());
+        let list = self.list(
+            |root| &mut root.list_of_events,
+            Tag::ListOfEvents(ListOfEvents {
+                parent: Some(0),
+                ..Default::default()
+            }),
+        );
+        let event_idx = self.model.add_node(Tag::Event(Event {
+            id: Some(id.to_string()),
+            use_values_from_trigger_time: Some(event.use_values_from_trigger_time),
+            parent: Some(list),
+            ..Default::default()
+        }));
+        if let Tag::ListOfEvents(list) = &mut self.model.nodes[list] {
+            list.events.push(event_idx);
+        }
+
+        let trigger_idx = self.model.add_node(Tag::Trigger(Trigger {
+            initial_value: Some(true),
+            persistent: Some(true),
+            parent: Some(event_idx),
+            ..Default::default()
+        }));
+        let math = self.math(trigger_idx, trigger);
+        if let Tag::Trigger(trigger) = &mut self.model.nodes[trigger_idx] {
+            trigger.math = math;
+        }
+        let priority = event.priority.map(|formula| {
+            let idx = self.model.add_node(Tag::Priority(Priority {
+                parent: Some(event_idx),
+                ..Default::default()
+            }));
+            let math = self.math(idx, &formula);
+            if let Tag::Priority(priority) = &mut self.model.nodes[idx] {
+                priority.math = math;
+            }
+            idx
+        });
+        let delay = event.delay.map(|formula| {
+            let idx = self.model.add_node(Tag::Delay(Delay {
+                parent: Some(event_idx),
+                ..Default::default()
+            }));
+            let math = self.math(idx, &formula);
+            if let Tag::Delay(delay) = &mut self.model.nodes[idx] {
+                delay.math = math;
+            }
+            idx
+        });
+        let mut list_of_event_assignments = None;
+        if !event.assignments.is_empty() {
+            let list_idx =
+                self.model
+                    .add_node(Tag::ListOfEventAssignments(ListOfEventAssignments {
+                        parent: Some(event_idx),
+                        ..Default::default()
+                    }));
+            let mut indices = Vec::new();
+            for (variable, formula) in &event.assignments {
+                let idx = self.model.add_node(Tag::EventAssignment(EventAssignment {
+                    variable: Some(variable.clone()),
+                    parent: Some(list_idx),
+                    ..Default::default()
+                }));
+                let math = self.math(idx, formula);
+                if let Tag::EventAssignment(assignment) = &mut self.model.nodes[idx] {
+                    assignment.math = math;
+                }
+                indices.push(idx);
+            }
+            if let Tag::ListOfEventAssignments(list) = &mut self.model.nodes[list_idx] {
+                list.event_assignments = indices;
+            }
+            list_of_event_assignments = Some(list_idx);
+        }
+
+        if let Tag::Event(e) = &mut self.model.nodes[event_idx] {
+            e.trigger = Some(trigger_idx);
+            e.priority = priority;
+            e.delay = delay;
+            e.list_of_event_assignments = list_of_event_assignments;
+        }
+        self
+    }
+
+    // The formula has to stay true during a simulation
+    pub fn constraint(mut self, formula: &str) -> Self {
+        let list = self.list(
+            |root| &mut root.list_of_constraints,
+            Tag::ListOfConstraints(ListOfConstraints {
+                parent: Some(0),
+                ..Default::default()
+            }),
+        );
+        let idx = self.model.add_node(Tag::Constraint(Constraint {
+            parent: Some(list),
+            ..Default::default()
+        }));
+        if let Tag::ListOfConstraints(list) = &mut self.model.nodes[list] {
+            list.constraints.push(idx);
+        }
+        let math = self.math(idx, formula);
+        if let Tag::Constraint(constraint) = &mut self.model.nodes[idx] {
+            constraint.math = math;
+        }
+        self
+    }
+
+    pub fn build(self) -> Result<Model, SbmlError> {
+        match self.error {
+            Some(error) => Err(error),
+            None => Ok(self.model),
+        }
+    }
+
+    // The index of a list below Root. On first use the empty list, whose
+    // parent is Root, is added.
+    fn list(&mut self, field: fn(&mut Root) -> &mut Option<TagIndex>, empty: Tag) -> TagIndex {
+        if let Tag::Root(root) = &mut self.model.nodes[0] {
+            if let Some(idx) = *field(root) {
+                return idx;
+            }
+        }
+        let idx = self.model.add_node(empty);
+        if let Tag::Root(root) = &mut self.model.nodes[0] {
+            *field(root) = Some(idx);
+        }
+        idx
+    }
+
+    fn rules(&mut self) -> TagIndex {
+        self.list(
+            |root| &mut root.list_of_rules,
+            Tag::ListOfRules(ListOfRules {
+                parent: Some(0),
+                ..Default::default()
+            }),
+        )
+    }
+
+    // Adds the math of an element, read from a Level 3 formula
+    fn math(&mut self, parent: TagIndex, formula: &str) -> Option<TagIndex> {
+        match MathTag::from_formula(formula) {
+            Ok(mut math_tag) => {
+                math_tag.parent = Some(parent);
+                Some(self.model.add_node(Tag::MathTag(math_tag)))
+            }
+            Err(message) => {
+                let location = Location::at(self.model.element_path(parent));
+                self.error
+                    .get_or_insert(SbmlError::InvalidMath { message, location });
+                None
+            }
+        }
+    }
+
+    fn species_references(
+        &mut self,
+        reaction_idx: TagIndex,
+        references: &[(String, f64)],
+        reactants: bool,
+    ) -> Option<TagIndex> {
+        if references.is_empty() {
+            return None;
+        }
+        let list_idx = self.model.add_node(if reactants {
+            Tag::ListOfReactants(ListOfReactants {
+                parent: Some(reaction_idx),
+                ..Default::default()
+            })
+        } else {
+            Tag::ListOfProducts(ListOfProducts {
+                parent: Some(reaction_idx),
+                ..Default::default()
+            })
+        });
+        let mut indices = Vec::new();
+        for (species, stoichiometry) in references {
+            indices.push(self.model.add_node(Tag::SpeciesReference(SpeciesReference {
+                species: Some(species.clone()),
+                stoichiometry: Some(*stoichiometry),
+                constant: Some(true),
+                parent: Some(list_idx),
+                ..Default::default()
+            })));
+        }
+        match &mut self.model.nodes[list_idx] {
+            Tag::ListOfReactants(list) => list.species_references = indices,
+            Tag::ListOfProducts(list) => list.species_references = indices,
+            _ => {}
+        }
+        Some(list_idx)
+    }
+
+    fn modifiers(&mut self, reaction_idx: TagIndex, modifiers: &[String]) -> Option<TagIndex> {
+        if modifiers.is_empty() {
+            return None;
+        }
+        let list_idx = self.model.add_node(Tag::ListOfModifiers(ListOfModifiers {
+            parent: Some(reaction_idx),
+            ..Default::default()
+        }));
+        let mut indices = Vec::new();
+        for species in modifiers {
+            indices.push(self.model.add_node(Tag::ModifierSpeciesReference(
+                ModifierSpeciesReference {
+                    species: Some(species.clone()),
+                    parent: Some(list_idx),
+                    ..Default::default()
+                },
+            )));
+        }
+        if let Tag::ListOfModifiers(list) = &mut self.model.nodes[list_idx] {
+            list.modifier_species_references = indices;
+        }
+        Some(list_idx)
+    }
+
+    fn kinetic_law(
+        &mut self,
+        reaction_idx: TagIndex,
+        formula: &str,
+        local_parameters: &[(String, f64)],
+    ) -> Option<TagIndex> {
+        let mut math_tag = match MathTag::from_formula(formula) {
+            Ok(math_tag) => math_tag,
+            Err(message) => {
+                let location = format!("{}/kineticLaw", self.model.element_path(reaction_idx));
+                self.error.get_or_insert(SbmlError::InvalidMath {
+                    message,
+                    location: Location::at(location),
+                });
+                return None;
+            }
+        };
+        let kinetic_law_idx = self.model.add_node(Tag::KineticLaw(KineticLaw {
+            parent: Some(reaction_idx),
+            ..Default::default()
+        }));
+        math_tag.parent = Some(kinetic_law_idx);
+        let math_idx = self.model.add_node(Tag::MathTag(math_tag));
+
+        let mut list_of_local_parameters = None;
+        if !local_parameters.is_empty() {
+            let list_idx = self
+                .model
+                .add_node(Tag::ListOfLocalParameters(ListOfLocalParameters {
+                    parent: Some(kinetic_law_idx),
+                    ..Default::default()
+                }));
+            let mut indices = Vec::new();
+            for (id, value) in local_parameters {
+                indices.push(self.model.add_node(Tag::LocalParameter(LocalParameter {
+                    id: Some(id.clone()),
+                    value: Some(*value),
+                    parent: Some(list_idx),
+                    ..Default::default()
+                })));
+            }
+            if let Tag::ListOfLocalParameters(list) = &mut self.model.nodes[list_idx] {
+                list.local_parameters = indices;
+            }
+            list_of_local_parameters = Some(list_idx);
+        }
+
+        if let Tag::KineticLaw(kinetic_law) = &mut self.model.nodes[kinetic_law_idx] {
+            kinetic_law.math = Some(math_idx);
+            kinetic_law.list_of_local_parameters = list_of_local_parameters;
+        }
+        Some(kinetic_law_idx)
+    }
+}
+
+// Describes a reaction for ModelBuilder::reaction
+pub struct ReactionBuilder {
+    id: String,
+    reversible: bool,
+    reactants: Vec<(String, f64)>,
+    products: Vec<(String, f64)>,
+    modifiers: Vec<String>,
+    kinetic_law: Option<String>,
+    local_parameters: Vec<(String, f64)>,
+}
+
+impl ReactionBuilder {
+    fn new(id: &str) -> Self {
+        ReactionBuilder {
+            id: id.to_string(),
+            reversible: false,
+            reactants: Vec::new(),
+            products: Vec::new(),
+            modifiers: Vec::new(),
+            kinetic_law: None,
+            local_parameters: Vec::new(),
+        }
+    }
+
+    pub fn reversible(mut self, reversible: bool) -> Self {
+        self.reversible = reversible;
+        self
+    }
+
+    pub fn reactant(mut self, species: &str, stoichiometry: f64) -> Self {
+        self.reactants.push((species.to_string(), stoichiometry));
+        self
+    }
+
+    pub fn product(mut self, species: &str, stoichiometry: f64) -> Self {
+        self.products.push((species.to_string(), stoichiometry));
+        self
+    }
+
+    pub fn modifier(mut self, species: &str) -> Self {
+        self.modifiers.push(species.to_string());
+        self
+    }
+
+    // The rate as a Level 3 formula, e.g. "k * A"
+    pub fn kinetic_law(mut self, formula: &str) -> Self {
+        self.kinetic_law = Some(formula.to_string());
+        self
+    }
+
+    pub fn local_parameter(mut self, id: &str, value: f64) -> Self {
+        self.local_parameters.push((id.to_string(), value));
+        self
+    }
+}
+
+// Describes an event for ModelBuilder::event
+pub struct EventBuilder {
+    use_values_from_trigger_time: bool,
+    priority: Option<String>,
+    delay: Option<String>,
+    assignments: Vec<(String, String)>,
+}
+
+impl EventBuilder {
+    fn new() -> Self {
+        EventBuilder {
+            use_values_from_trigger_time: true,
+            priority: None,
+            delay: None,
+            assignments: Vec::new(),
+        }
+    }
+
+    pub fn use_values_from_trigger_time(mut self, use_values_from_trigger_time: bool) -> Self {
+        self.use_values_from_trigger_time = use_values_from_trigger_time;
+        self
+    }
+
+    pub fn priority(mut self, formula: &str) -> Self {
+        self.priority = Some(formula.to_string());
+        self
+    }
+
+    pub fn delay(mut self, formula: &str) -> Self {
+        self.delay = Some(formula.to_string());
+        self
+    }
+
+    // Sets the variable to the value of the formula when the event fires
+    pub fn assignment(mut self, variable: &str, formula: &str) -> Self {
+        self.assignments
+            .push((variable.to_string(), formula.to_string()));
+        self
+    }
+}
@@ -0,0 +1,308 @@
+use std::cell::RefCell;
+use std::error::Error;
+use std::fmt;
+use std::io::{self, BufRead, Read};
+use std::rc::Rc;
+
+use crate::Strictness;
+
+// Where in a document something was found
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Location {
+    // slash separated element names, e.g. /sbml/model/listOfSpecies/species
+    pub path: String,
+    // line and column are only known while the document is being read
+    pub line: Option<usize>,
+    pub column: Option<usize>,
+}
+
+impl Location {
+    pub fn at(path: String) -> Self {
+        Location {
+            path,
+            line: None,
+            column: None,
+        }
+    }
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.path)?;
+        if let (Some(line), Some(column)) = (self.line, self.column) {
+            write!(f, " (line {}, column {})", line, column)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum SbmlError {
+    Io {
+        filename: String,
+        message: String,
+    },
+    XmlSyntax {
+        message: String,
+        location: Location,
+    },
+    UnknownElement {
+        element: String,
+        location: Location,
+    },
+    UnknownAttribute {
+        attribute: String,
+        location: Location,
+    },
+    InvalidAttributeValue {
+        attribute: String,
+        value: String,
+        expected: &'static str,
+        location: Location,
+    },
+    MissingRequiredAttribute {
+        attribute: String,
+        location: Location,
+    },
+    DanglingReference {
+        attribute: String,
+        value: String,
+        location: Location,
+    },
+    InvalidMath {
+        message: String,
+        location: Location,
+    },
+    Write {
+        message: String,
+    },
+}
+
+impl SbmlError {
+    pub fn xml_syntax<E: fmt::Display>(error: E, location: &Location) -> Self {
+        SbmlError::XmlSyntax {
+            message: error.to_string(),
+            location: location.clone(),
+        }
+    }
+
+    pub fn location(&self) -> Option<&Location> {
+        match self {
+            SbmlError::Io { .. } | SbmlError::Write { .. } => None,
+            SbmlError::XmlSyntax { location, .. }
+            | SbmlError::UnknownElement { location, .. }
+            | SbmlError::UnknownAttribute { location, .. }
+            | SbmlError::InvalidAttributeValue { location, .. }
+            | SbmlError::MissingRequiredAttribute { location, .. }
+            | SbmlError::DanglingReference { location, .. }
+            | SbmlError::InvalidMath { location, .. } => Some(location),
+        }
+    }
+}
+
+impl SbmlError {
+    // What went wrong, without saying where
+    pub fn description(&self) -> String {
+        match self {
+            SbmlError::Io { filename, message } => {
+                format!("Could not read {}: {}", filename, message)
+            }
+            SbmlError::XmlSyntax { message, .. } => format!("XML error: {}", message),
+            SbmlError::UnknownElement { element, .. } => {
+                format!("Unknown element '{}'", element)
+            }
+            SbmlError::UnknownAttribute { attribute, .. } => {
+                format!("Unknown attribute '{}'", attribute)
+            }
+            SbmlError::InvalidAttributeValue {
+                attribute,
+                value,
+                expected,
+                ..
+            } => format!(
+                "Invalid value '{}' for attribute '{}', expected {}",
+                value, attribute, expected
+            ),
+            SbmlError::MissingRequiredAttribute { attribute, .. } => {
+                format!("Missing required attribute '{}'", attribute)
+            }
+            SbmlError::DanglingReference {
+                attribute, value, ..
+            } => format!(
+                "Attribute '{}' refers to '{}' which does not exist",
+                attribute, value
+            ),
+            SbmlError::InvalidMath { message, .. } => format!("Invalid math: {}", message),
+            SbmlError::Write { message } => format!("Could not write SBML: {}", message),
+        }
+    }
+}
+
+impl fmt::Display for SbmlError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.location() {
+            Some(location) => write!(f, "{} at {}", self.description(), location),
+            None => write!(f, "{}", self.description()),
+        }
+    }
+}
+
+impl Error for SbmlError {}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+// A problem with a document that did not stop it from being read, or that
+// Model::validate found in a model
+#[derive(Clone, Debug, PartialEq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    // libSBML's identifier of the validation rule, e.g. sbml-10301
+    pub rule: Option<&'static str>,
+    pub message: String,
+    pub location: Location,
+}
+
+impl Diagnostic {
+    pub fn from_error(severity: Severity, error: &SbmlError) -> Self {
+        Diagnostic {
+            severity,
+            rule: None,
+            message: error.description(),
+            location: error.location().cloned().unwrap_or_default(),
+        }
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let severity = match self.severity {
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        };
+        match self.rule {
+            Some(rule) => write!(
+                f,
+                "{} [{}]: {} at {}",
+                severity, rule, self.message, self.location
+            ),
+            None => write!(f, "{}: {} at {}", severity, self.message, self.location),
+        }
+    }
+}
+
+// Decides whether a problem found while parsing aborts the parse or
+// is only recorded, depending on how strict the parse is.
+pub(crate) struct Diagnostics {
+    strictness: Strictness,
+    pub(crate) warnings: Vec<Diagnostic>,
+}
+
+impl Diagnostics {
+    pub(crate) fn new(strictness: Strictness) -> Self {
+        Diagnostics {
+            strictness,
+            warnings: Vec::new(),
+        }
+    }
+
+    pub(crate) fn report(&mut self, error: SbmlError) -> Result<(), SbmlError> {
+        match self.strictness {
+            Strictness::Strict => Err(error),
+            Strictness::Lenient => {
+                self.warnings
+                    .push(Diagnostic::from_error(Severity::Warning, &error));
+                Ok(())
+            }
+        }
+    }
+}
+
+// Wraps a source and remembers where each line and the latest tag start, so
+// that positions in the XML reader can be turned into lines and columns.
+pub(crate) struct LineTracker<R> {
+    inner: R,
+    consumed: usize,
+    positions: Rc<RefCell<Positions>>,
+}
+
+#[derive(Default)]
+struct Positions {
+    line_starts: Vec<usize>,
+    last_tag_start: usize,
+}
+
+#[derive(Clone)]
+pub(crate) struct LineIndex {
+    positions: Rc<RefCell<Positions>>,
+}
+
+impl<R: BufRead> LineTracker<R> {
+    pub(crate) fn new(inner: R) -> (Self, LineIndex) {
+        let positions = Rc::new(RefCell::new(Positions {
+            line_starts: vec![0],
+            last_tag_start: 0,
+        }));
+        let tracker = LineTracker {
+            inner,
+            consumed: 0,
+            positions: Rc::clone(&positions),
+        };
+        (tracker, LineIndex { positions })
+    }
+}
+
+impl<R: BufRead> Read for LineTracker<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let available = self.fill_buf()?;
+        let amt = available.len().min(buf.len());
+        buf[..amt].copy_from_slice(&available[..amt]);
+        self.consume(amt);
+        Ok(amt)
+    }
+}
+
+impl<R: BufRead> BufRead for LineTracker<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.inner.fill_buf()
+    }
+
+    fn consume(&mut self, amt: usize) {
+        // the bytes being consumed are still in the inner buffer
+        if let Ok(data) = self.inner.fill_buf() {
+            let mut positions = self.positions.borrow_mut();
+            for (i, byte) in data[..amt.min(data.len())].iter().enumerate() {
+                match byte {
+                    b'\n' => positions.line_starts.push(self.consumed + i + 1),
+                    b'<' => positions.last_tag_start = self.consumed + i,
+                    _ => {}
+                }
+            }
+        }
+        self.consumed += amt;
+        self.inner.consume(amt);
+    }
+}
+
+impl LineIndex {
+    pub(crate) fn locate(&self, offset: usize, path: &[String]) -> Location {
+        let positions = self.positions.borrow();
+        let line = positions
+            .line_starts
+            .partition_point(|start| *start <= offset);
+        Location {
+            path: format!("/{}", path.join("/")),
+            line: Some(line),
+            column: Some(offset - positions.line_starts[line - 1] + 1),
+        }
+    }
+
+    // Location of the tag the reader has just returned
+    pub(crate) fn locate_tag(&self, path: &[String]) -> Location {
+        let offset = self.positions.borrow().last_tag_start;
+        self.locate(offset, path)
+    }
+}
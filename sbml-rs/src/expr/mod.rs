@@ -0,0 +1,443 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::ops;
+
+use mathml_rs::{
+    Apply, BVar, Ci, Cn, Constant, ConstantNode, Csymbol, Lambda, MathNode, NodeIndex, NumType,
+    Number, Op, OpNode, Otherwise, Piece, Piecewise, Root,
+};
+
+use crate::mathml::link_child;
+use crate::MathTag;
+
+pub const SYMBOLS_URL: &str = "http://www.sbml.org/sbml/symbols/";
+
+// An expression as a tree, for building math without index arithmetic.
+// Turn it into a MathTag with to_math_tag, or get one back with MathTag::to_expr.
+//
+//     let rate = Expr::ci("k").times(Expr::ci("S")).div(Expr::ci("C"));
+#[derive(Clone, Debug)]
+pub enum Expr {
+    // a number and its units
+    Number(Number, Option<String>),
+    Ci(String),
+    Constant(Constant),
+    // a csymbol such as time, by definitionURL
+    Csymbol(String),
+    Apply(Op, Vec<Expr>),
+    // a call of a function definition
+    Call(String, Vec<Expr>),
+    // a call of a csymbol function such as delay, by definitionURL
+    CsymbolCall(String, Vec<Expr>),
+    // pieces of value and condition, and the otherwise value
+    Piecewise(Vec<(Expr, Expr)>, Option<Box<Expr>>),
+    // bound variables and body of a function definition
+    Lambda(Vec<String>, Box<Expr>),
+}
+
+impl Expr {
+    pub fn ci(name: &str) -> Self {
+        Expr::Ci(name.to_string())
+    }
+
+    pub fn integer(value: i32) -> Self {
+        Expr::Number(Number::Integer(value), None)
+    }
+
+    pub fn real(value: f64) -> Self {
+        Expr::Number(Number::Real(value), None)
+    }
+
+    // time, avogadro, delay or rateOf
+    pub fn symbol(name: &str) -> Self {
+        Expr::Csymbol(format!("{}{}", SYMBOLS_URL, name))
+    }
+
+    pub fn apply(op: Op, operands: Vec<Expr>) -> Self {
+        Expr::Apply(op, operands)
+    }
+
+    pub fn call(function: &str, args: Vec<Expr>) -> Self {
+        Expr::Call(function.to_string(), args)
+    }
+
+    // Chains of plus and times stay a single n-ary apply
+    pub fn plus(self, rhs: Expr) -> Self {
+        self.join(Op::Plus, rhs)
+    }
+
+    pub fn minus(self, rhs: Expr) -> Self {
+        Expr::Apply(Op::Minus, vec![self, rhs])
+    }
+
+    pub fn times(self, rhs: Expr) -> Self {
+        self.join(Op::Times, rhs)
+    }
+
+    #[allow(clippy::should_implement_trait)]
+    pub fn div(self, rhs: Expr) -> Self {
+        Expr::Apply(Op::Divide, vec![self, rhs])
+    }
+
+    pub fn pow(self, rhs: Expr) -> Self {
+        Expr::Apply(Op::Power, vec![self, rhs])
+    }
+
+    pub(crate) fn join(self, op: Op, rhs: Expr) -> Self {
+        let same = |candidate: &Op| {
+            matches!(
+                (&op, candidate),
+                (Op::Plus, Op::Plus)
+                    | (Op::Times, Op::Times)
+                    | (Op::And, Op::And)
+                    | (Op::Or, Op::Or)
+            )
+        };
+        match self {
+            Expr::Apply(lhs_op, mut operands) if same(&lhs_op) => {
+                operands.push(rhs);
+                Expr::Apply(lhs_op, operands)
+            }
+            lhs => Expr::Apply(op, vec![lhs, rhs]),
+        }
+    }
+
+    // Replaces identifiers for which replace returns an expression,
+    // leaving the bound variables of lambdas alone
+    pub fn replace_ci(self, replace: &dyn Fn(&str) -> Option<Expr>) -> Self {
+        self.replace_leaves(&|leaf: &Expr| match leaf {
+            Expr::Ci(name) => replace(name),
+            _ => None,
+        })
+    }
+
+    // Replaces every use of a csymbol such as time
+    pub fn replace_symbol(self, name: &str, replacement: &Expr) -> Self {
+        let url = format!("{}{}", SYMBOLS_URL, name);
+        self.replace_leaves(&|leaf: &Expr| match leaf {
+            Expr::Csymbol(symbol) if *symbol == url => Some(replacement.clone()),
+            _ => None,
+        })
+    }
+
+    fn replace_leaves(self, replace: &dyn Fn(&Expr) -> Option<Expr>) -> Self {
+        let all = |exprs: Vec<Expr>| {
+            exprs
+                .into_iter()
+                .map(|e| e.replace_leaves(replace))
+                .collect()
+        };
+        match self {
+            Expr::Apply(op, operands) => Expr::Apply(op, all(operands)),
+            Expr::Call(name, args) => Expr::Call(name, all(args)),
+            Expr::CsymbolCall(url, args) => Expr::CsymbolCall(url, all(args)),
+            Expr::Piecewise(pieces, otherwise) => Expr::Piecewise(
+                pieces
+                    .into_iter()
+                    .map(|(value, condition)| {
+                        (
+                            value.replace_leaves(replace),
+                            condition.replace_leaves(replace),
+                        )
+                    })
+                    .collect(),
+                otherwise.map(|otherwise| Box::new(otherwise.replace_leaves(replace))),
+            ),
+            Expr::Lambda(bindings, body) => {
+                let body = body.replace_leaves(&|leaf: &Expr| match leaf {
+                    Expr::Ci(name) if bindings.iter().any(|binding| binding == name) => None,
+                    leaf => replace(leaf),
+                });
+                Expr::Lambda(bindings, Box::new(body))
+            }
+            leaf => replace(&leaf).unwrap_or(leaf),
+        }
+    }
+
+    // Flattens the expression into a MathTag with consistent indices
+    pub fn to_math_tag(&self) -> MathTag {
+        let mut nodes = vec![MathNode::Root(Root::default())];
+        let mut number_units = HashMap::new();
+        emit(self.clone(), &mut nodes, &mut number_units, 0);
+        MathTag {
+            nodes,
+            parent: None,
+            number_units,
+        }
+    }
+
+    // Reads the subtree at idx back into an expression, with the expression
+    // at `replace.0` swapped for `replace.1`
+    pub(crate) fn from_nodes(
+        math_tag: &MathTag,
+        idx: NodeIndex,
+        replace: Option<(NodeIndex, &Expr)>,
+    ) -> Result<Self, String> {
+        if let Some((replaced, expr)) = replace {
+            if replaced == idx {
+                return Ok(expr.clone());
+            }
+        }
+        let nodes = &math_tag.nodes;
+        let read = |idx: NodeIndex| Expr::from_nodes(math_tag, idx, replace);
+        let read_all = |indices: &[NodeIndex]| -> Result<Vec<Expr>, String> {
+            indices.iter().map(|idx| read(*idx)).collect()
+        };
+        let expr = match &nodes[idx] {
+            MathNode::Root(root) => match root.children.first() {
+                Some(child) => read(*child)?,
+                None => return Err("Empty math".to_string()),
+            },
+            MathNode::Apply(apply) => {
+                let operands = read_all(&apply.operands)?;
+                match apply.operator.map(|operator| &nodes[operator]) {
+                    Some(MathNode::Op(OpNode { op: Some(op), .. })) => {
+                        Expr::Apply(op.clone(), operands)
+                    }
+                    Some(MathNode::Ci(Ci {
+                        name: Some(name), ..
+                    })) => Expr::Call(name.clone(), operands),
+                    Some(MathNode::Csymbol(Csymbol {
+                        definition_url: Some(url),
+                        ..
+                    })) => Expr::CsymbolCall(url.clone(), operands),
+                    _ => return Err(format!("Apply at node {} has no operator", idx)),
+                }
+            }
+            MathNode::Ci(Ci {
+                name: Some(name), ..
+            }) => Expr::Ci(name.clone()),
+            MathNode::Cn(Cn {
+                value: Some(value), ..
+            }) => Expr::Number(value.clone(), math_tag.number_units.get(&idx).cloned()),
+            MathNode::Csymbol(Csymbol {
+                definition_url: Some(url),
+                ..
+            }) => Expr::Csymbol(url.clone()),
+            MathNode::Constant(ConstantNode {
+                constant: Some(constant),
+                ..
+            }) => Expr::Constant(constant.clone()),
+            MathNode::Piecewise(piecewise) => {
+                let mut pieces = Vec::new();
+                for piece in &piecewise.pieces {
+                    match &nodes[*piece] {
+                        MathNode::Piece(Piece {
+                            expr: Some(value),
+                            condition: Some(condition),
+                            ..
+                        }) => pieces.push((read(*value)?, read(*condition)?)),
+                        _ => return Err(format!("Incomplete piece at node {}", piece)),
+                    }
+                }
+                let otherwise = match piecewise.otherwise.map(|otherwise| &nodes[otherwise]) {
+                    Some(MathNode::Otherwise(Otherwise {
+                        expr: Some(value), ..
+                    })) => Some(Box::new(read(*value)?)),
+                    Some(_) => return Err(format!("Incomplete otherwise in node {}", idx)),
+                    None => None,
+                };
+                Expr::Piecewise(pieces, otherwise)
+            }
+            MathNode::Lambda(lambda) => {
+                let mut bindings = Vec::new();
+                for binding in &lambda.bindings {
+                    match &nodes[*binding] {
+                        MathNode::Bvar(bvar) => match bvar.children.first().map(|c| &nodes[*c]) {
+                            Some(MathNode::Ci(Ci {
+                                name: Some(name), ..
+                            })) => bindings.push(name.clone()),
+                            _ => return Err(format!("Empty bvar at node {}", binding)),
+                        },
+                        _ => return Err(format!("Node {} is not a bvar", binding)),
+                    }
+                }
+                match lambda.expr {
+                    Some(body) => Expr::Lambda(bindings, Box::new(read(body)?)),
+                    None => return Err(format!("Lambda at node {} has no body", idx)),
+                }
+            }
+            node => return Err(format!("Unexpected node {} at {}", node, idx)),
+        };
+        Ok(expr)
+    }
+}
+
+// As an infix formula, see MathTag::to_infix
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.to_math_tag().to_infix())
+    }
+}
+
+impl ops::Add for Expr {
+    type Output = Expr;
+
+    fn add(self, rhs: Expr) -> Expr {
+        self.plus(rhs)
+    }
+}
+
+impl ops::Sub for Expr {
+    type Output = Expr;
+
+    fn sub(self, rhs: Expr) -> Expr {
+        self.minus(rhs)
+    }
+}
+
+impl ops::Mul for Expr {
+    type Output = Expr;
+
+    fn mul(self, rhs: Expr) -> Expr {
+        self.times(rhs)
+    }
+}
+
+impl ops::Div for Expr {
+    type Output = Expr;
+
+    fn div(self, rhs: Expr) -> Expr {
+        Expr::div(self, rhs)
+    }
+}
+
+impl ops::Neg for Expr {
+    type Output = Expr;
+
+    fn neg(self) -> Expr {
+        Expr::Apply(Op::Minus, vec![self])
+    }
+}
+
+// Appends the expression below parent, returning the index of its node
+fn emit(
+    expr: Expr,
+    nodes: &mut Vec<MathNode>,
+    number_units: &mut HashMap<NodeIndex, String>,
+    parent: NodeIndex,
+) -> NodeIndex {
+    let idx = nodes.len();
+    let (node, operator, children) = match expr {
+        Expr::Number(value, units) => {
+            let r#type = match value {
+                Number::Integer(_) => NumType::Integer,
+                Number::Rational(..) => NumType::Rational,
+                Number::ENotation(..) => NumType::ENotation,
+                _ => NumType::Real,
+            };
+            if let Some(units) = units {
+                number_units.insert(idx, units);
+            }
+            let node = MathNode::Cn(Cn {
+                r#type: Some(r#type),
+                value: Some(value),
+                parent: Some(parent),
+                ..Default::default()
+            });
+            (node, None, Vec::new())
+        }
+        Expr::Ci(name) => (ci(name, parent), None, Vec::new()),
+        Expr::Constant(constant) => {
+            let node = MathNode::Constant(ConstantNode {
+                constant: Some(constant),
+                parent: Some(parent),
+            });
+            (node, None, Vec::new())
+        }
+        Expr::Csymbol(url) => (csymbol(url, parent), None, Vec::new()),
+        Expr::Apply(op, operands) => {
+            let operator = MathNode::Op(OpNode {
+                op: Some(op),
+                parent: Some(idx),
+            });
+            (apply(parent), Some(operator), operands)
+        }
+        Expr::Call(name, args) => (apply(parent), Some(ci(name, idx)), args),
+        Expr::CsymbolCall(url, args) => (apply(parent), Some(csymbol(url, idx)), args),
+        Expr::Piecewise(pieces, otherwise) => {
+            let node = MathNode::Piecewise(Piecewise {
+                parent: Some(parent),
+                ..Default::default()
+            });
+            link_child(nodes, parent, idx, &node);
+            nodes.push(node);
+            for (value, condition) in pieces {
+                let piece = MathNode::Piece(Piece {
+                    parent: Some(idx),
+                    ..Default::default()
+                });
+                let piece_idx = push(nodes, idx, piece);
+                emit(value, nodes, number_units, piece_idx);
+                emit(condition, nodes, number_units, piece_idx);
+            }
+            if let Some(value) = otherwise {
+                let otherwise = MathNode::Otherwise(Otherwise {
+                    parent: Some(idx),
+                    ..Default::default()
+                });
+                let otherwise_idx = push(nodes, idx, otherwise);
+                emit(*value, nodes, number_units, otherwise_idx);
+            }
+            return idx;
+        }
+        Expr::Lambda(bindings, body) => {
+            let node = MathNode::Lambda(Lambda {
+                parent: Some(parent),
+                ..Default::default()
+            });
+            link_child(nodes, parent, idx, &node);
+            nodes.push(node);
+            for binding in bindings {
+                let bvar = MathNode::Bvar(BVar {
+                    parent: Some(idx),
+                    ..Default::default()
+                });
+                let bvar_idx = push(nodes, idx, bvar);
+                push(nodes, bvar_idx, ci(binding, bvar_idx));
+            }
+            emit(*body, nodes, number_units, idx);
+            return idx;
+        }
+    };
+    link_child(nodes, parent, idx, &node);
+    nodes.push(node);
+    if let Some(operator) = operator {
+        push(nodes, idx, operator);
+    }
+    for child in children {
+        emit(child, nodes, number_units, idx);
+    }
+    idx
+}
+
+fn push(nodes: &mut Vec<MathNode>, parent: NodeIndex, node: MathNode) -> NodeIndex {
+    let idx = nodes.len();
+    link_child(nodes, parent, idx, &node);
+    nodes.push(node);
+    idx
+}
+
+fn ci(name: String, parent: NodeIndex) -> MathNode {
+    MathNode::Ci(Ci {
+        name: Some(name),
+        parent: Some(parent),
+        ..Default::default()
+    })
+}
+
+fn csymbol(url: String, parent: NodeIndex) -> MathNode {
+    MathNode::Csymbol(Csymbol {
+        definition_url: Some(url),
+        encoding: Some("text".to_string()),
+        parent: Some(parent),
+        ..Default::default()
+    })
+}
+
+fn apply(parent: NodeIndex) -> MathNode {
+    MathNode::Apply(Apply {
+        parent: Some(parent),
+        ..Default::default()
+    })
+}
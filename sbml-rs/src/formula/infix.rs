@@ -0,0 +1,241 @@
+use std::collections::HashMap;
+
+use mathml_rs::{MathNode, NodeIndex, Number, Op};
+
+use crate::mathml::{constant_name, op_name};
+use crate::writer::format_double;
+
+// Operator precedence of the Level 3 formula syntax, loosest first
+const LOGICAL: u8 = 2;
+const RELATIONAL: u8 = 3;
+const SUM: u8 = 4;
+const PRODUCT: u8 = 5;
+const UNARY: u8 = 6;
+const POWER: u8 = 7;
+const ATOM: u8 = 8;
+
+// Renders the nodes of a MathTag as a Level 3 infix formula, e.g. k1 * S1 / cell.
+// Parentheses are only added where reading the formula back would otherwise
+// give a different tree.
+pub(crate) fn to_infix(nodes: &[MathNode], number_units: &HashMap<NodeIndex, String>) -> String {
+    match nodes.first() {
+        Some(MathNode::Root(root)) => root
+            .children
+            .iter()
+            .map(|child| render(nodes, number_units, *child).0)
+            .collect::<Vec<_>>()
+            .join(", "),
+        Some(_) => render(nodes, number_units, 0).0,
+        None => String::new(),
+    }
+}
+
+// The text of a node and the precedence of its outermost operator
+fn render(
+    nodes: &[MathNode],
+    number_units: &HashMap<NodeIndex, String>,
+    idx: NodeIndex,
+) -> (String, u8) {
+    match &nodes[idx] {
+        MathNode::Apply(apply) => {
+            let operands: Vec<(String, u8)> = apply
+                .operands
+                .iter()
+                .map(|operand| render(nodes, number_units, *operand))
+                .collect();
+            match apply.operator.map(|operator| &nodes[operator]) {
+                Some(MathNode::Op(op)) => match &op.op {
+                    Some(op) => render_op(op, operands),
+                    None => call("apply", &operands),
+                },
+                // function definitions and csymbols such as delay
+                Some(operator) => call(&render_name(operator), &operands),
+                None => call("apply", &operands),
+            }
+        }
+        MathNode::Ci(ci) => (ci.name.clone().unwrap_or_default(), ATOM),
+        MathNode::Cn(cn) => {
+            let (text, precedence) = match &cn.value {
+                Some(value) => render_number(value),
+                None => (String::new(), ATOM),
+            };
+            match number_units.get(&idx) {
+                Some(units) => (format!("{} {}", text, units), precedence),
+                None => (text, precedence),
+            }
+        }
+        MathNode::Csymbol(_) | MathNode::Constant(_) => (render_name(&nodes[idx]), ATOM),
+        MathNode::Piecewise(piecewise) => call(
+            "piecewise",
+            &children(nodes, number_units, &piecewise.children),
+        ),
+        MathNode::Piece(piece) => (
+            join_args(&children(nodes, number_units, &piece.children)),
+            ATOM,
+        ),
+        MathNode::Otherwise(otherwise) => (
+            join_args(&children(nodes, number_units, &otherwise.children)),
+            ATOM,
+        ),
+        MathNode::Bvar(bvar) => (
+            join_args(&children(nodes, number_units, &bvar.children)),
+            ATOM,
+        ),
+        MathNode::Lambda(lambda) => {
+            let mut args = children(nodes, number_units, &lambda.bindings);
+            if let Some(expr) = lambda.expr {
+                args.push(render(nodes, number_units, expr));
+            }
+            call("lambda", &args)
+        }
+        MathNode::Root(root) => match root.children.first() {
+            Some(child) => render(nodes, number_units, *child),
+            None => (String::new(), ATOM),
+        },
+        MathNode::Op(op) => (
+            op.op.as_ref().map(op_name).unwrap_or_default().to_string(),
+            ATOM,
+        ),
+        MathNode::Text(text) => (text.clone(), ATOM),
+    }
+}
+
+fn render_op(op: &Op, operands: Vec<(String, u8)>) -> (String, u8) {
+    let infix = |symbol: &str, precedence: u8| infix(symbol, precedence, &operands);
+    match (op, operands.len()) {
+        (Op::Plus, n) if n >= 2 => infix(" + ", SUM),
+        (Op::Minus, 2) => infix(" - ", SUM),
+        (Op::Minus, 1) => prefix("-", &operands[0]),
+        (Op::Times, n) if n >= 2 => infix(" * ", PRODUCT),
+        (Op::Divide, 2) => infix(" / ", PRODUCT),
+        (Op::Power, 2) => {
+            // a^b^c is ambiguous to the reader, so nested powers are always bracketed
+            let base = wrap(&operands[0], POWER + 1);
+            let exponent = wrap(&operands[1], POWER + 1);
+            (format!("{}^{}", base, exponent), POWER)
+        }
+        (Op::Eq, 2) => infix(" == ", RELATIONAL),
+        (Op::Neq, 2) => infix(" != ", RELATIONAL),
+        (Op::Gt, 2) => infix(" > ", RELATIONAL),
+        (Op::Lt, 2) => infix(" < ", RELATIONAL),
+        (Op::Geq, 2) => infix(" >= ", RELATIONAL),
+        (Op::Leq, 2) => infix(" <= ", RELATIONAL),
+        // && and || bind equally, mixing them without brackets is hard to read
+        (Op::And, n) if n >= 2 => logical(" && ", &operands),
+        (Op::Or, n) if n >= 2 => logical(" || ", &operands),
+        (Op::Not, 1) => prefix("!", &operands[0]),
+        (Op::Root, 1) => call("sqrt", &operands),
+        (Op::Log, 1) => call("log10", &operands),
+        (Op::Log, 2) if operands[0].0 == "10" => call("log10", &operands[1..]),
+        (Op::Ceiling, _) => call("ceil", &operands),
+        // anything else, including unusual numbers of operands, is written as a call
+        _ => call(op_name(op), &operands),
+    }
+}
+
+// Left associative binary operators: the first operand only needs brackets
+// when it binds more loosely, later ones also when they bind equally
+fn infix(symbol: &str, precedence: u8, operands: &[(String, u8)]) -> (String, u8) {
+    let text = operands
+        .iter()
+        .enumerate()
+        .map(|(i, operand)| {
+            if i == 0 {
+                wrap(operand, precedence)
+            } else {
+                wrap(operand, precedence + 1)
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(symbol);
+    (text, precedence)
+}
+
+fn logical(symbol: &str, operands: &[(String, u8)]) -> (String, u8) {
+    let text = operands
+        .iter()
+        .map(|operand| wrap(operand, LOGICAL + 1))
+        .collect::<Vec<_>>()
+        .join(symbol);
+    (text, LOGICAL)
+}
+
+fn prefix(symbol: &str, operand: &(String, u8)) -> (String, u8) {
+    (format!("{}{}", symbol, wrap(operand, UNARY + 1)), UNARY)
+}
+
+fn call(name: &str, args: &[(String, u8)]) -> (String, u8) {
+    (format!("{}({})", name, join_args(args)), ATOM)
+}
+
+fn join_args(args: &[(String, u8)]) -> String {
+    args.iter()
+        .map(|(text, _)| text.as_str())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+// Brackets the operand if it binds more loosely than required
+fn wrap((text, precedence): &(String, u8), required: u8) -> String {
+    if *precedence < required {
+        format!("({})", text)
+    } else {
+        text.clone()
+    }
+}
+
+fn children(
+    nodes: &[MathNode],
+    number_units: &HashMap<NodeIndex, String>,
+    indices: &[NodeIndex],
+) -> Vec<(String, u8)> {
+    indices
+        .iter()
+        .map(|idx| render(nodes, number_units, *idx))
+        .collect()
+}
+
+fn render_name(node: &MathNode) -> String {
+    match node {
+        MathNode::Ci(ci) => ci.name.clone().unwrap_or_default(),
+        // the display name is not kept, the last part of the URL is the name
+        // the formula syntax uses for time, avogadro, delay and rateOf
+        MathNode::Csymbol(csymbol) => csymbol
+            .definition_url
+            .as_deref()
+            .and_then(|url| url.rsplit('/').next())
+            .unwrap_or_default()
+            .to_string(),
+        MathNode::Constant(constant) => match &constant.constant {
+            Some(mathml_rs::Constant::Infinity) => "INF".to_string(),
+            Some(mathml_rs::Constant::NotANumber) => "NaN".to_string(),
+            Some(constant) => constant_name(constant).to_string(),
+            None => String::new(),
+        },
+        _ => String::new(),
+    }
+}
+
+fn render_number(value: &Number) -> (String, u8) {
+    let text = match value {
+        Number::Integer(value) => value.to_string(),
+        Number::Real(value) => format_double(*value),
+        Number::ENotation(mantissa, exponent) => format!("{}e{}", mantissa, exponent),
+        Number::Rational(numerator, denominator) => {
+            return (format!("{}/{}", numerator, denominator), PRODUCT);
+        }
+        Number::ComplexCartesian(a, b) | Number::ComplexPolar(a, b) => {
+            return call(
+                "complex",
+                &[(format_double(*a), ATOM), (format_double(*b), ATOM)],
+            );
+        }
+        Number::Constant(value) => value.clone(),
+    };
+    // negative numbers bind like a unary minus
+    if text.starts_with('-') {
+        (text, UNARY)
+    } else {
+        (text, ATOM)
+    }
+}
@@ -0,0 +1,484 @@
+use std::collections::HashMap;
+
+use mathml_rs::{Constant, MathNode, NodeIndex, Number, Op};
+
+use crate::expr::{Expr, SYMBOLS_URL};
+use crate::mathml::op_from_name;
+
+mod infix;
+pub(crate) use infix::to_infix;
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Number(String),
+    Name(String),
+    Symbol(&'static str),
+}
+
+// Longest first, so that <= is not read as < followed by =
+const SYMBOLS: [&str; 18] = [
+    "==", "!=", "<=", ">=", "&&", "||", "+", "-", "*", "/", "%", "^", "(", ")", ",", "<", ">", "!",
+];
+
+// Parses an infix formula as written in the `formula` attributes of
+// SBML Level 1 into the same flat node layout as a <math> element
+pub fn parse_formula(formula: &str) -> Result<Vec<MathNode>, String> {
+    parse(formula, false).map(|(nodes, _)| nodes)
+}
+
+// Parses a formula in the Level 3 syntax of libSBML, which adds relational
+// and logical operators, constants, piecewise, lambda and numbers with units
+// (2 mole) to the Level 1 syntax. Unlike Level 1, log(x) is the base 10
+// logarithm and log(b, x) has base b. Also returns the units of the numbers.
+pub fn parse_l3_formula(
+    formula: &str,
+) -> Result<(Vec<MathNode>, HashMap<NodeIndex, String>), String> {
+    parse(formula, true)
+}
+
+fn parse(
+    formula: &str,
+    level_3: bool,
+) -> Result<(Vec<MathNode>, HashMap<NodeIndex, String>), String> {
+    let tokens = tokenize(formula)?;
+    let mut parser = Parser {
+        tokens,
+        pos: 0,
+        level_3,
+    };
+    let expr = parser.expr()?;
+    if let Some(token) = parser.peek() {
+        return Err(format!(
+            "Unexpected {} in formula '{}'",
+            describe(token),
+            formula
+        ));
+    }
+    let math_tag = expr.to_math_tag();
+    Ok((math_tag.nodes, math_tag.number_units))
+}
+
+fn tokenize(formula: &str) -> Result<Vec<Token>, String> {
+    let chars: Vec<char> = formula.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    'chars: while i < chars.len() {
+        let c = chars[i];
+        if c.is_whitespace() {
+            i += 1;
+        } else if c.is_ascii_digit() || (c == '.' && next_is_digit(&chars, i)) {
+            let start = i;
+            while i < chars.len() && (chars[i].is_ascii_digit() || chars[i] == '.') {
+                i += 1;
+            }
+            // exponent, only if digits follow
+            if i < chars.len() && (chars[i] == 'e' || chars[i] == 'E') {
+                let mut j = i + 1;
+                if j < chars.len() && (chars[j] == '+' || chars[j] == '-') {
+                    j += 1;
+                }
+                if j < chars.len() && chars[j].is_ascii_digit() {
+                    i = j;
+                    while i < chars.len() && chars[i].is_ascii_digit() {
+                        i += 1;
+                    }
+                }
+            }
+            tokens.push(Token::Number(chars[start..i].iter().collect()));
+        } else if c.is_alphabetic() || c == '_' {
+            let start = i;
+            while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') {
+                i += 1;
+            }
+            tokens.push(Token::Name(chars[start..i].iter().collect()));
+        } else {
+            for symbol in SYMBOLS.iter().copied() {
+                let len = symbol.len();
+                if chars[i..].iter().take(len).copied().eq(symbol.chars()) {
+                    tokens.push(Token::Symbol(symbol));
+                    i += len;
+                    continue 'chars;
+                }
+            }
+            return Err(format!("Unexpected character '{}' in formula", c));
+        }
+    }
+    Ok(tokens)
+}
+
+fn next_is_digit(chars: &[char], i: usize) -> bool {
+    matches!(chars.get(i + 1), Some(c) if c.is_ascii_digit())
+}
+
+fn describe(token: &Token) -> String {
+    match token {
+        Token::Number(number) => format!("number {}", number),
+        Token::Name(name) => format!("name '{}'", name),
+        Token::Symbol(symbol) => format!("'{}'", symbol),
+    }
+}
+
+// Recursive descent over the grammar
+//   expr       = relational (('&&' | '||') relational)*
+//   relational = sum (('==' | '!=' | '<' | '>' | '<=' | '>=') sum)*
+//   sum        = term (('+' | '-') term)*
+//   term       = unary (('*' | '/' | '%') unary)*
+//   unary      = ('-' | '!') unary | power
+//   power      = primary ('^' unary)?
+//   primary    = number name? | name | name '(' args ')' | '(' expr ')'
+// Level 1 formulas start at sum and have neither '!', '%' nor units.
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+    level_3: bool,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn eat(&mut self, symbol: &str) -> bool {
+        if matches!(self.peek(), Some(Token::Symbol(s)) if *s == symbol) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, symbol: &str) -> Result<(), String> {
+        if self.eat(symbol) {
+            Ok(())
+        } else {
+            Err(match self.peek() {
+                Some(token) => format!("Expected '{}' but found {}", symbol, describe(token)),
+                None => format!("Expected '{}' at the end of the formula", symbol),
+            })
+        }
+    }
+
+    fn expr(&mut self) -> Result<Expr, String> {
+        if !self.level_3 {
+            return self.sum();
+        }
+        let mut lhs = self.relational()?;
+        loop {
+            if self.eat("&&") {
+                lhs = lhs.join(Op::And, self.relational()?);
+            } else if self.eat("||") {
+                lhs = lhs.join(Op::Or, self.relational()?);
+            } else {
+                return Ok(lhs);
+            }
+        }
+    }
+
+    fn relational(&mut self) -> Result<Expr, String> {
+        let mut lhs = self.sum()?;
+        loop {
+            let op = if self.eat("==") {
+                Op::Eq
+            } else if self.eat("!=") {
+                Op::Neq
+            } else if self.eat("<=") {
+                Op::Leq
+            } else if self.eat(">=") {
+                Op::Geq
+            } else if self.eat("<") {
+                Op::Lt
+            } else if self.eat(">") {
+                Op::Gt
+            } else {
+                return Ok(lhs);
+            };
+            lhs = Expr::Apply(op, vec![lhs, self.sum()?]);
+        }
+    }
+
+    fn sum(&mut self) -> Result<Expr, String> {
+        let mut lhs = self.term()?;
+        loop {
+            if self.eat("+") {
+                lhs = lhs.plus(self.term()?);
+            } else if self.eat("-") {
+                lhs = lhs.minus(self.term()?);
+            } else {
+                return Ok(lhs);
+            }
+        }
+    }
+
+    fn term(&mut self) -> Result<Expr, String> {
+        let mut lhs = self.unary()?;
+        loop {
+            if self.eat("*") {
+                lhs = lhs.times(self.unary()?);
+            } else if self.eat("/") {
+                lhs = lhs.div(self.unary()?);
+            } else if self.level_3 && self.eat("%") {
+                lhs = modulo(lhs, self.unary()?);
+            } else {
+                return Ok(lhs);
+            }
+        }
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        if self.eat("-") {
+            Ok(Expr::Apply(Op::Minus, vec![self.unary()?]))
+        } else if self.eat("+") {
+            self.unary()
+        } else if self.level_3 && self.eat("!") {
+            Ok(Expr::Apply(Op::Not, vec![self.unary()?]))
+        } else {
+            self.power()
+        }
+    }
+
+    fn power(&mut self) -> Result<Expr, String> {
+        let base = self.primary()?;
+        if self.eat("^") {
+            Ok(base.pow(self.unary()?))
+        } else {
+            Ok(base)
+        }
+    }
+
+    fn primary(&mut self) -> Result<Expr, String> {
+        match self.next() {
+            Some(Token::Number(number)) => {
+                let value = parse_number(&number)?;
+                let units = match self.peek() {
+                    Some(Token::Name(units)) if self.level_3 => Some(units.clone()),
+                    _ => None,
+                };
+                if units.is_some() {
+                    self.pos += 1;
+                }
+                Ok(Expr::Number(value, units))
+            }
+            Some(Token::Name(name)) => {
+                if self.eat("(") {
+                    let args = self.args()?;
+                    if self.level_3 {
+                        call_l3(name, args)
+                    } else {
+                        call(name, args)
+                    }
+                } else if self.level_3 {
+                    Ok(name_l3(name))
+                } else {
+                    Ok(Expr::Ci(name))
+                }
+            }
+            Some(Token::Symbol("(")) => {
+                let expr = self.expr()?;
+                self.expect(")")?;
+                Ok(expr)
+            }
+            Some(token) => Err(format!("Unexpected {} in formula", describe(&token))),
+            None => Err("Unexpected end of formula".to_string()),
+        }
+    }
+
+    fn args(&mut self) -> Result<Vec<Expr>, String> {
+        let mut args = Vec::new();
+        if self.eat(")") {
+            return Ok(args);
+        }
+        loop {
+            args.push(self.expr()?);
+            if self.eat(")") {
+                return Ok(args);
+            }
+            self.expect(",")?;
+        }
+    }
+}
+
+fn parse_number(number: &str) -> Result<Number, String> {
+    let invalid = || format!("Invalid number '{}' in formula", number);
+    let is_real = number.contains(&['.', 'e', 'E'][..]);
+    let value = match number.parse::<i32>() {
+        Ok(value) if !is_real => Number::Integer(value),
+        _ => Number::Real(number.parse::<f64>().map_err(|_| invalid())?),
+    };
+    Ok(value)
+}
+
+fn arity(name: &str, n: usize, args: &[Expr]) -> Result<(), String> {
+    if args.len() == n {
+        Ok(())
+    } else {
+        Err(format!(
+            "Function '{}' takes {} argument(s) but {} were given",
+            name,
+            n,
+            args.len()
+        ))
+    }
+}
+
+// Maps the functions of Level 1 formulas onto MathML operators.
+// Any other name is taken to be a function definition.
+fn call(name: String, mut args: Vec<Expr>) -> Result<Expr, String> {
+    let op = match name.as_str() {
+        "abs" => Op::Abs,
+        "acos" => Op::Arccos,
+        "asin" => Op::Arcsin,
+        "atan" => Op::Arctan,
+        "ceil" => Op::Ceiling,
+        "cos" => Op::Cos,
+        "exp" => Op::Exp,
+        "floor" => Op::Floor,
+        // natural logarithm in Level 1
+        "log" => Op::Ln,
+        "log10" => {
+            arity(&name, 1, &args)?;
+            args.insert(0, Expr::Number(Number::Integer(10), None));
+            Op::Log
+        }
+        "pow" => {
+            arity(&name, 2, &args)?;
+            Op::Power
+        }
+        "sqr" => {
+            arity(&name, 1, &args)?;
+            args.push(Expr::Number(Number::Integer(2), None));
+            Op::Power
+        }
+        "sqrt" => Op::Root,
+        "sin" => Op::Sin,
+        "tan" => Op::Tan,
+        _ => return Ok(Expr::Call(name, args)),
+    };
+    if matches!(op, Op::Log | Op::Power) {
+        return Ok(Expr::Apply(op, args));
+    }
+    arity(&name, 1, &args)?;
+    Ok(Expr::Apply(op, args))
+}
+
+// Names that stand for a constant or symbol in Level 3, regardless of case
+fn name_l3(name: String) -> Expr {
+    let constant = match name.to_lowercase().as_str() {
+        "true" => Constant::True,
+        "false" => Constant::False,
+        "pi" => Constant::Pi,
+        "exponentiale" => Constant::ExponentialE,
+        "inf" | "infinity" => Constant::Infinity,
+        "nan" | "notanumber" => Constant::NotANumber,
+        "time" => return Expr::symbol("time"),
+        "avogadro" => return Expr::symbol("avogadro"),
+        _ => return Expr::Ci(name),
+    };
+    Expr::Constant(constant)
+}
+
+// Maps the functions of Level 3 formulas onto MathML operators, accepting
+// both the short names (acos, ceil) and the MathML ones (arccos, ceiling)
+fn call_l3(name: String, mut args: Vec<Expr>) -> Result<Expr, String> {
+    let op = match name.to_lowercase().as_str() {
+        "piecewise" => return Ok(piecewise(args)),
+        "lambda" => return lambda(args),
+        "delay" => {
+            arity(&name, 2, &args)?;
+            return Ok(Expr::CsymbolCall(format!("{}delay", SYMBOLS_URL), args));
+        }
+        "rateof" => {
+            arity(&name, 1, &args)?;
+            return Ok(Expr::CsymbolCall(format!("{}rateOf", SYMBOLS_URL), args));
+        }
+        "acos" => Op::Arccos,
+        "asin" => Op::Arcsin,
+        "atan" => Op::Arctan,
+        "acosh" => Op::Arccosh,
+        "asinh" => Op::Arcsinh,
+        "atanh" => Op::Arctanh,
+        "ceil" => Op::Ceiling,
+        "pow" => Op::Power,
+        "sqr" => {
+            arity(&name, 1, &args)?;
+            args.push(Expr::Number(Number::Integer(2), None));
+            Op::Power
+        }
+        "sqrt" => Op::Root,
+        "log10" => {
+            arity(&name, 1, &args)?;
+            args.insert(0, Expr::Number(Number::Integer(10), None));
+            Op::Log
+        }
+        "log" if args.len() == 1 => {
+            args.insert(0, Expr::Number(Number::Integer(10), None));
+            Op::Log
+        }
+        lower => match op_from_name(lower.as_bytes()) {
+            Some(op) => op,
+            None => return Ok(Expr::Call(name, args)),
+        },
+    };
+    match op {
+        // n-ary operators
+        Op::Plus | Op::Times | Op::And | Op::Or | Op::Xor => {}
+        Op::Eq | Op::Gt | Op::Lt | Op::Geq | Op::Leq => {}
+        Op::Minus | Op::Root if args.len() == 1 => {}
+        Op::Minus | Op::Divide | Op::Power | Op::Neq | Op::Log | Op::Root => {
+            arity(&name, 2, &args)?
+        }
+        _ => arity(&name, 1, &args)?,
+    }
+    Ok(Expr::Apply(op, args))
+}
+
+// piecewise(value, condition, ..., otherwise)
+fn piecewise(mut args: Vec<Expr>) -> Expr {
+    let otherwise = if args.len() % 2 == 1 {
+        args.pop().map(Box::new)
+    } else {
+        None
+    };
+    let mut pieces = Vec::new();
+    let mut args = args.into_iter();
+    while let (Some(value), Some(condition)) = (args.next(), args.next()) {
+        pieces.push((value, condition));
+    }
+    Expr::Piecewise(pieces, otherwise)
+}
+
+// a % b, written out with piecewise the way libSBML does, so that the
+// result has the sign of a
+fn modulo(a: Expr, b: Expr) -> Expr {
+    let quotient = a.clone().div(b.clone());
+    let negative = |x: &Expr| Expr::Apply(Op::Lt, vec![x.clone(), Expr::integer(0)]);
+    let signs_differ = Expr::Apply(Op::Xor, vec![negative(&a), negative(&b)]);
+    let towards_zero = Expr::Apply(Op::Ceiling, vec![quotient.clone()]);
+    let otherwise = a
+        .clone()
+        .minus(b.clone().times(Expr::Apply(Op::Floor, vec![quotient])));
+    Expr::Piecewise(
+        vec![(a.minus(b.times(towards_zero)), signs_differ)],
+        Some(Box::new(otherwise)),
+    )
+}
+
+// lambda(x, y, body)
+fn lambda(mut args: Vec<Expr>) -> Result<Expr, String> {
+    let body = args
+        .pop()
+        .ok_or_else(|| "Function 'lambda' needs a body".to_string())?;
+    let mut bindings = Vec::new();
+    for arg in args {
+        match arg {
+            Expr::Ci(name) => bindings.push(name),
+            _ => return Err("The arguments of 'lambda' must be names".to_string()),
+        }
+    }
+    Ok(Expr::Lambda(bindings, Box::new(body)))
+}
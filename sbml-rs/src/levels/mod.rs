@@ -0,0 +1,244 @@
+use std::io::BufRead;
+
+use quick_xml::events::BytesStart;
+use quick_xml::Reader;
+
+use crate::{AssignmentRule, ListOfRules, Model, Tag, TagIndex};
+
+// Level 1 spells some elements and attributes differently and writes math as
+// infix `formula` attributes. Translates a Level 1 start tag into its Level 2
+// equivalent and takes out the formula, which has no attribute counterpart.
+pub(crate) fn translate_level_1<R: BufRead>(
+    e: &BytesStart,
+    reader: &Reader<R>,
+) -> Result<(BytesStart<'static>, Option<String>), quick_xml::Error> {
+    let mut attributes = Vec::new();
+    for attribute in e.attributes() {
+        let attribute = attribute?;
+        let key = String::from_utf8_lossy(attribute.key).to_string();
+        let value = attribute.unescape_and_decode_value(reader)?;
+        attributes.push((key, value));
+    }
+    let take = |attributes: &mut Vec<(String, String)>, key: &str| {
+        let pos = attributes.iter().position(|(k, _)| k == key)?;
+        Some(attributes.remove(pos).1)
+    };
+    let rename = |attributes: &mut Vec<(String, String)>, from: &str, to: &str| {
+        for (key, _) in attributes.iter_mut() {
+            if key == from {
+                *key = to.to_string();
+            }
+        }
+    };
+
+    let mut formula = None;
+    let mut name = String::from_utf8_lossy(e.name()).to_string();
+    match name.as_str() {
+        "specie" | "species" => {
+            name = "species".to_string();
+            rename(&mut attributes, "units", "substanceUnits");
+        }
+        "specieReference" | "speciesReference" => {
+            name = "speciesReference".to_string();
+            rename(&mut attributes, "specie", "species");
+            // the stoichiometry is a fraction of integers
+            if let Some(denominator) = take(&mut attributes, "denominator") {
+                let stoichiometry = take(&mut attributes, "stoichiometry");
+                let numerator = stoichiometry.as_deref().unwrap_or("1").parse::<f64>();
+                match (numerator, denominator.parse::<f64>()) {
+                    (Ok(numerator), Ok(denominator)) => {
+                        let value = (numerator / denominator).to_string();
+                        attributes.push(("stoichiometry".to_string(), value));
+                    }
+                    // left for the parser to report
+                    _ => {
+                        attributes.push(("denominator".to_string(), denominator));
+                        if let Some(stoichiometry) = stoichiometry {
+                            attributes.push(("stoichiometry".to_string(), stoichiometry));
+                        }
+                    }
+                }
+            }
+        }
+        "compartment" => rename(&mut attributes, "volume", "size"),
+        "kineticLaw" | "algebraicRule" => formula = take(&mut attributes, "formula"),
+        "specieConcentrationRule"
+        | "speciesConcentrationRule"
+        | "compartmentVolumeRule"
+        | "parameterRule" => {
+            formula = take(&mut attributes, "formula");
+            let variable = match name.as_str() {
+                "compartmentVolumeRule" => take(&mut attributes, "compartment"),
+                "parameterRule" => take(&mut attributes, "name"),
+                _ => take(&mut attributes, "specie").or_else(|| take(&mut attributes, "species")),
+            };
+            if let Some(variable) = variable {
+                attributes.push(("variable".to_string(), variable));
+            }
+            // Level 2 rules have no units, they follow from the variable
+            take(&mut attributes, "units");
+            name = match take(&mut attributes, "type").as_deref() {
+                Some("rate") => "rateRule".to_string(),
+                _ => "assignmentRule".to_string(),
+            };
+        }
+        _ => {}
+    }
+    // names are the identifiers in Level 1
+    if let "model" | "compartment" | "species" | "parameter" | "reaction" | "unitDefinition" =
+        name.as_str()
+    {
+        let id = attributes
+            .iter()
+            .find(|(k, _)| k == "name")
+            .map(|(_, v)| v.clone());
+        if let (Some(id), None) = (id, attributes.iter().find(|(k, _)| k == "id")) {
+            attributes.push(("id".to_string(), id));
+        }
+    }
+
+    let mut translated = BytesStart::owned_name(name);
+    for (key, value) in &attributes {
+        translated.push_attribute((key.as_str(), value.as_str()));
+    }
+    Ok((translated, formula))
+}
+
+// A copy of the start tag without the attribute, for attributes that are
+// allowed but have nothing to be stored in
+pub(crate) fn without_attribute(
+    e: &BytesStart,
+    key: &[u8],
+) -> Result<BytesStart<'static>, quick_xml::Error> {
+    let mut copy = BytesStart::owned_name(e.name().to_vec());
+    for attribute in e.attributes() {
+        let attribute = attribute?;
+        if attribute.key != key {
+            copy.push_attribute(attribute);
+        }
+    }
+    Ok(copy)
+}
+
+// Level 3 made most attributes required, earlier levels give them default
+// values instead. Fills in those defaults for attributes the document left
+// out, so the rest of the crate can treat every model the same.
+pub(crate) fn apply_defaults(model: &mut Model, level: u32) {
+    if level >= 3 {
+        return;
+    }
+    for tag in model.nodes.iter_mut() {
+        match tag {
+            Tag::Compartment(compartment) => {
+                if level == 1 {
+                    compartment.size.get_or_insert(1.0);
+                }
+                compartment.spatial_dimensions.get_or_insert(3.0);
+                compartment.constant.get_or_insert(true);
+            }
+            Tag::Species(species) => {
+                species.has_only_substance_units.get_or_insert(false);
+                species.boundary_condition.get_or_insert(false);
+                species.constant.get_or_insert(false);
+            }
+            Tag::Parameter(parameter) => {
+                parameter.constant.get_or_insert(true);
+            }
+            Tag::Reaction(reaction) => {
+                reaction.reversible.get_or_insert(true);
+                reaction.fast.get_or_insert(false);
+            }
+            Tag::SpeciesReference(species_reference) => {
+                // a stoichiometryMath replaces the fixed stoichiometry
+                let constant = species_reference.stoichiometry_math.is_none();
+                if constant {
+                    species_reference.stoichiometry.get_or_insert(1.0);
+                }
+                species_reference.constant.get_or_insert(constant);
+            }
+            Tag::Event(event) => {
+                event.use_values_from_trigger_time.get_or_insert(true);
+            }
+            Tag::Trigger(trigger) => {
+                trigger.initial_value.get_or_insert(true);
+                trigger.persistent.get_or_insert(true);
+            }
+            _ => {}
+        }
+    }
+}
+
+// Level 3 has no stoichiometryMath. An assignment rule to the id of the
+// species reference, which is given one if it has none, takes its place.
+pub(crate) fn stoichiometry_math_to_rules(model: &Model) -> Model {
+    let mut model = model.clone();
+    let references: Vec<TagIndex> = model
+        .nodes
+        .iter()
+        .enumerate()
+        .filter_map(|(idx, tag)| match tag {
+            Tag::SpeciesReference(t) if t.stoichiometry_math.is_some() => Some(idx),
+            _ => None,
+        })
+        .collect();
+    for idx in references {
+        let (id, species, stoichiometry_math) = match &model.nodes[idx] {
+            Tag::SpeciesReference(t) => (t.id.clone(), t.species.clone(), t.stoichiometry_math),
+            _ => continue,
+        };
+        let math = match stoichiometry_math.map(|idx| &model.nodes[idx]) {
+            Some(Tag::StoichiometryMath(t)) => model.math_tag_at(t.math).cloned(),
+            _ => None,
+        };
+        let id = id.unwrap_or_else(|| {
+            let base = format!("{}_stoichiometry", species.as_deref().unwrap_or("species"));
+            let mut id = base.clone();
+            let mut n = 1;
+            while model.get_by_sid(&id).is_some() {
+                n += 1;
+                id = format!("{}_{}", base, n);
+            }
+            id
+        });
+        if let Tag::SpeciesReference(t) = &mut model.nodes_mut()[idx] {
+            t.id = Some(id.clone());
+            t.stoichiometry = None;
+            t.stoichiometry_math = None;
+            t.constant = Some(false);
+        }
+
+        let mut list_idx = None;
+        if let Tag::Root(root) = &model.nodes[0] {
+            list_idx = root.list_of_rules;
+        }
+        let list_idx = match list_idx {
+            Some(list_idx) => list_idx,
+            None => {
+                let list_idx = model.add_node(Tag::ListOfRules(ListOfRules {
+                    parent: Some(0),
+                    ..Default::default()
+                }));
+                if let Tag::Root(root) = &mut model.nodes[0] {
+                    root.list_of_rules = Some(list_idx);
+                }
+                list_idx
+            }
+        };
+        let rule_idx = model.add_node(Tag::AssignmentRule(AssignmentRule {
+            variable: Some(id),
+            parent: Some(list_idx),
+            ..Default::default()
+        }));
+        let math_idx = math.map(|mut math| {
+            math.parent = Some(rule_idx);
+            model.add_node(Tag::MathTag(math))
+        });
+        if let Tag::AssignmentRule(rule) = &mut model.nodes[rule_idx] {
+            rule.math = math_idx;
+        }
+        if let Tag::ListOfRules(list) = &mut model.nodes[list_idx] {
+            list.assignment_rules.push(rule_idx);
+        }
+    }
+    model
+}
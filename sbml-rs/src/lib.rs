@@ -1,12 +1,27 @@
 use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
 use std::str;
 
-use quick_xml::events::Event;
-use quick_xml::Reader;
-use sbml_macros::{attach, attach_math, close};
+use quick_xml::events::Event as XmlEvent;
+use quick_xml::{Reader, Writer};
+use sbml_macros::{attach, attach_math, attach_xml, close};
 
+pub mod builder;
+pub use builder::{EventBuilder, ModelBuilder, ReactionBuilder};
+pub mod errors;
+pub use errors::*;
+pub mod expr;
+pub use expr::Expr;
+pub mod formula;
+mod levels;
+pub mod mathml;
+pub mod miriam;
 pub mod structs;
 pub use structs::compartments::*;
+pub use structs::constraints::*;
+pub use structs::document::*;
+pub use structs::events::*;
 pub use structs::function_definitions::*;
 pub use structs::initial_assignments::*;
 pub use structs::math::*;
@@ -19,21 +34,88 @@ pub use structs::species::*;
 pub use structs::tag::*;
 pub use structs::units::*;
 pub mod transformations;
+pub mod units;
+pub use units::{BaseUnit, DerivedUnit, UnitInference};
+mod validation;
+pub mod writer;
 pub use transformations::*;
 
+// How the parser deals with constructs it does not support
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Strictness {
+    // fail on the first problem
+    #[default]
+    Strict,
+    // skip unknown elements and attributes, recording a warning for each
+    Lenient,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct ParseOptions {
+    pub strictness: Strictness,
+}
+
+pub fn parse(filename: &str) -> Result<Model, SbmlError> {
+    Ok(parse_document(filename)?.model)
+}
+
+pub fn parse_document(filename: &str) -> Result<SbmlDocument, SbmlError> {
+    parse_with_options(filename, &ParseOptions::default())
+}
+
+pub fn parse_with_options(
+    filename: &str,
+    options: &ParseOptions,
+) -> Result<SbmlDocument, SbmlError> {
+    let file = File::open(filename).map_err(|e| SbmlError::Io {
+        filename: filename.to_string(),
+        message: e.to_string(),
+    })?;
+    parse_reader_with_options(BufReader::new(file), options)
+}
+
+pub fn parse_str(sbml: &str) -> Result<Model, SbmlError> {
+    parse_bytes(sbml.as_bytes())
+}
+
+pub fn parse_document_str(sbml: &str) -> Result<SbmlDocument, SbmlError> {
+    parse_reader_with_options(sbml.as_bytes(), &ParseOptions::default())
+}
+
+pub fn parse_bytes(sbml: &[u8]) -> Result<Model, SbmlError> {
+    parse_reader(sbml)
+}
+
+// Parses a model from any buffered source, e.g. stdin, an archive entry
+// or a network buffer.
+pub fn parse_reader<R: BufRead>(source: R) -> Result<Model, SbmlError> {
+    Ok(parse_reader_with_options(source, &ParseOptions::default())?.model)
+}
+
+// All other parse functions end up here. The document holds warnings
+// for everything that was skipped in lenient mode.
 #[allow(unused_variables, unused_assignments, dead_code)]
-pub fn parse(filename: &str) -> Result<Model, Vec<String>> {
-    // read file
-    //let file = File::open().unwrap();
-    let mut reader = Reader::from_file(filename).expect("File error.");
+pub fn parse_reader_with_options<R: BufRead>(
+    source: R,
+    options: &ParseOptions,
+) -> Result<SbmlDocument, SbmlError> {
+    let (source, line_index) = LineTracker::new(source);
+    let mut reader = Reader::from_reader(source);
     reader.trim_text(true);
     reader.expand_empty_elements(true);
     let mut buf = Vec::new();
+    let mut skip_buf = Vec::new();
+    let mut diagnostics = Diagnostics::new(options.strictness);
 
     let mut stack: Vec<TagIndex> = Vec::new();
+    // names of the currently open elements, used to report locations
+    let mut path: Vec<String> = Vec::new();
     let mut nodes = Vec::new();
     let mut nodes_len = 0;
     let mut model_attrs = HashMap::new();
+    let mut model_notes = None;
+    let mut model_annotation = None;
+    let mut document = SbmlDocument::default();
 
     let root = Root::default();
     nodes.push(Tag::Root(root));
@@ -44,32 +126,127 @@ pub fn parse(filename: &str) -> Result<Model, Vec<String>> {
     loop {
         match reader.read_event(&mut buf) {
             // for each starting tag
-            Ok(Event::Start(ref e)) => {
+            Ok(XmlEvent::Start(ref start)) => {
                 let mut new_tag = None;
+                // set when the element cannot be used and its content has to be skipped
+                let mut skip_subtree = false;
+                let element = str::from_utf8(start.name())
+                    .map_err(|err| SbmlError::xml_syntax(err, &line_index.locate_tag(&path)))?
+                    .to_string();
+                path.push(element.clone());
+                let location = line_index.locate_tag(&path);
+                // Level 1 elements are read as their Level 2 equivalents
+                let (level_1, formula) = if document.level == Some(1) {
+                    let (translated, formula) = levels::translate_level_1(start, &reader)
+                        .map_err(|err| SbmlError::xml_syntax(err, &location))?;
+                    (Some(translated), formula)
+                } else {
+                    (None, None)
+                };
+                let e = level_1.as_ref().unwrap_or(start);
                 match e.name() {
-                    b"sbml" => {}
+                    b"sbml" => {
+                        // package prefixes and their required flags
+                        let mut required = HashMap::new();
+                        for attribute in e.attributes() {
+                            let attribute =
+                                attribute.map_err(|err| SbmlError::xml_syntax(err, &location))?;
+                            let key = str::from_utf8(attribute.key)
+                                .map_err(|err| SbmlError::xml_syntax(err, &location))?;
+                            let value = attribute
+                                .unescape_and_decode_value(&reader)
+                                .map_err(|err| SbmlError::xml_syntax(err, &location))?;
+                            let invalid_value = || SbmlError::InvalidAttributeValue {
+                                attribute: key.to_string(),
+                                value: value.clone(),
+                                expected: "u32",
+                                location: location.clone(),
+                            };
+                            match key {
+                                "level" => match value.parse::<u32>() {
+                                    Ok(level) => document.level = Some(level),
+                                    Err(_) => diagnostics.report(invalid_value())?,
+                                },
+                                "version" => match value.parse::<u32>() {
+                                    Ok(version) => document.version = Some(version),
+                                    Err(_) => diagnostics.report(invalid_value())?,
+                                },
+                                "xmlns" => {
+                                    document.namespaces.insert(String::new(), value);
+                                }
+                                _ if key.starts_with("xmlns:") => {
+                                    document
+                                        .namespaces
+                                        .insert(key["xmlns:".len()..].to_string(), value);
+                                }
+                                _ if key.ends_with(":required") => {
+                                    let prefix = &key[..key.len() - ":required".len()];
+                                    match value.parse::<bool>() {
+                                        Ok(flag) => {
+                                            required.insert(prefix.to_string(), flag);
+                                        }
+                                        Err(_) => diagnostics.report(
+                                            SbmlError::InvalidAttributeValue {
+                                                attribute: key.to_string(),
+                                                value: value.clone(),
+                                                expected: "bool",
+                                                location: location.clone(),
+                                            },
+                                        )?,
+                                    }
+                                }
+                                // attributes of other namespaces, e.g. xsi:schemaLocation
+                                _ if key.contains(':') => {}
+                                _ => diagnostics.report(SbmlError::UnknownAttribute {
+                                    attribute: key.to_string(),
+                                    location: location.clone(),
+                                })?,
+                            }
+                        }
+                        // every L3 package namespace comes with a required flag
+                        for (prefix, required) in required {
+                            if let Some(uri) = document.namespaces.get(&prefix) {
+                                document.packages.push(Package {
+                                    prefix,
+                                    uri: uri.clone(),
+                                    required,
+                                });
+                            }
+                        }
+                        document.packages.sort_by(|a, b| a.prefix.cmp(&b.prefix));
+                    }
                     b"model" => {
-                        let attributes = e.attributes().map(|a| a.unwrap()).collect::<Vec<_>>();
-                        for attribute in attributes {
-                            let key = str::from_utf8(attribute.key).unwrap();
-                            let value = attribute.unescape_and_decode_value(&reader).unwrap();
+                        for attribute in e.attributes() {
+                            let attribute =
+                                attribute.map_err(|err| SbmlError::xml_syntax(err, &location))?;
+                            let key = str::from_utf8(attribute.key)
+                                .map_err(|err| SbmlError::xml_syntax(err, &location))?;
+                            let value = attribute
+                                .unescape_and_decode_value(&reader)
+                                .map_err(|err| SbmlError::xml_syntax(err, &location))?;
                             match key {
                                 "id" | "substanceUnits" | "timeUnits" | "extentUnits"
                                 | "volumeUnits" | "areaUnits" | "lengthUnits"
-                                | "conversionFactor" | "metaid" | "name" => {
+                                | "conversionFactor" | "metaid" | "name" | "sboTerm" => {
                                     model_attrs.insert(key.to_string(), value);
                                 }
-                                _ => panic!("Attribute {} not parsed for model.", key),
+                                _ => diagnostics.report(SbmlError::UnknownAttribute {
+                                    attribute: key.to_string(),
+                                    location: location.clone(),
+                                })?,
                             }
                         }
                     }
                     b"listOfUnitDefinitions" => attach!(ListOfUnitDefinitions to Root),
                     b"unitDefinition" => attach!(UnitDefinition with
-                                                id as String
+                                                id as String,
+                                                metaid as String,
+                                                name as String
                                             to ListOfUnitDefinitions),
                     b"listOfUnits" => attach!(ListOfUnits to UnitDefinition),
-                    b"unit" => attach!(Unit with 
+                    b"unit" => attach!(Unit with
                                         kind as String,
+                                        metaid as String,
                                         exponent as f64,
                                         scale as i64,
                                         multiplier as f64
@@ -78,14 +255,49 @@ pub fn parse(filename: &str) -> Result<Model, Vec<String>> {
                     b"compartment" => attach!(Compartment with
                                                 name as String,
                                                 id as String,
+                                                metaid as String,
                                                 units as String,
                                                 constant as bool,
                                                 spatial_dimensions as f64,
                                                 sbo_term as String,
-                                                size as f64
+                                                size as f64,
+                                                compartment_type as String,
+                                                outside as String
                                             to ListOfCompartments),
-                    b"listOfParameters" => attach!(ListOfParameters to Root),
-                    b"parameter" => attach!(Parameter with
+                    b"listOfCompartmentTypes" => attach!(ListOfCompartmentTypes to Root),
+                    b"compartmentType" => attach!(CompartmentType with
+                                                id as String,
+                                                metaid as String,
+                                                name as String,
+                                                sbo_term as String
+                                            to ListOfCompartmentTypes),
+                    b"listOfSpeciesTypes" => attach!(ListOfSpeciesTypes to Root),
+                    b"speciesType" => attach!(SpeciesType with
+                                                id as String,
+                                                metaid as String,
+                                                name as String,
+                                                sbo_term as String
+                                            to ListOfSpeciesTypes),
+                    // Level 2 kinetic laws keep their local parameters in a listOfParameters
+                    b"listOfParameters" => match nodes[current] {
+                        Tag::KineticLaw(_) => attach!(ListOfLocalParameters to KineticLaw),
+                        _ => attach!(ListOfParameters to Root),
+                    },
+                    b"parameter" => match nodes[current] {
+                        Tag::ListOfLocalParameters(_) => {
+                            // local parameters are always constant, Level 2 may say so anyway
+                            let e = &levels::without_attribute(e, b"constant")
+                                .map_err(|err| SbmlError::xml_syntax(err, &location))?;
+                            attach!(LocalParameter with
+                                            id as String,
+                                            metaid as String,
+                                            name as String,
+                                            value as f64,
+                                            units as String,
+                                            sbo_term as String
+                                        to ListOfLocalParameters)
+                        }
+                        _ => attach!(Parameter with
                                             id as String,
                                             metaid as String,
                                             name as String,
@@ -94,6 +306,7 @@ pub fn parse(filename: &str) -> Result<Model, Vec<String>> {
                                             sbo_term as String,
                                             constant as bool
                                         to ListOfParameters),
+                    },
                     b"listOfSpecies" => attach!(ListOfSpecies to Root),
                     b"species" => attach!(Species with
                                             id as String,
@@ -107,12 +320,17 @@ pub fn parse(filename: &str) -> Result<Model, Vec<String>> {
                                             has_only_substance_units as bool,
                                             boundary_condition as bool,
                                             constant as bool,
-                                            //conversion_factor as String,
+                                            conversion_factor as String,
+                                            species_type as String,
+                                            charge as i32,
+                                            spatial_size_units as String,
                                     to ListOfSpecies),
                     b"listOfReactions" => attach!(ListOfReactions to Root),
                     b"reaction" => attach!(Reaction with
                                              id as String,
+                                             metaid as String,
                                              reversible as bool,
+                                             fast as bool,
                                              compartment as String,
                                              name as String,
                                              sbo_term as String
@@ -121,44 +339,75 @@ pub fn parse(filename: &str) -> Result<Model, Vec<String>> {
                     b"listOfProducts" => attach!(ListOfProducts to Reaction),
                     b"speciesReference" => attach!(SpeciesReference with
                                                     id as String,
+                                                    metaid as String,
                                                     name as String,
                                                     species as String,
                                                     constant as bool,
                                                     sbo_term as String,
                                                     stoichiometry as f64,
                                         to ListOfReactants | ListOfProducts),
+                    b"stoichiometryMath" => attach!(StoichiometryMath with
+                                                    metaid as String,
+                                                    sbo_term as String
+                                        to SpeciesReference),
                     b"listOfModifiers" => attach!(ListOfModifiers to Reaction),
                     b"modifierSpeciesReference" => attach!(ModifierSpeciesReference with
                                                     id as String,
+                                                    metaid as String,
                                                     name as String,
                                                     species as String,
                                                     sbo_term as String,
                                         to ListOfModifiers),
                     b"kineticLaw" => attach!(KineticLaw with
+                                                    metaid as String,
                                                     sbo_term as String,
+                                                    time_units as String,
+                                                    substance_units as String,
                                         to Reaction),
                     b"listOfLocalParameters" => attach!(ListOfLocalParameters to KineticLaw),
                     b"localParameter" => attach!(LocalParameter with
                                             id as String,
+                                            metaid as String,
+                                            name as String,
                                             value as f64,
                                             units as String,
                                             sbo_term as String,
                                         to ListOfLocalParameters),
-                    b"math" => {
-                        let (math_nodes, returned_reader) = mathml_rs::parse_fragment(reader);
-                        reader = returned_reader;
-                        attach_math![
-                            KineticLaw,
-                            FunctionDefinition,
-                            InitialAssignment,
-                            AssignmentRule,
-                            RateRule,
-                        ];
-                    }
+                    b"math" => match mathml::parse_math(&mut reader) {
+                        Ok((math_nodes, number_units)) => {
+                            // the fragment parser consumed the closing tag
+                            path.pop();
+                            attach_math![
+                                KineticLaw,
+                                FunctionDefinition,
+                                InitialAssignment,
+                                AssignmentRule,
+                                RateRule,
+                                AlgebraicRule,
+                                Trigger,
+                                Delay,
+                                Priority,
+                                EventAssignment,
+                                Constraint,
+                                StoichiometryMath,
+                            ];
+                            if let Some(Tag::MathTag(math_tag)) = &mut new_tag {
+                                math_tag.number_units = number_units;
+                            }
+                        }
+                        Err(message) => {
+                            diagnostics.report(SbmlError::InvalidMath {
+                                message,
+                                location: location.clone(),
+                            })?;
+                            skip_subtree = true;
+                        }
+                    },
                     b"listOfFunctionDefinitions" => attach!(ListOfFunctionDefinitions to Root),
                     b"functionDefinition" => {
                         attach!(FunctionDefinition with
                                     id as String,
+                                    metaid as String,
                                     name as String,
                                     sbo_term as String
                                 to ListOfFunctionDefinitions)
@@ -167,6 +416,7 @@ pub fn parse(filename: &str) -> Result<Model, Vec<String>> {
                     b"initialAssignment" => {
                         attach!(InitialAssignment with
                                     id as String,
+                                    metaid as String,
                                     symbol as String,
                                     sbo_term as String
                                 to ListOfInitialAssignments)
@@ -188,68 +438,326 @@ pub fn parse(filename: &str) -> Result<Model, Vec<String>> {
                                     sbo_term as String
                                 to ListOfRules)
                     }
+                    b"algebraicRule" => {
+                        attach!(AlgebraicRule with
+                                    id as String,
+                                    metaid as String,
+                                    sbo_term as String
+                                to ListOfRules)
+                    }
+                    b"listOfEvents" => attach!(ListOfEvents to Root),
+                    b"event" => {
+                        attach!(Event with
+                                    id as String,
+                                    metaid as String,
+                                    name as String,
+                                    sbo_term as String,
+                                    use_values_from_trigger_time as bool
+                                to ListOfEvents)
+                    }
+                    b"trigger" => {
+                        attach!(Trigger with
+                                    metaid as String,
+                                    initial_value as bool,
+                                    persistent as bool,
+                                    sbo_term as String
+                                to Event)
+                    }
+                    b"delay" => attach!(Delay with metaid as String, sbo_term as String to Event),
+                    b"priority" => {
+                        attach!(Priority with metaid as String, sbo_term as String to Event)
+                    }
+                    b"listOfEventAssignments" => attach!(ListOfEventAssignments to Event),
+                    b"eventAssignment" => {
+                        attach!(EventAssignment with
+                                    variable as String,
+                                    metaid as String,
+                                    sbo_term as String
+                                to ListOfEventAssignments)
+                    }
+                    b"notes" | b"annotation" => {
+                        let content = read_raw_content(&mut reader, e.name())
+                            .map_err(|err| SbmlError::xml_syntax(err, &location))?;
+                        // the closing tag has been consumed
+                        path.pop();
+                        // <sbml> and <model> share the root tag
+                        if current == 0 {
+                            let (notes, annotation) = if path.len() == 1 {
+                                (&mut document.notes, &mut document.annotation)
+                            } else {
+                                (&mut model_notes, &mut model_annotation)
+                            };
+                            if e.name() == b"notes" {
+                                *notes = Some(content);
+                            } else {
+                                *annotation = Some(content);
+                            }
+                        } else {
+                            attach_xml![
+                                ListOfUnitDefinitions,
+                                UnitDefinition,
+                                ListOfUnits,
+                                Unit,
+                                ListOfCompartments,
+                                Compartment,
+                                ListOfParameters,
+                                Parameter,
+                                ListOfSpecies,
+                                Species,
+                                ListOfReactions,
+                                Reaction,
+                                ListOfReactants,
+                                ListOfProducts,
+                                SpeciesReference,
+                                ListOfModifiers,
+                                ModifierSpeciesReference,
+                                ListOfLocalParameters,
+                                LocalParameter,
+                                KineticLaw,
+                                ListOfFunctionDefinitions,
+                                FunctionDefinition,
+                                ListOfInitialAssignments,
+                                InitialAssignment,
+                                ListOfRules,
+                                AssignmentRule,
+                                RateRule,
+                                AlgebraicRule,
+                                ListOfEvents,
+                                Event,
+                                Trigger,
+                                Delay,
+                                Priority,
+                                ListOfEventAssignments,
+                                EventAssignment,
+                                ListOfConstraints,
+                                Constraint,
+                                ListOfCompartmentTypes,
+                                CompartmentType,
+                                ListOfSpeciesTypes,
+                                SpeciesType,
+                                StoichiometryMath,
+                            ];
+                        }
+                    }
+                    b"listOfConstraints" => attach!(ListOfConstraints to Root),
+                    b"constraint" => {
+                        attach!(Constraint with
+                                    id as String,
+                                    metaid as String,
+                                    sbo_term as String
+                                to ListOfConstraints)
+                    }
+                    b"message" => match nodes[current] {
+                        Tag::Constraint(ref mut constraint) => {
+                            let message = read_raw_content(&mut reader, b"message")
+                                .map_err(|err| SbmlError::xml_syntax(err, &location))?;
+                            constraint.message = Some(message);
+                            // the closing tag has been consumed
+                            path.pop();
+                        }
+                        _ => {
+                            diagnostics.report(SbmlError::UnknownElement {
+                                element: element.clone(),
+                                location: location.clone(),
+                            })?;
+                            skip_subtree = true;
+                        }
+                    },
                     _ => {
-                        panic!("Tag not parsed: {}", str::from_utf8(e.name()).unwrap());
+                        diagnostics.report(SbmlError::UnknownElement {
+                            element: element.clone(),
+                            location: location.clone(),
+                        })?;
+                        skip_subtree = true;
                     }
                 }
                 if let Some(t) = new_tag {
                     nodes.push(t);
                     nodes_len += 1;
                 }
+                // a Level 1 formula stands in for a <math> child
+                if let (Some(formula), false) = (formula, skip_subtree) {
+                    match formula::parse_formula(&formula) {
+                        Ok(math_nodes) => {
+                            let mut new_tag = None;
+                            attach_math![KineticLaw, AssignmentRule, RateRule, AlgebraicRule];
+                            if let Some(t) = new_tag {
+                                nodes.push(t);
+                                nodes_len += 1;
+                            }
+                        }
+                        Err(message) => diagnostics.report(SbmlError::InvalidMath {
+                            message,
+                            location: location.clone(),
+                        })?,
+                    }
+                }
+                if skip_subtree {
+                    reader
+                        .read_to_end(element.as_bytes(), &mut skip_buf)
+                        .map_err(|err| SbmlError::xml_syntax(err, &location))?;
+                    skip_buf.clear();
+                    path.pop();
+                }
             }
             // for each closing tag
-            Ok(Event::End(ref e)) => match e.name() {
-                b"listOfUnitDefinitions" => close![ListOfUnitDefinitions],
-                b"unitDefinition" => close![UnitDefinition],
-                b"listOfUnits" => close![ListOfUnits],
-                b"unit" => close![Unit],
-                b"listOfCompartments" => close![ListOfCompartments],
-                b"compartment" => close![Compartment],
-                b"listOfParameters" => close![ListOfParameters],
-                b"parameter" => close![Parameter],
-                b"listOfSpecies" => close![ListOfSpecies],
-                b"species" => close![Species],
-                b"listOfReactions" => close![ListOfReactions],
-                b"reaction" => close![Reaction],
-                b"listOfReactants" => close![ListOfReactants],
-                b"listOfProducts" => close![ListOfProducts],
-                b"speciesReference" => close![SpeciesReference],
-                b"listOfModifiers" => close![ListOfModifiers],
-                b"modifierSpeciesReference" => close![ModifierSpeciesReference],
-                b"kineticLaw" => close![KineticLaw],
-                b"listOfLocalParameters" => close![ListOfLocalParameters],
-                b"localParameter" => close![LocalParameter],
-                b"math" => close![MathTag],
-                b"listOfFunctionDefinitions" => close![ListOfFunctionDefinitions],
-                b"functionDefinition" => close![FunctionDefinition],
-                b"listOfInitialAssignments" => close![ListOfInitialAssignments],
-                b"initialAssignment" => close![InitialAssignment],
-                b"listOfRules" => close![ListOfRules],
-                b"assignmentRule" => close![AssignmentRule],
-                b"rateRule" => close![RateRule],
-                _ => {}
-            },
+            Ok(XmlEvent::End(ref e)) => {
+                let location = line_index.locate_tag(&path);
+                path.pop();
+                match e.name() {
+                    b"listOfUnitDefinitions" => close![ListOfUnitDefinitions],
+                    b"unitDefinition" => close![UnitDefinition],
+                    b"listOfUnits" => close![ListOfUnits],
+                    b"unit" => close![Unit],
+                    b"listOfCompartments" => close![ListOfCompartments],
+                    b"compartment" => close![Compartment],
+                    b"listOfCompartmentTypes" => close![ListOfCompartmentTypes],
+                    b"compartmentType" => close![CompartmentType],
+                    b"listOfSpeciesTypes" => close![ListOfSpeciesTypes],
+                    b"speciesType" => close![SpeciesType],
+                    b"listOfParameters" => match nodes[current] {
+                        Tag::ListOfLocalParameters(_) => close![ListOfLocalParameters],
+                        _ => close![ListOfParameters],
+                    },
+                    b"parameter" => match nodes[current] {
+                        Tag::LocalParameter(_) => close![LocalParameter],
+                        _ => close![Parameter],
+                    },
+                    b"listOfSpecies" => close![ListOfSpecies],
+                    b"species" | b"specie" => close![Species],
+                    b"listOfReactions" => close![ListOfReactions],
+                    b"reaction" => close![Reaction],
+                    b"listOfReactants" => close![ListOfReactants],
+                    b"listOfProducts" => close![ListOfProducts],
+                    b"speciesReference" | b"specieReference" => close![SpeciesReference],
+                    b"stoichiometryMath" => close![StoichiometryMath],
+                    b"listOfModifiers" => close![ListOfModifiers],
+                    b"modifierSpeciesReference" => close![ModifierSpeciesReference],
+                    b"kineticLaw" => close![KineticLaw],
+                    b"listOfLocalParameters" => close![ListOfLocalParameters],
+                    b"localParameter" => close![LocalParameter],
+                    b"math" => close![MathTag],
+                    b"listOfFunctionDefinitions" => close![ListOfFunctionDefinitions],
+                    b"functionDefinition" => close![FunctionDefinition],
+                    b"listOfInitialAssignments" => close![ListOfInitialAssignments],
+                    b"initialAssignment" => close![InitialAssignment],
+                    b"listOfRules" => close![ListOfRules],
+                    b"assignmentRule" => close![AssignmentRule],
+                    b"rateRule" => close![RateRule],
+                    b"algebraicRule" => close![AlgebraicRule],
+                    // Level 1 rules, read as assignment or rate rules
+                    b"specieConcentrationRule"
+                    | b"speciesConcentrationRule"
+                    | b"compartmentVolumeRule"
+                    | b"parameterRule" => match nodes[current] {
+                        Tag::RateRule(_) => close![RateRule],
+                        _ => close![AssignmentRule],
+                    },
+                    b"listOfEvents" => close![ListOfEvents],
+                    b"event" => close![Event],
+                    b"trigger" => close![Trigger],
+                    b"delay" => close![Delay],
+                    b"priority" => close![Priority],
+                    b"listOfEventAssignments" => close![ListOfEventAssignments],
+                    b"eventAssignment" => close![EventAssignment],
+                    b"listOfConstraints" => close![ListOfConstraints],
+                    b"constraint" => close![Constraint],
+                    _ => {}
+                }
+            }
             // unescape and decode the text event using the reader encoding
-            Ok(Event::Text(e)) => {
-                let s = e.unescape_and_decode(&reader).unwrap();
-                panic!("Unknown text found in {:?}", nodes[current]);
+            Ok(XmlEvent::Text(e)) => {
+                let location = line_index.locate(reader.buffer_position(), &path);
+                let s = e
+                    .unescape_and_decode(&reader)
+                    .map_err(|err| SbmlError::xml_syntax(err, &location))?;
+                diagnostics.report(SbmlError::XmlSyntax {
+                    message: format!("Unexpected text '{}'", s),
+                    location,
+                })?;
+            }
+            Ok(XmlEvent::Eof) => break, // exits the loop when reaching end of file
+            Err(e) => {
+                let location = line_index.locate(reader.buffer_position(), &path);
+                return Err(SbmlError::xml_syntax(e, &location));
             }
-            Ok(Event::Eof) => break, // exits the loop when reaching end of file
-            Err(e) => panic!("Error at position {}: {:?}", reader.buffer_position(), e),
             _ => (), // There are several other `Event`s we do not consider here
         }
+        buf.clear();
     }
 
-    let model = Model::new(nodes, model_attrs);
+    document.model = Model::new(nodes, model_attrs);
+    document.model.notes = model_notes;
+    document.model.annotation = model_annotation;
+    let (level, _) = document.level_version();
+    levels::apply_defaults(&mut document.model, level);
+    document.diagnostics = diagnostics.warnings;
 
-    Ok(model)
+    Ok(document)
+}
+
+// Reads everything up to the closing tag of the element that was just
+// opened and returns it as XML text, keeping whitespace and empty tags.
+fn read_raw_content<R: BufRead>(
+    reader: &mut Reader<R>,
+    end: &[u8],
+) -> Result<String, quick_xml::Error> {
+    let mut buf = Vec::new();
+    let mut writer = Writer::new(Vec::new());
+    let mut depth = 0;
+    reader.trim_text(false).expand_empty_elements(false);
+    let result = loop {
+        let event = match reader.read_event(&mut buf) {
+            Ok(event) => event,
+            Err(e) => break Err(e),
+        };
+        match &event {
+            XmlEvent::Start(e) if e.name() == end => depth += 1,
+            XmlEvent::End(e) if e.name() == end => {
+                if depth == 0 {
+                    break Ok(());
+                }
+                depth -= 1;
+            }
+            XmlEvent::Eof => {
+                break Err(quick_xml::Error::UnexpectedEof(
+                    String::from_utf8_lossy(end).to_string(),
+                ))
+            }
+            _ => {}
+        }
+        if let Err(e) = writer.write_event(&event) {
+            break Err(e);
+        }
+        buf.clear();
+    };
+    reader.trim_text(true).expand_empty_elements(true);
+    result?;
+    Ok(String::from_utf8_lossy(&writer.into_inner())
+        .trim()
+        .to_string())
 }
 
-pub fn parse_and_transform(filename: &str) -> Result<Model, Vec<String>> {
+pub fn parse_and_transform(filename: &str) -> Result<Model, SbmlError> {
     let model = parse(filename)?;
     transform(model)
 }
 
+pub fn parse_and_transform_str(sbml: &str) -> Result<Model, SbmlError> {
+    let model = parse_str(sbml)?;
+    transform(model)
+}
+
+pub fn parse_and_transform_bytes(sbml: &[u8]) -> Result<Model, SbmlError> {
+    let model = parse_bytes(sbml)?;
+    transform(model)
+}
+
+pub fn parse_and_transform_reader<R: BufRead>(source: R) -> Result<Model, SbmlError> {
+    let model = parse_reader(source)?;
+    transform(model)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -269,10 +777,1733 @@ mod tests {
                         println!("{}", function_definition.math_tag(&model).unwrap());
                     }
                 }
-                Err(errors) => {
-                    println!("{:?}", errors);
+                Err(error) => {
+                    println!("{}", error);
                 }
             }
         }
     }
+
+    #[test]
+    fn parse_from_memory() {
+        let filename = "../models/small.xml";
+        let sbml = std::fs::read_to_string(filename).unwrap();
+
+        let models = vec![
+            parse(filename).unwrap(),
+            parse_str(&sbml).unwrap(),
+            parse_bytes(sbml.as_bytes()).unwrap(),
+            parse_reader(sbml.as_bytes()).unwrap(),
+        ];
+        for model in models {
+            assert_eq!(model.species().len(), 2);
+            let reactions = model.reactions();
+            assert_eq!(reactions.len(), 1);
+            // root, apply, times and two identifiers
+            let kinetic_law = reactions[0].kinetic_law(&model).unwrap();
+            assert_eq!(kinetic_law.nodes.len(), 5);
+        }
+    }
+
+    #[test]
+    fn parse_errors() {
+        let wrap = |species: &str| {
+            format!(
+                "<sbml>\n  <model>\n    <listOfSpecies>\n      {}\n    </listOfSpecies>\n  </model>\n</sbml>",
+                species
+            )
+        };
+
+        match parse_str(&wrap(r#"<species id="S1" colour="red"/>"#)) {
+            Err(SbmlError::UnknownAttribute {
+                attribute,
+                location,
+            }) => {
+                assert_eq!(attribute, "colour");
+                assert_eq!(location.path, "/sbml/model/listOfSpecies/species");
+                assert_eq!(location.line, Some(4));
+                assert_eq!(location.column, Some(7));
+            }
+            other => panic!("Expected an unknown attribute, got {:?}", other),
+        }
+
+        match parse_str(&wrap(r#"<species id="S1" initialAmount="lots"/>"#)) {
+            Err(SbmlError::InvalidAttributeValue {
+                attribute, value, ..
+            }) => {
+                assert_eq!(attribute, "initialAmount");
+                assert_eq!(value, "lots");
+            }
+            other => panic!("Expected an invalid value, got {:?}", other),
+        }
+
+        match parse_str(&wrap("<widget/>")) {
+            Err(SbmlError::UnknownElement { element, location }) => {
+                assert_eq!(element, "widget");
+                assert_eq!(location.path, "/sbml/model/listOfSpecies/widget");
+            }
+            other => panic!("Expected an unknown element, got {:?}", other),
+        }
+
+        // only attributes in other namespaces are allowed on <sbml>
+        assert!(matches!(
+            parse_str(r#"<sbml level="3" version="2" colour="red"><model/></sbml>"#),
+            Err(SbmlError::UnknownAttribute { ref attribute, .. }) if attribute == "colour"
+        ));
+
+        assert!(matches!(
+            parse("does-not-exist.xml"),
+            Err(SbmlError::Io { .. })
+        ));
+    }
+
+    #[test]
+    fn lenient_parsing() {
+        let sbml = r#"
+            <sbml>
+              <model id="m">
+                <listOfWidgets>
+                  <widget id="w1"/>
+                </listOfWidgets>
+                <listOfSpecies>
+                  <species id="S1" compartment="C" colour="red"/>
+                  <species id="S2" compartment="C"/>
+                </listOfSpecies>
+              </model>
+            </sbml>"#;
+        assert!(parse_str(sbml).is_err());
+
+        let options = ParseOptions {
+            strictness: Strictness::Lenient,
+        };
+        let document = parse_reader_with_options(sbml.as_bytes(), &options).unwrap();
+        let warnings = document.diagnostics;
+        assert_eq!(document.model.species().len(), 2);
+        assert_eq!(warnings.len(), 2);
+        assert_eq!(warnings[0].message, "Unknown element 'listOfWidgets'");
+        assert_eq!(warnings[0].location.line, Some(4));
+        assert_eq!(warnings[1].message, "Unknown attribute 'colour'");
+        assert_eq!(
+            warnings[1].location.path,
+            "/sbml/model/listOfSpecies/species"
+        );
+    }
+
+    #[test]
+    fn document_level_and_packages() {
+        let sbml = r#"
+            <sbml xmlns="http://www.sbml.org/sbml/level3/version2/core" level="3" version="2"
+                  xmlns:fbc="http://www.sbml.org/sbml/level3/version1/fbc/version2"
+                  xmlns:jd="http://www.sys-bio.org/sbml"
+                  xmlns:xsi="http://www.w3.org/2001/XMLSchema-instance"
+                  xsi:schemaLocation="http://www.sbml.org/sbml/level3/version2/core sbml.xsd"
+                  fbc:required="false">
+              <model id="m">
+                <annotation><jd:header version="2"/></annotation>
+              </model>
+            </sbml>"#;
+        let document = parse_document_str(sbml).unwrap();
+        assert_eq!(document.level_version(), (3, 2));
+        assert_eq!(
+            document.core_namespace().unwrap(),
+            "http://www.sbml.org/sbml/level3/version2/core"
+        );
+        let fbc = document.package("fbc").unwrap();
+        assert_eq!(
+            fbc.uri,
+            "http://www.sbml.org/sbml/level3/version1/fbc/version2"
+        );
+        assert!(!fbc.required);
+        assert_eq!(document.model.id.as_deref(), Some("m"));
+
+        // the prefixes annotations use stay declared when written
+        let written = document.to_sbml_string().unwrap();
+        assert!(written.contains(r#"xmlns:jd="http://www.sys-bio.org/sbml""#));
+        let reread = parse_document_str(&written).unwrap();
+        assert_eq!(reread.namespaces, document.namespaces);
+        assert_eq!(reread.packages, document.packages);
+        assert!(reread.model.annotation.unwrap().contains("<jd:header"));
+    }
+
+    #[test]
+    fn parse_events() {
+        let sbml = r#"
+            <sbml level="3" version="2">
+              <model>
+                <listOfEvents>
+                  <event id="e1" useValuesFromTriggerTime="false">
+                    <trigger initialValue="true" persistent="false">
+                      <math><apply><gt/><ci>S1</ci><cn>5</cn></apply></math>
+                    </trigger>
+                    <delay><math><cn type="integer">2</cn></math></delay>
+                    <listOfEventAssignments>
+                      <eventAssignment variable="S1"><math><cn>0</cn></math></eventAssignment>
+                    </listOfEventAssignments>
+                  </event>
+                </listOfEvents>
+              </model>
+            </sbml>"#;
+        let model = parse_str(sbml).unwrap();
+        let events = model.events();
+        assert_eq!(events.len(), 1);
+        let event = &events[0];
+        assert_eq!(event.use_values_from_trigger_time, Some(false));
+
+        let trigger = event.trigger(&model).unwrap();
+        assert_eq!(trigger.initial_value, Some(true));
+        assert_eq!(trigger.persistent, Some(false));
+        // root, apply, gt, ci and cn
+        assert_eq!(event.trigger_math(&model).unwrap().nodes.len(), 5);
+        assert!(event.delay_math(&model).is_some());
+        assert!(event.priority_math(&model).is_none());
+
+        let assignments = event.event_assignments(&model);
+        assert_eq!(assignments.len(), 1);
+        assert_eq!(assignments[0].variable.as_deref(), Some("S1"));
+        assert!(assignments[0].math_tag(&model).is_some());
+    }
+
+    #[test]
+    fn transform_algebraic_rules() {
+        let sbml = r#"
+            <sbml level="3" version="2">
+              <model>
+                <listOfCompartments>
+                  <compartment id="C" size="2" constant="true"/>
+                </listOfCompartments>
+                <listOfSpecies>
+                  <species id="S" compartment="C" hasOnlySubstanceUnits="false"/>
+                </listOfSpecies>
+                <listOfRules>
+                  <algebraicRule>
+                    <math><apply><minus/><ci>S</ci><cn>1</cn></apply></math>
+                  </algebraicRule>
+                </listOfRules>
+              </model>
+            </sbml>"#;
+        let model = parse_and_transform_str(sbml).unwrap();
+        let rules = model.algebraic_rules();
+        assert_eq!(rules.len(), 1);
+
+        // S was replaced with S / C
+        let math = rules[0].math_tag(&model).unwrap();
+        let divisions = math
+            .nodes
+            .iter()
+            .filter(|node| match node {
+                MathNode::Op(op) => matches!(op.op, Some(mathml_rs::Op::Divide)),
+                _ => false,
+            })
+            .count();
+        assert_eq!(divisions, 1);
+    }
+
+    #[test]
+    fn transform_unreadable_math() {
+        // a number is no operator, so the math is no expression tree
+        let sbml = r#"
+            <sbml level="3" version="2">
+              <model>
+                <listOfCompartments>
+                  <compartment id="C" size="2" constant="true"/>
+                </listOfCompartments>
+                <listOfSpecies>
+                  <species id="S" compartment="C" hasOnlySubstanceUnits="false"/>
+                </listOfSpecies>
+                <listOfRules>
+                  <algebraicRule>
+                    <math><apply><cn>2</cn><ci>S</ci></apply></math>
+                  </algebraicRule>
+                </listOfRules>
+              </model>
+            </sbml>"#;
+        let model = parse_str(sbml).unwrap();
+        let math = model.algebraic_rules()[0].math_tag(&model).unwrap();
+        assert!(math.to_expr().is_err());
+
+        // S is still replaced with S / C
+        let model = parse_and_transform_str(sbml).unwrap();
+        let math = model.algebraic_rules()[0].math_tag(&model).unwrap();
+        let apply = math
+            .nodes
+            .iter()
+            .find_map(|node| match node {
+                MathNode::Op(op) if matches!(op.op, Some(mathml_rs::Op::Divide)) => op.parent,
+                _ => None,
+            })
+            .unwrap();
+        let operands = match &math.nodes[apply] {
+            MathNode::Apply(apply) => apply
+                .operands
+                .iter()
+                .filter_map(|idx| match &math.nodes[*idx] {
+                    MathNode::Ci(ci) => ci.name.as_deref(),
+                    _ => None,
+                })
+                .collect::<Vec<_>>(),
+            _ => Vec::new(),
+        };
+        assert_eq!(operands, vec!["S", "C"]);
+    }
+
+    #[test]
+    fn parse_constraints() {
+        let sbml = r#"
+            <sbml level="3" version="2">
+              <model>
+                <listOfConstraints>
+                  <constraint id="positive">
+                    <math><apply><gt/><ci>S1</ci><cn>0</cn></apply></math>
+                    <message>
+                      <p xmlns="http://www.w3.org/1999/xhtml">S1 must stay <b>positive</b><br/></p>
+                    </message>
+                  </constraint>
+                </listOfConstraints>
+              </model>
+            </sbml>"#;
+        let model = parse_str(sbml).unwrap();
+        let constraints = model.constraints();
+        assert_eq!(constraints.len(), 1);
+        let constraint = &constraints[0];
+        assert_eq!(
+            constraint.message.as_deref(),
+            Some(
+                r#"<p xmlns="http://www.w3.org/1999/xhtml">S1 must stay <b>positive</b><br/></p>"#
+            )
+        );
+
+        let mut values = HashMap::new();
+        values.insert("S1".to_string(), 3.0);
+        assert_eq!(constraint.check(&model, &values), Ok(true));
+        values.insert("S1".to_string(), -1.0);
+        assert_eq!(constraint.check(&model, &values), Ok(false));
+    }
+
+    #[test]
+    fn parse_notes_and_annotations() {
+        let sbml = r#"
+            <sbml level="3" version="2">
+              <notes><body xmlns="http://www.w3.org/1999/xhtml">Document notes</body></notes>
+              <model metaid="model_1">
+                <notes>
+                  <body xmlns="http://www.w3.org/1999/xhtml"><p>Curated model</p></body>
+                </notes>
+                <listOfSpecies>
+                  <species id="S1" compartment="C">
+                    <annotation>
+                      <myapp:data xmlns:myapp="http://example.org/myapp" colour="red"/>
+                    </annotation>
+                  </species>
+                </listOfSpecies>
+              </model>
+            </sbml>"#;
+        let document = parse_document_str(sbml).unwrap();
+        assert_eq!(
+            document.notes.as_deref(),
+            Some(r#"<body xmlns="http://www.w3.org/1999/xhtml">Document notes</body>"#)
+        );
+        let model = document.model;
+        assert_eq!(
+            model.notes.as_deref(),
+            Some(r#"<body xmlns="http://www.w3.org/1999/xhtml"><p>Curated model</p></body>"#)
+        );
+        let species = model.species();
+        assert_eq!(
+            species[0].annotation.as_deref(),
+            Some(r#"<myapp:data xmlns:myapp="http://example.org/myapp" colour="red"/>"#)
+        );
+        assert!(species[0].notes.is_none());
+    }
+
+    #[test]
+    fn parse_cv_terms() {
+        let sbml = r##"<?xml version="1.0" encoding="UTF-8"?>
+            <sbml xmlns="http://www.sbml.org/sbml/level3/version2/core" level="3" version="2">
+              <model>
+                <listOfSpecies>
+                  <species id="glc" metaid="meta_glc" compartment="C">
+                    <annotation>
+                      <rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#"
+                               xmlns:bqbiol="http://biomodels.net/biology-qualifiers/">
+                        <rdf:Description rdf:about="#meta_glc">
+                          <bqbiol:is>
+                            <rdf:Bag>
+                              <rdf:li rdf:resource="http://identifiers.org/CHEBI:17234"/>
+                              <rdf:li rdf:resource="http://identifiers.org/kegg.compound/C00293"/>
+                            </rdf:Bag>
+                          </bqbiol:is>
+                          <bqbiol:isPartOf>
+                            <rdf:Bag>
+                              <rdf:li rdf:resource="http://identifiers.org/GO:0005737"/>
+                            </rdf:Bag>
+                          </bqbiol:isPartOf>
+                        </rdf:Description>
+                      </rdf:RDF>
+                    </annotation>
+                  </species>
+                  <species id="atp" metaid="meta_atp" compartment="C"/>
+                </listOfSpecies>
+              </model>
+            </sbml>"##;
+        let mut model = parse_str(sbml).unwrap();
+        let glc = &model.species()[0];
+        assert_eq!(glc.meta_id.as_deref(), Some("meta_glc"));
+        let terms = glc.cv_terms().unwrap();
+        assert_eq!(terms.len(), 2);
+        assert_eq!(
+            terms[0].qualifier,
+            miriam::Qualifier::Biological(miriam::BiologicalQualifier::Is)
+        );
+        assert_eq!(terms[0].resources.len(), 2);
+        assert_eq!(
+            terms[1].qualifier,
+            miriam::Qualifier::Biological(miriam::BiologicalQualifier::IsPartOf)
+        );
+
+        let chebi = model.species_with_resource("CHEBI:").unwrap();
+        assert_eq!(chebi.len(), 1);
+        assert_eq!(chebi[0].id(), "glc");
+        assert_eq!(model.cv_terms().unwrap()["meta_glc"], terms);
+
+        // annotate the second species and read the terms back
+        let atp_terms = vec![miriam::CvTerm::new(
+            miriam::Qualifier::Biological(miriam::BiologicalQualifier::Is),
+            vec!["http://identifiers.org/CHEBI:15422".to_string()],
+        )];
+        for tag in model.nodes_mut().iter_mut() {
+            if let Tag::Species(species) = tag {
+                if species.id() == "atp" {
+                    species.set_cv_terms(&atp_terms).unwrap();
+                    assert_eq!(species.cv_terms().unwrap(), atp_terms);
+                }
+            }
+        }
+        assert_eq!(model.species_with_resource("CHEBI:").unwrap().len(), 2);
+
+        // replacing the terms keeps whatever else the annotation says
+        let annotation = r##"<rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#"
+                     xmlns:bqbiol="http://biomodels.net/biology-qualifiers/">
+              <rdf:Description rdf:about="#meta_glc">
+                <bqbiol:isRelatedTo>
+                  <rdf:Bag>
+                    <rdf:li rdf:resource="http://identifiers.org/GO:0006096"/>
+                  </rdf:Bag>
+                </bqbiol:isRelatedTo>
+                <bqbiol:is>
+                  <rdf:Bag>
+                    <rdf:li rdf:resource="http://identifiers.org/CHEBI:17234"/>
+                  </rdf:Bag>
+                </bqbiol:is>
+              </rdf:Description>
+              <rdf:Description rdf:about="#meta_other">
+                <rdf:value>42</rdf:value>
+              </rdf:Description>
+            </rdf:RDF>
+            <tool xmlns="http://example.org/tool">kept</tool>"##;
+        let updated = miriam::set_cv_terms(Some(annotation), "meta_glc", &atp_terms).unwrap();
+        assert!(updated.contains("bqbiol:isRelatedTo"));
+        assert!(updated.contains("GO:0006096"));
+        assert!(updated.contains(r##"<rdf:Description rdf:about="#meta_other">"##));
+        assert!(updated.contains("<rdf:value>42</rdf:value>"));
+        assert!(updated.contains(r#"<tool xmlns="http://example.org/tool">kept</tool>"#));
+        assert!(!updated.contains("CHEBI:17234"));
+        let terms = miriam::parse_cv_terms(&updated).unwrap();
+        assert_eq!(terms["meta_glc"], atp_terms);
+    }
+
+    #[test]
+    fn model_history() {
+        let sbml = r##"<?xml version="1.0" encoding="UTF-8"?>
+            <sbml xmlns="http://www.sbml.org/sbml/level3/version1/core" level="3" version="1">
+              <model metaid="meta_model">
+                <annotation>
+                  <rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#"
+                           xmlns:dc="http://purl.org/dc/elements/1.1/"
+                           xmlns:dcterms="http://purl.org/dc/terms/"
+                           xmlns:vCard="http://www.w3.org/2001/vcard-rdf/3.0#"
+                           xmlns:bqmodel="http://biomodels.net/model-qualifiers/"
+                           xmlns:bqbiol="http://biomodels.net/biology-qualifiers/">
+                    <rdf:Description rdf:about="#meta_model">
+                      <dc:creator>
+                        <rdf:Bag>
+                          <rdf:li rdf:parseType="Resource">
+                            <vCard:N rdf:parseType="Resource">
+                              <vCard:Family>Doe</vCard:Family>
+                              <vCard:Given>Jane</vCard:Given>
+                            </vCard:N>
+                            <vCard:EMAIL>jane@example.org</vCard:EMAIL>
+                            <vCard:ORG rdf:parseType="Resource">
+                              <vCard:Orgname>Example University</vCard:Orgname>
+                            </vCard:ORG>
+                            <vCard:TEL rdf:parseType="Resource">
+                              <vCard:NUMBER>555-0100</vCard:NUMBER>
+                            </vCard:TEL>
+                          </rdf:li>
+                        </rdf:Bag>
+                      </dc:creator>
+                      <dcterms:created rdf:parseType="Resource">
+                        <dcterms:W3CDTF>2005-02-02T14:56:11Z</dcterms:W3CDTF>
+                      </dcterms:created>
+                      <dcterms:modified rdf:parseType="Resource">
+                        <dcterms:W3CDTF>2008-03-05T10:00:00Z</dcterms:W3CDTF>
+                      </dcterms:modified>
+                      <bqmodel:is>
+                        <rdf:Bag>
+                          <rdf:li rdf:resource="http://identifiers.org/biomodels.db/BIOMD0000000001"/>
+                        </rdf:Bag>
+                      </bqmodel:is>
+                      <bqbiol:isRelatedTo>
+                        <rdf:Bag>
+                          <rdf:li rdf:resource="http://identifiers.org/GO:0006096"/>
+                        </rdf:Bag>
+                      </bqbiol:isRelatedTo>
+                    </rdf:Description>
+                  </rdf:RDF>
+                </annotation>
+              </model>
+            </sbml>"##;
+        let mut model = parse_str(sbml).unwrap();
+        let mut history = model.history().unwrap().unwrap();
+        assert_eq!(history.creators.len(), 1);
+        let creator = &history.creators[0];
+        assert_eq!(creator.family_name.as_deref(), Some("Doe"));
+        assert_eq!(creator.given_name.as_deref(), Some("Jane"));
+        assert_eq!(creator.email.as_deref(), Some("jane@example.org"));
+        assert_eq!(creator.organisation.as_deref(), Some("Example University"));
+        assert_eq!(creator.other_fields.len(), 1);
+        assert!(creator.other_fields[0].starts_with("<vCard:TEL"));
+        assert_eq!(history.created.as_deref(), Some("2005-02-02T14:56:11Z"));
+        assert_eq!(
+            history.last_modified().map(String::as_str),
+            Some("2008-03-05T10:00:00Z")
+        );
+
+        // update the history, the controlled vocabulary terms are kept
+        history
+            .creators
+            .push(miriam::ModelCreator::new("John", "Smith"));
+        history.add_modified("2021-06-01T09:30:00Z");
+        model.set_history(Some(&history)).unwrap();
+        assert_eq!(model.history().unwrap(), Some(history));
+        assert_eq!(model.cv_terms().unwrap()["meta_model"].len(), 1);
+
+        // fields and qualifiers that are not modelled survive the update
+        let annotation = model.annotation.as_ref().unwrap();
+        assert!(annotation.contains("<vCard:NUMBER>555-0100</vCard:NUMBER>"));
+        assert!(annotation.contains("<bqbiol:isRelatedTo>"));
+        assert!(annotation.contains("GO:0006096"));
+        let reread = parse_str(&model.to_sbml_string().unwrap()).unwrap();
+        assert_eq!(reread.history().unwrap(), model.history().unwrap());
+    }
+
+    #[test]
+    fn parse_level_2() {
+        let sbml = r#"<?xml version="1.0" encoding="UTF-8"?>
+            <sbml xmlns="http://www.sbml.org/sbml/level2/version4" level="2" version="4">
+              <model id="l2">
+                <listOfCompartmentTypes>
+                  <compartmentType id="cell"/>
+                </listOfCompartmentTypes>
+                <listOfSpeciesTypes>
+                  <speciesType id="ion" name="Ion"/>
+                </listOfSpeciesTypes>
+                <listOfCompartments>
+                  <compartment id="outer" size="10"/>
+                  <compartment id="C" compartmentType="cell" outside="outer" size="1"/>
+                </listOfCompartments>
+                <listOfSpecies>
+                  <species id="S1" compartment="C" speciesType="ion" charge="-1"
+                           spatialSizeUnits="volume" initialConcentration="2"/>
+                  <species id="S2" compartment="C" initialConcentration="0"/>
+                </listOfSpecies>
+                <listOfParameters>
+                  <parameter id="n" value="2"/>
+                </listOfParameters>
+                <listOfReactions>
+                  <reaction id="R1">
+                    <listOfReactants>
+                      <speciesReference species="S1">
+                        <stoichiometryMath>
+                          <math xmlns="http://www.w3.org/1998/Math/MathML"><ci> n </ci></math>
+                        </stoichiometryMath>
+                      </speciesReference>
+                    </listOfReactants>
+                    <listOfProducts>
+                      <speciesReference species="S2"/>
+                    </listOfProducts>
+                    <kineticLaw>
+                      <math xmlns="http://www.w3.org/1998/Math/MathML">
+                        <apply><times/><ci> k </ci><ci> S1 </ci></apply>
+                      </math>
+                      <listOfParameters>
+                        <parameter id="k" name="rate" value="0.1" constant="true"/>
+                      </listOfParameters>
+                    </kineticLaw>
+                  </reaction>
+                </listOfReactions>
+              </model>
+            </sbml>"#;
+        let model = parse_str(sbml).unwrap();
+        assert_eq!(model.compartment_types()[0].id.as_deref(), Some("cell"));
+        assert_eq!(model.species_types()[0].name.as_deref(), Some("Ion"));
+
+        let compartments = model.compartments();
+        assert_eq!(compartments[1].compartment_type.as_deref(), Some("cell"));
+        assert_eq!(compartments[1].outside.as_deref(), Some("outer"));
+        assert_eq!(compartments[1].spatial_dimensions, Some(3.0));
+        assert_eq!(compartments[1].constant, Some(true));
+
+        let species = model.species();
+        assert_eq!(species[0].species_type.as_deref(), Some("ion"));
+        assert_eq!(species[0].charge, Some(-1));
+        assert_eq!(species[0].spatial_size_units.as_deref(), Some("volume"));
+        assert_eq!(species[0].boundary_condition, Some(false));
+        assert_eq!(species[0].has_only_substance_units, Some(false));
+        assert_eq!(model.parameters()[0].constant, Some(true));
+
+        // kinetic law parameters are local parameters
+        assert_eq!(model.parameters().len(), 1);
+        let reaction = &model.reactions()[0];
+        assert_eq!(reaction.reversible, Some(true));
+        let local_parameters = model.local_parameter_values();
+        assert_eq!(local_parameters["R1"]["k"], 0.1);
+
+        let reactants = reaction.reactants(&model);
+        assert!(reactants[0].stoichiometry.is_none());
+        assert_eq!(reactants[0].constant, Some(false));
+        let stoichiometry = reactants[0].stoichiometry_math(&model).unwrap();
+        assert_eq!(stoichiometry.nodes.len(), 2);
+        let products = reaction.products(&model);
+        assert_eq!(products[0].stoichiometry, Some(1.0));
+        assert_eq!(products[0].constant, Some(true));
+
+        // Level 3 sets a variable stoichiometry with an assignment rule
+        let written = parse_str(&model.to_sbml_string().unwrap()).unwrap();
+        let reactant = &written.reactions()[0].reactants(&written)[0];
+        assert_eq!(reactant.id.as_deref(), Some("S1_stoichiometry"));
+        assert_eq!(reactant.constant, Some(false));
+        assert!(reactant.stoichiometry.is_none());
+        let rule = &written.assignment_rules()[0];
+        assert_eq!(rule.variable.as_deref(), Some("S1_stoichiometry"));
+        assert_eq!(rule.math_tag(&written).unwrap().to_infix(), "n");
+    }
+
+    #[test]
+    fn parse_level_1() {
+        let sbml = r#"<?xml version="1.0" encoding="UTF-8"?>
+            <sbml xmlns="http://www.sbml.org/sbml/level1" level="1" version="1">
+              <model name="archival">
+                <listOfCompartments>
+                  <compartment name="cell"/>
+                </listOfCompartments>
+                <listOfSpecies>
+                  <specie name="X0" compartment="cell" initialAmount="1" units="mole"/>
+                  <specie name="S1" compartment="cell" initialAmount="0"/>
+                </listOfSpecies>
+                <listOfParameters>
+                  <parameter name="Km" value="0.5"/>
+                </listOfParameters>
+                <listOfRules>
+                  <parameterRule name="Km" formula="sqr(k1) / 2" type="scalar"/>
+                  <specieConcentrationRule specie="S1" formula="-k1 * S1" type="rate"/>
+                </listOfRules>
+                <listOfReactions>
+                  <reaction name="R1" reversible="false">
+                    <listOfReactants>
+                      <specieReference specie="X0" stoichiometry="1" denominator="2"/>
+                    </listOfReactants>
+                    <listOfProducts>
+                      <specieReference specie="S1"/>
+                    </listOfProducts>
+                    <kineticLaw formula="k1 * X0 / (Km + pow(X0, 2))">
+                      <listOfParameters>
+                        <parameter name="k1" value="0.1"/>
+                      </listOfParameters>
+                    </kineticLaw>
+                  </reaction>
+                </listOfReactions>
+              </model>
+            </sbml>"#;
+        let model = parse_str(sbml).unwrap();
+        assert_eq!(model.id.as_deref(), Some("archival"));
+        assert_eq!(model.compartments()[0].size, Some(1.0));
+
+        let species = model.species();
+        assert_eq!(species[0].id(), "X0");
+        assert_eq!(species[0].substance_units.as_deref(), Some("mole"));
+
+        let reaction = &model.reactions()[0];
+        assert_eq!(reaction.id.as_deref(), Some("R1"));
+        assert_eq!(reaction.reactant_ids(&model), vec!["X0".to_string()]);
+        assert_eq!(reaction.reactants(&model)[0].stoichiometry, Some(0.5));
+        assert_eq!(reaction.products(&model)[0].stoichiometry, Some(1.0));
+
+        // k1 * X0 / (Km + X0^2) with k1 = 0.1, X0 = 2 and Km = 0.5
+        let kinetic_law = reaction.kinetic_law(&model).unwrap();
+        let mut values = model.local_parameter_values()["R1"].clone();
+        values.insert("X0".to_string(), 2.0);
+        values.insert("Km".to_string(), 0.5);
+        let rate = mathml_rs::evaluate_node(&kinetic_law.nodes, 0, &values, &HashMap::new());
+        assert!((rate.unwrap() - 0.2 / 4.5).abs() < 1e-12);
+
+        let assignment_rules = model.assignment_rules();
+        assert_eq!(assignment_rules[0].variable.as_deref(), Some("Km"));
+        assert!(assignment_rules[0].math_tag(&model).is_some());
+        let rate_rules = model.rate_rules();
+        assert_eq!(rate_rules[0].variable.as_deref(), Some("S1"));
+        assert!(rate_rules[0].math_tag(&model).is_some());
+    }
+
+    #[test]
+    fn write_round_trip() {
+        let sbml = r#"<?xml version="1.0" encoding="UTF-8"?>
+            <sbml xmlns="http://www.sbml.org/sbml/level3/version2/core" level="3" version="2">
+              <model id="decay" metaid="m1" sboTerm="SBO:0000004" timeUnits="second">
+                <notes><body xmlns="http://www.w3.org/1999/xhtml"><p>A &amp; B</p></body></notes>
+                <listOfCompartments>
+                  <compartment id="cell" size="1" spatialDimensions="3" constant="true"/>
+                </listOfCompartments>
+                <listOfSpecies>
+                  <species id="S1" compartment="cell" initialAmount="10" hasOnlySubstanceUnits="false" boundaryCondition="false" constant="false"/>
+                </listOfSpecies>
+                <listOfParameters>
+                  <parameter id="k" value="0.1" constant="true"/>
+                  <parameter id="half" constant="false"/>
+                </listOfParameters>
+                <listOfRules>
+                  <assignmentRule variable="half">
+                    <math xmlns="http://www.w3.org/1998/Math/MathML">
+                      <apply><divide/><apply><ln/><cn type="integer"> 2 </cn></apply><ci> k </ci></apply>
+                    </math>
+                  </assignmentRule>
+                </listOfRules>
+                <listOfReactions>
+                  <reaction id="R1" reversible="false">
+                    <listOfReactants>
+                      <speciesReference species="S1" stoichiometry="1" constant="true"/>
+                    </listOfReactants>
+                    <kineticLaw>
+                      <math xmlns="http://www.w3.org/1998/Math/MathML">
+                        <apply><times/><ci> k </ci><ci> S1 </ci></apply>
+                      </math>
+                    </kineticLaw>
+                  </reaction>
+                </listOfReactions>
+                <listOfEvents>
+                  <event id="E1" useValuesFromTriggerTime="true">
+                    <trigger initialValue="false" persistent="true">
+                      <math xmlns="http://www.w3.org/1998/Math/MathML">
+                        <apply><gt/><csymbol encoding="text" definitionURL="http://www.sbml.org/sbml/symbols/time"> t </csymbol><cn> 2.5 </cn></apply>
+                      </math>
+                    </trigger>
+                    <listOfEventAssignments>
+                      <eventAssignment variable="S1">
+                        <math xmlns="http://www.w3.org/1998/Math/MathML"><cn type="e-notation"> 1 <sep/> 2 </cn></math>
+                      </eventAssignment>
+                    </listOfEventAssignments>
+                  </event>
+                </listOfEvents>
+              </model>
+            </sbml>"#;
+        let model = parse_str(sbml).unwrap();
+        let written = model.to_sbml_string().unwrap();
+        let reread = parse_str(&written).unwrap();
+
+        assert_eq!(reread.id.as_deref(), Some("decay"));
+        assert_eq!(reread.time_units.as_deref(), Some("second"));
+        assert_eq!(reread.sbo_term.as_deref(), Some("SBO:0000004"));
+        assert!(reread.notes.as_ref().unwrap().contains("A &amp; B"));
+        assert_eq!(reread.species()[0].initial_amount, Some(10.0));
+        assert_eq!(reread.parameters()[1].value, None);
+        assert_eq!(reread.reactions()[0].reactant_ids(&reread), vec!["S1"]);
+        assert_eq!(reread.events()[0].use_values_from_trigger_time, Some(true));
+
+        let rule = &reread.assignment_rules()[0];
+        let values: HashMap<String, f64> = vec![("k".to_string(), 0.1)].into_iter().collect();
+        let half = mathml_rs::evaluate_node(
+            &rule.math_tag(&reread).unwrap().nodes,
+            0,
+            &values,
+            &HashMap::new(),
+        );
+        assert!((half.unwrap() - 2f64.ln() / 0.1).abs() < 1e-12);
+
+        // writing is deterministic, so a second pass gives the same document
+        assert_eq!(reread.to_sbml_string().unwrap(), written);
+    }
+
+    #[test]
+    fn model_ignores_unknown_attributes() {
+        // attributes the parser does not know are not the constructor's problem
+        let mut attributes = HashMap::new();
+        attributes.insert("unknown".to_string(), "value".to_string());
+        attributes.insert("id".to_string(), "m".to_string());
+        let model = Model::new(vec![Tag::Root(Root::default())], attributes);
+        assert_eq!(model.id.as_deref(), Some("m"));
+    }
+
+    #[test]
+    fn math_to_mathml() {
+        let sbml = r#"
+            <sbml level="3" version="2">
+              <model>
+                <listOfCompartments>
+                  <compartment id="C" size="2" constant="false"/>
+                </listOfCompartments>
+                <listOfSpecies>
+                  <species id="S" compartment="C" initialAmount="4" hasOnlySubstanceUnits="false"/>
+                </listOfSpecies>
+                <listOfParameters>
+                  <parameter id="k" value="0.5"/>
+                </listOfParameters>
+                <listOfRules>
+                  <rateRule variable="S">
+                    <math><apply><times/><ci> k </ci><ci> S </ci></apply></math>
+                  </rateRule>
+                  <rateRule variable="C">
+                    <math><apply><divide/><ci> C </ci><cn> 10 </cn></apply></math>
+                  </rateRule>
+                </listOfRules>
+              </model>
+            </sbml>"#;
+        let model = parse_and_transform_str(sbml).unwrap();
+        let rate_rule = &model.rate_rules()[0];
+        let math_tag = rate_rule.math_tag(&model).unwrap();
+
+        let mathml = math_tag.to_mathml();
+        assert!(mathml.starts_with("<math xmlns=\"http://www.w3.org/1998/Math/MathML\">"));
+        assert!(mathml.contains("<plus/>"));
+
+        // the transformed rule C * (k * S / C) + S / C * (C / 10) reads back unchanged
+        let values: HashMap<String, f64> = vec![
+            ("k".to_string(), 0.5),
+            ("S".to_string(), 4.0),
+            ("C".to_string(), 2.0),
+        ]
+        .into_iter()
+        .collect();
+        let expected = 2.0 * (0.5 * 4.0 / 2.0) + 4.0 / 2.0 * (2.0 / 10.0);
+        let reread = parse_str(&model.to_sbml_string().unwrap()).unwrap();
+        for (model, math_tag) in [
+            (&model, math_tag.clone()),
+            (&reread, reread.rate_rules()[0].math_tag(&reread).unwrap()),
+        ] {
+            let value = math_tag.evaluate(&values, &model.function_definition_math());
+            assert!((value.unwrap() - expected).abs() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn math_to_infix() {
+        let sbml = r#"
+            <sbml level="3" version="2">
+              <model>
+                <listOfFunctionDefinitions>
+                  <functionDefinition id="f">
+                    <math><lambda><bvar><ci>x</ci></bvar><bvar><ci>y</ci></bvar>
+                      <apply><power/><apply><minus/><ci>x</ci></apply><apply><minus/><ci>y</ci></apply></apply>
+                    </lambda></math>
+                  </functionDefinition>
+                </listOfFunctionDefinitions>
+                <listOfRules>
+                  <assignmentRule variable="a">
+                    <math><apply><minus/><ci>b</ci><apply><minus/><ci>c</ci><ci>d</ci></apply></apply></math>
+                  </assignmentRule>
+                  <assignmentRule variable="b">
+                    <math><apply><times/><apply><plus/><ci>c</ci><cn type="integer">1</cn></apply><apply><minus/><apply><power/><ci>d</ci><cn>2.5</cn></apply></apply></apply></math>
+                  </assignmentRule>
+                  <assignmentRule variable="c">
+                    <math><piecewise>
+                      <piece><apply><root/><degree><cn type="integer">3</cn></degree><ci>d</ci></apply><apply><or/><apply><not/><apply><and/><true/><apply><gt/><csymbol encoding="text" definitionURL="http://www.sbml.org/sbml/symbols/time">t</csymbol><cn type="integer">2</cn></apply></apply></apply><false/></apply></piece>
+                      <otherwise><apply><ci>f</ci><ci>d</ci><cn type="e-notation">1<sep/>-3</cn></apply></otherwise>
+                    </piecewise></math>
+                  </assignmentRule>
+                </listOfRules>
+                <listOfReactions>
+                  <reaction id="R1">
+                    <kineticLaw>
+                      <math><apply><divide/><apply><times/><ci>k1</ci><ci>S1</ci></apply><ci>compartment</ci></apply></math>
+                    </kineticLaw>
+                  </reaction>
+                </listOfReactions>
+              </model>
+            </sbml>"#;
+        let model = parse_str(sbml).unwrap();
+        let kinetic_law = model.reactions()[0].kinetic_law(&model).unwrap();
+        assert_eq!(kinetic_law.to_infix(), "k1 * S1 / compartment");
+
+        let function_definition = &model.function_definitions()[0];
+        assert_eq!(
+            function_definition.math_tag(&model).unwrap().to_infix(),
+            "lambda(x, y, (-x)^(-y))"
+        );
+
+        let rules: Vec<String> = model
+            .assignment_rules()
+            .iter()
+            .map(|rule| rule.math_tag(&model).unwrap().to_infix())
+            .collect();
+        assert_eq!(rules[0], "b - (c - d)");
+        assert_eq!(rules[1], "(c + 1) * -d^2.5");
+        assert_eq!(
+            rules[2],
+            "piecewise(root(3, d), !(true && time > 2) || false, f(d, 1e-3))"
+        );
+    }
+
+    #[test]
+    fn math_from_formula() {
+        let math_tag = MathTag::from_formula("Vmax*S/(Km+S)").unwrap();
+        assert_eq!(math_tag.to_infix(), "Vmax * S / (Km + S)");
+        let values: HashMap<String, f64> = vec![
+            ("Vmax".to_string(), 3.0),
+            ("S".to_string(), 2.0),
+            ("Km".to_string(), 1.0),
+        ]
+        .into_iter()
+        .collect();
+        let rate = math_tag.evaluate(&values, &HashMap::new()).unwrap();
+        assert!((rate - 2.0).abs() < 1e-12);
+
+        // rendering and parsing again gives the same formula
+        for formula in &[
+            "-x^2 + (-x)^2 - x^(-2)",
+            "a - (b - c) / (d * e)",
+            "!(a > 1 && b <= 2) || c != d",
+            "piecewise(1, time < 10, exponentiale^2)",
+            "lambda(x, y, x * y)",
+            "log(2, x) + log10(x) + ln(x) + sqrt(x) + root(3, x)",
+            "delay(S, 2) + rateOf(S) + avogadro * f(S, INF)",
+            "2 mole * k",
+        ] {
+            let infix = MathTag::from_formula(formula).unwrap().to_infix();
+            assert_eq!(&infix, formula);
+        }
+
+        // log is the base 10 logarithm in Level 3
+        let math_tag = MathTag::from_formula("log(100)").unwrap();
+        assert_eq!(math_tag.to_infix(), "log10(100)");
+        let math_tag = MathTag::from_formula("2^3 + 1e-3").unwrap();
+        let value = math_tag.evaluate(&HashMap::new(), &HashMap::new()).unwrap();
+        assert!((value - 8.001).abs() < 1e-12);
+
+        // units are kept as sbml:units when written and read back
+        let math_tag = MathTag::from_formula("k * 2 mole").unwrap();
+        let mathml = math_tag.to_mathml();
+        assert!(mathml.contains(r#"<cn type="integer" sbml:units="mole">"#));
+        let sbml = format!(
+            "<sbml level=\"3\" version=\"2\"><model><listOfRules><assignmentRule variable=\"x\">{}</assignmentRule></listOfRules></model></sbml>",
+            mathml
+        );
+        let model = parse_str(&sbml).unwrap();
+        let math_tag = model.assignment_rules()[0].math_tag(&model).unwrap();
+        assert_eq!(math_tag.to_infix(), "k * 2 mole");
+
+        for formula in &["a +", "f(a b)", "lambda(1, x)", "sin(a, b)", "a %"] {
+            assert!(MathTag::from_formula(formula).is_err(), "{}", formula);
+        }
+
+        // % binds like * and is written out with piecewise
+        let math_tag = MathTag::from_formula("2 * a % b").unwrap();
+        assert_eq!(
+            math_tag.to_infix(),
+            "piecewise(2 * a - b * ceil(2 * a / b), xor(2 * a < 0, b < 0), 2 * a - b * floor(2 * a / b))"
+        );
+    }
+
+    #[test]
+    fn build_and_splice_math() {
+        let rate = Expr::ci("Vmax")
+            .times(Expr::ci("S"))
+            .div(Expr::ci("Km").plus(Expr::ci("S")));
+        let mut math_tag = rate.to_math_tag();
+        assert_eq!(math_tag.to_infix(), "Vmax * S / (Km + S)");
+        assert_eq!(
+            (Expr::ci("a") + Expr::ci("b") + Expr::real(1.5) * -Expr::ci("c")).to_string(),
+            "a + b + 1.5 * -c"
+        );
+
+        // the parent pointers agree with the child lists
+        for (idx, node) in math_tag.nodes.iter().enumerate() {
+            if let MathNode::Apply(apply) = node {
+                for child in &apply.children {
+                    assert_eq!(parent_of(&math_tag.nodes[*child]), Some(idx));
+                }
+            }
+        }
+
+        // replace Km + S with Km + S^2
+        let denominator = math_tag
+            .nodes
+            .iter()
+            .position(|node| match node {
+                MathNode::Apply(apply) => matches!(
+                    &math_tag.nodes[apply.operator.unwrap()],
+                    MathNode::Op(op) if matches!(op.op, Some(mathml_rs::Op::Plus))
+                ),
+                _ => false,
+            })
+            .unwrap();
+        let squared = Expr::ci("Km").plus(Expr::ci("S").pow(Expr::integer(2)));
+        math_tag.splice(denominator, &squared).unwrap();
+        assert_eq!(math_tag.to_infix(), "Vmax * S / (Km + S^2)");
+        assert_eq!(
+            math_tag.expr_at(0).unwrap().to_string(),
+            math_tag.to_infix()
+        );
+
+        // identifiers bound by a lambda are not replaced
+        let function = MathTag::from_formula("lambda(S, S * C)").unwrap();
+        let replaced = function
+            .to_expr()
+            .unwrap()
+            .replace_ci(&|name: &str| Some(Expr::ci(name).div(Expr::ci("V"))));
+        assert_eq!(replaced.to_string(), "lambda(S, S * (C / V))");
+    }
+
+    fn parent_of(node: &MathNode) -> Option<usize> {
+        match node {
+            MathNode::Apply(apply) => apply.parent,
+            MathNode::Op(op) => op.parent,
+            MathNode::Ci(ci) => ci.parent,
+            MathNode::Cn(cn) => cn.parent,
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn validate_model() {
+        let sbml = r#"<?xml version="1.0" encoding="UTF-8"?>
+            <sbml xmlns="http://www.sbml.org/sbml/level3/version2/core" level="3" version="2">
+              <model id="m">
+                <listOfCompartments>
+                  <compartment id="cell" size="1" constant="true"/>
+                </listOfCompartments>
+                <listOfSpecies>
+                  <species id="S1" compartment="nucleus" hasOnlySubstanceUnits="false" boundaryCondition="false" constant="false"/>
+                  <species id="S2" compartment="cell" hasOnlySubstanceUnits="false" boundaryCondition="false"/>
+                </listOfSpecies>
+                <listOfParameters>
+                  <parameter id="k" value="0.1" constant="true"/>
+                  <parameter id="S1" value="1" constant="false"/>
+                </listOfParameters>
+                <listOfRules>
+                  <assignmentRule variable="k">
+                    <math xmlns="http://www.w3.org/1998/Math/MathML"><cn> 1 </cn></math>
+                  </assignmentRule>
+                </listOfRules>
+                <listOfReactions>
+                  <reaction id="R1" reversible="false">
+                    <listOfReactants>
+                      <speciesReference species="S3" constant="true"/>
+                    </listOfReactants>
+                  </reaction>
+                </listOfReactions>
+              </model>
+            </sbml>"#;
+        let model = parse_str(sbml).unwrap();
+        let diagnostics = model.validate();
+        let found = diagnostics
+            .iter()
+            .map(|d| (d.rule.unwrap(), d.location.path.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            found,
+            vec![
+                ("sbml-10301", "/sbml/model/listOfParameters/parameter[@id='S1']"),
+                ("sbml-20623", "/sbml/model/listOfSpecies/species[@id='S2']"),
+                ("sbml-20601", "/sbml/model/listOfSpecies/species[@id='S1']"),
+                (
+                    "sbml-21111",
+                    "/sbml/model/listOfReactions/reaction[@id='R1']/listOfReactants/speciesReference"
+                ),
+                ("sbml-20903", "/sbml/model/listOfRules/assignmentRule"),
+            ]
+        );
+        assert!(diagnostics.iter().all(|d| d.severity == Severity::Error));
+        assert_eq!(
+            diagnostics[1].to_string(),
+            "error [sbml-20623]: Missing required attribute 'constant' at /sbml/model/listOfSpecies/species[@id='S2']"
+        );
+        // a reaction without a kinetic law is left out instead of panicking
+        assert!(model.all_kinetic_laws().is_empty());
+        assert_eq!(
+            model.species()[0].compartment_size(&model),
+            Err("Compartment 'nucleus' does not exist".to_string())
+        );
+    }
+
+    #[test]
+    fn unit_algebra() {
+        let sbml = r#"<?xml version="1.0" encoding="UTF-8"?>
+            <sbml xmlns="http://www.sbml.org/sbml/level3/version2/core" level="3" version="2">
+              <model id="m" substanceUnits="mmol" volumeUnits="ul" timeUnits="minute">
+                <listOfUnitDefinitions>
+                  <unitDefinition id="mmol">
+                    <listOfUnits>
+                      <unit kind="mole" exponent="1" scale="-3" multiplier="1"/>
+                    </listOfUnits>
+                  </unitDefinition>
+                  <unitDefinition id="ul">
+                    <listOfUnits>
+                      <unit kind="litre" exponent="1" scale="-6" multiplier="1"/>
+                    </listOfUnits>
+                  </unitDefinition>
+                  <unitDefinition id="minute">
+                    <listOfUnits>
+                      <unit kind="second" exponent="1" scale="0" multiplier="60"/>
+                    </listOfUnits>
+                  </unitDefinition>
+                  <unitDefinition id="per_mm_ul_min">
+                    <listOfUnits>
+                      <unit kind="mole" exponent="-1" scale="-3" multiplier="1"/>
+                      <unit kind="litre" exponent="1" scale="-6" multiplier="1"/>
+                      <unit kind="second" exponent="-1" scale="0" multiplier="60"/>
+                    </listOfUnits>
+                  </unitDefinition>
+                </listOfUnitDefinitions>
+                <listOfCompartments>
+                  <compartment id="cell" size="1" spatialDimensions="3" constant="true"/>
+                </listOfCompartments>
+                <listOfSpecies>
+                  <species id="S" compartment="cell" initialAmount="1" hasOnlySubstanceUnits="false" boundaryCondition="false" constant="false"/>
+                  <species id="A" compartment="cell" initialAmount="1" hasOnlySubstanceUnits="true" boundaryCondition="false" constant="false"/>
+                </listOfSpecies>
+                <listOfParameters>
+                  <parameter id="k" value="1" units="per_mm_ul_min" constant="true"/>
+                  <parameter id="x" value="1" constant="true"/>
+                </listOfParameters>
+              </model>
+            </sbml>"#;
+        let model = parse_str(sbml).unwrap();
+
+        let mole = UnitSId::Mole.derived();
+        let litre = UnitSId::Litre.derived();
+        let second = UnitSId::Second.derived();
+        assert!(litre.is_equivalent(&UnitSId::Metre.derived().scaled(0.1).pow(3.0)));
+        assert!(!litre.is_equivalent(&UnitSId::Metre.derived().pow(3.0)));
+
+        // a millimole per microlitre is a thousand moles per litre
+        let concentration = model.units_of("S").unwrap();
+        assert!(concentration.is_equivalent(&(mole.clone() / litre.clone()).scaled(1000.0)));
+        assert_eq!(concentration.to_string(), "1000000 mole metre^-3");
+        assert!(model
+            .units_of("A")
+            .unwrap()
+            .is_equivalent(&mole.clone().scaled(1e-3)));
+        assert!(model
+            .units_of("cell")
+            .unwrap()
+            .is_equivalent(&litre.clone().scaled(1e-6)));
+
+        // k * S is per minute
+        let rate = model.units_of("k").unwrap() * concentration;
+        assert!(rate.same_dimensions(&second.pow(-1.0)));
+        let per_second = rate.conversion_factor_to(&second.pow(-1.0)).unwrap();
+        assert!((per_second - 1.0 / 60.0).abs() < 1e-12);
+        assert!(rate.conversion_factor_to(&mole).is_none());
+
+        assert_eq!(model.units_of("x"), None);
+        assert_eq!(model.units_of("missing"), None);
+        assert_eq!(DerivedUnit::dimensionless().to_string(), "dimensionless");
+        assert_eq!(UnitSId::from_name("liter"), Some(UnitSId::Litre));
+    }
+
+    #[test]
+    fn check_units() {
+        let sbml = r#"<?xml version="1.0" encoding="UTF-8"?>
+            <sbml xmlns="http://www.sbml.org/sbml/level3/version2/core" level="3" version="2">
+              <model id="m" substanceUnits="mole" volumeUnits="litre" timeUnits="second" extentUnits="mole">
+                <listOfUnitDefinitions>
+                  <unitDefinition id="per_second">
+                    <listOfUnits>
+                      <unit kind="second" exponent="-1" scale="0" multiplier="1"/>
+                    </listOfUnits>
+                  </unitDefinition>
+                </listOfUnitDefinitions>
+                <listOfCompartments>
+                  <compartment id="cell" size="1" spatialDimensions="3" constant="true"/>
+                </listOfCompartments>
+                <listOfSpecies>
+                  <species id="S" compartment="cell" initialConcentration="1" hasOnlySubstanceUnits="false" boundaryCondition="false" constant="false"/>
+                  <species id="A" compartment="cell" initialAmount="1" hasOnlySubstanceUnits="true" boundaryCondition="false" constant="false"/>
+                </listOfSpecies>
+                <listOfParameters>
+                  <parameter id="total" constant="false" units="mole"/>
+                </listOfParameters>
+                <listOfRules>
+                  <assignmentRule variable="total">
+                    <math xmlns="http://www.w3.org/1998/Math/MathML">
+                      <apply><plus/><ci> S </ci><ci> A </ci></apply>
+                    </math>
+                  </assignmentRule>
+                </listOfRules>
+                <listOfReactions>
+                  <reaction id="R1" reversible="false">
+                    <listOfReactants>
+                      <speciesReference species="S" stoichiometry="1" constant="true"/>
+                    </listOfReactants>
+                    <kineticLaw>
+                      <math xmlns="http://www.w3.org/1998/Math/MathML">
+                        <apply><times/><ci> k </ci><ci> S </ci><ci> cell </ci></apply>
+                      </math>
+                      <listOfLocalParameters>
+                        <localParameter id="k" value="0.1" units="per_second"/>
+                      </listOfLocalParameters>
+                    </kineticLaw>
+                  </reaction>
+                  <reaction id="R2" reversible="false">
+                    <listOfReactants>
+                      <speciesReference species="A" stoichiometry="1" constant="true"/>
+                    </listOfReactants>
+                    <kineticLaw>
+                      <math xmlns="http://www.w3.org/1998/Math/MathML">
+                        <apply><times/><ci> k </ci><ci> S </ci></apply>
+                      </math>
+                      <listOfLocalParameters>
+                        <localParameter id="k" value="0.1" units="per_second"/>
+                      </listOfLocalParameters>
+                    </kineticLaw>
+                  </reaction>
+                </listOfReactions>
+              </model>
+            </sbml>"#;
+        let model = parse_str(sbml).unwrap();
+
+        // k * S * cell is in mole per second
+        let reaction = &model.reactions()[0];
+        let inference = reaction.kinetic_law(&model).unwrap().infer_units(&model);
+        let mole_per_second = UnitSId::Mole.derived() / UnitSId::Second.derived();
+        assert!(inference.units.unwrap().is_equivalent(&mole_per_second));
+        assert!(inference.mismatches.is_empty());
+
+        // a number with units takes part, one without does not
+        let math = MathTag::from_formula("2 second + 3").unwrap();
+        let seconds = math.infer_units(&model).units.unwrap();
+        assert!(seconds.is_equivalent(&UnitSId::Second.derived()));
+
+        // a unary plus keeps the units of its operand
+        let math = Expr::Apply(mathml_rs::Op::Plus, vec![Expr::ci("A")]).to_math_tag();
+        let inference = math.infer_units(&model);
+        let moles = inference.units.unwrap();
+        assert!(moles.is_equivalent(&UnitSId::Mole.derived()));
+        assert!(inference.mismatches.is_empty());
+
+        let diagnostics = model.check_units();
+        let found = diagnostics
+            .iter()
+            .map(|d| (d.rule.unwrap(), d.location.path.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            found,
+            vec![
+                ("sbml-10501", "/sbml/model/listOfRules/assignmentRule"),
+                // the sum takes the units of its first term
+                ("sbml-10513", "/sbml/model/listOfRules/assignmentRule"),
+                (
+                    "sbml-10541",
+                    "/sbml/model/listOfReactions/reaction[@id='R2']/kineticLaw"
+                ),
+            ]
+        );
+        assert!(diagnostics.iter().all(|d| d.severity == Severity::Warning));
+        assert_eq!(
+            diagnostics[0].message,
+            "The operands of 'S + A' have different units: 1000 mole metre^-3 and mole"
+        );
+        assert_eq!(
+            diagnostics[2].message,
+            "The units of the kinetic law should be mole second^-1, but are 1000 mole metre^-3 second^-1"
+        );
+    }
+
+    #[test]
+    fn convert_model_units() {
+        let sbml = r#"<?xml version="1.0" encoding="UTF-8"?>
+            <sbml xmlns="http://www.sbml.org/sbml/level3/version2/core" level="3" version="2">
+              <model id="m" substanceUnits="mmol" volumeUnits="ul" timeUnits="minute" extentUnits="mmol">
+                <listOfUnitDefinitions>
+                  <unitDefinition id="mmol">
+                    <listOfUnits>
+                      <unit kind="mole" exponent="1" scale="-3" multiplier="1"/>
+                    </listOfUnits>
+                  </unitDefinition>
+                  <unitDefinition id="ul">
+                    <listOfUnits>
+                      <unit kind="litre" exponent="1" scale="-6" multiplier="1"/>
+                    </listOfUnits>
+                  </unitDefinition>
+                  <unitDefinition id="minute">
+                    <listOfUnits>
+                      <unit kind="second" exponent="1" scale="0" multiplier="60"/>
+                    </listOfUnits>
+                  </unitDefinition>
+                  <unitDefinition id="per_minute">
+                    <listOfUnits>
+                      <unit kind="second" exponent="-1" scale="0" multiplier="60"/>
+                    </listOfUnits>
+                  </unitDefinition>
+                </listOfUnitDefinitions>
+                <listOfCompartments>
+                  <compartment id="cell" size="5" spatialDimensions="3" constant="true"/>
+                </listOfCompartments>
+                <listOfSpecies>
+                  <species id="A" compartment="cell" initialAmount="3" hasOnlySubstanceUnits="true" boundaryCondition="false" constant="false"/>
+                  <species id="S" compartment="cell" initialConcentration="2" hasOnlySubstanceUnits="false" boundaryCondition="false" constant="false"/>
+                </listOfSpecies>
+                <listOfParameters>
+                  <parameter id="k" value="6" units="per_minute" constant="true"/>
+                </listOfParameters>
+                <listOfReactions>
+                  <reaction id="R1" reversible="false">
+                    <listOfReactants>
+                      <speciesReference species="A" stoichiometry="1" constant="true"/>
+                    </listOfReactants>
+                    <kineticLaw>
+                      <math xmlns="http://www.w3.org/1998/Math/MathML">
+                        <apply><times/><ci> k </ci><ci> A </ci></apply>
+                      </math>
+                    </kineticLaw>
+                  </reaction>
+                </listOfReactions>
+              </model>
+            </sbml>"#;
+        let model = parse_str(sbml).unwrap();
+        let model = convert_units(model, &UnitSystem::default()).unwrap();
+
+        assert_eq!(model.substance_units.as_deref(), Some("mole"));
+        assert_eq!(model.extent_units.as_deref(), Some("mole"));
+        assert_eq!(model.volume_units.as_deref(), Some("litre"));
+        assert_eq!(model.time_units.as_deref(), Some("second"));
+
+        let close = |a: f64, b: f64| (a - b).abs() <= 1e-9 * b.abs();
+        assert!(close(model.compartments()[0].size.unwrap(), 5e-6));
+        let species = model.species();
+        assert!(close(species[0].initial_amount.unwrap(), 3e-3));
+        assert!(close(species[1].initial_concentration.unwrap(), 2e3));
+        let k = &model.parameters()[0];
+        assert!(close(k.value.unwrap(), 0.1));
+        assert_eq!(k.units.as_deref(), Some("per_second"));
+        let per_second = UnitSId::Second.derived().pow(-1.0);
+        assert!(model.units_of("k").unwrap().is_equivalent(&per_second));
+
+        // 18 mmol/min before, 3e-4 mol/s now
+        let kinetic_law = model.reactions()[0].kinetic_law(&model).unwrap();
+        let mut values = HashMap::new();
+        values.insert("k".to_string(), k.value.unwrap());
+        values.insert("A".to_string(), species[0].initial_amount.unwrap());
+        let rate =
+            mathml_rs::evaluate_node(&kinetic_law.nodes, 0, &values, &HashMap::new()).unwrap();
+        assert!(close(rate, 3e-4));
+        assert!(model.check_units().is_empty());
+
+        let invalid = convert_units(model, &UnitSystem::new("second", "litre", "second"));
+        assert!(matches!(
+            invalid,
+            Err(SbmlError::InvalidAttributeValue { ref attribute, .. }) if attribute == "substanceUnits"
+        ));
+
+        // without extentUnits the rate is in the substance units
+        let model = parse_str(&sbml.replace(r#" extentUnits="mmol""#, "")).unwrap();
+        let model = convert_units(model, &UnitSystem::default()).unwrap();
+        assert_eq!(model.extent_units.as_deref(), Some("mole"));
+        let kinetic_law = model.reactions()[0].kinetic_law(&model).unwrap();
+        let rate =
+            mathml_rs::evaluate_node(&kinetic_law.nodes, 0, &values, &HashMap::new()).unwrap();
+        assert!(close(rate, 3e-4));
+        assert!(model.check_units().is_empty());
+
+        // a concentration per spatialSizeUnits is not one per compartment size
+        let litre = sbml.replace(
+            r#"initialConcentration="2""#,
+            r#"initialConcentration="2" spatialSizeUnits="litre""#,
+        );
+        let model = convert_units(parse_str(&litre).unwrap(), &UnitSystem::default()).unwrap();
+        let concentration = model.species()[1].initial_concentration.unwrap();
+        assert!(close(concentration, 2e-3));
+    }
+
+    #[test]
+    fn conversion_factors() {
+        let sbml = r#"<?xml version="1.0" encoding="UTF-8"?>
+            <sbml xmlns="http://www.sbml.org/sbml/level3/version2/core" level="3" version="2">
+              <model id="m" conversionFactor="cf_model">
+                <listOfCompartments>
+                  <compartment id="cell" size="1" constant="true"/>
+                </listOfCompartments>
+                <listOfSpecies>
+                  <species id="A" compartment="cell" initialAmount="10" hasOnlySubstanceUnits="true" boundaryCondition="false" constant="false" conversionFactor="cf_A"/>
+                  <species id="B" compartment="cell" initialAmount="0" hasOnlySubstanceUnits="true" boundaryCondition="false" constant="false"/>
+                </listOfSpecies>
+                <listOfParameters>
+                  <parameter id="cf_A" value="2" constant="true"/>
+                  <parameter id="cf_model" value="0.5" constant="true"/>
+                </listOfParameters>
+                <listOfReactions>
+                  <reaction id="R1" reversible="false">
+                    <listOfReactants>
+                      <speciesReference species="A" stoichiometry="1" constant="true"/>
+                    </listOfReactants>
+                    <listOfProducts>
+                      <speciesReference species="B" stoichiometry="3" constant="true"/>
+                    </listOfProducts>
+                  </reaction>
+                </listOfReactions>
+              </model>
+            </sbml>"#;
+        let model = parse_str(sbml).unwrap();
+        assert_eq!(model.conversion_factor.as_deref(), Some("cf_model"));
+        let species = model.species();
+        assert_eq!(species[0].conversion_factor.as_deref(), Some("cf_A"));
+        assert_eq!(
+            species[0].effective_conversion_factor(&model).as_deref(),
+            Some("cf_A")
+        );
+        assert_eq!(
+            species[1].effective_conversion_factor(&model).as_deref(),
+            Some("cf_model")
+        );
+        assert!(model.validate().is_empty());
+        assert!(model
+            .to_sbml_string()
+            .unwrap()
+            .contains(r#"conversionFactor="cf_A""#));
+
+        let model = transform(model).unwrap();
+        assert!(model.conversion_factor.is_none());
+        assert_eq!(model.all_reactants()["R1"][0].stoichiometry, Some(2.0));
+        assert_eq!(model.all_products()["R1"][0].stoichiometry, Some(1.5));
+
+        let invalid = sbml.replace(
+            r#"id="cf_A" value="2" constant="true""#,
+            r#"id="cf_A" value="2" constant="false""#,
+        );
+        let model = parse_str(&invalid).unwrap();
+        let diagnostics = model.validate();
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].rule, Some("sbml-20617"));
+        assert!(matches!(
+            transform(model),
+            Err(SbmlError::InvalidAttributeValue { ref value, .. }) if value == "cf_A"
+        ));
+
+        // a stoichiometry that math sets or uses cannot be scaled
+        let sbml = sbml.replace(
+            r#"<speciesReference species="A""#,
+            r#"<speciesReference id="A_ref" species="A""#,
+        );
+        let initial_assignment = |symbol: &str, math: &str| {
+            sbml.replace(
+                "</listOfParameters>",
+                &format!(
+                    r#"</listOfParameters>
+                <listOfInitialAssignments>
+                  <initialAssignment symbol="{}">
+                    <math xmlns="http://www.w3.org/1998/Math/MathML">{}</math>
+                  </initialAssignment>
+                </listOfInitialAssignments>"#,
+                    symbol, math
+                ),
+            )
+        };
+        let kinetic_law = sbml.replace(
+            "</listOfProducts>",
+            r#"</listOfProducts>
+                    <kineticLaw>
+                      <math xmlns="http://www.w3.org/1998/Math/MathML"><ci> A_ref </ci></math>
+                    </kineticLaw>"#,
+        );
+        assert!(transform(parse_str(&sbml).unwrap()).is_ok());
+        for (invalid, expected) in &[
+            (initial_assignment("A_ref", "<cn> 4 </cn>"), "A"),
+            (kinetic_law, "A"),
+            // the initial value of A is not known before the simulation
+            (initial_assignment("cf_A", "<ci> A </ci>"), "cf_A"),
+        ] {
+            assert!(matches!(
+                transform(parse_str(invalid).unwrap()),
+                Err(SbmlError::InvalidAttributeValue { ref value, .. }) if value == expected
+            ));
+        }
+
+        // the factor takes the value of its initial assignment
+        let assigned = initial_assignment(
+            "cf_A",
+            "<apply><times/><cn> 2 </cn><ci> cf_A_base </ci></apply>",
+        )
+        .replace(
+            "</listOfParameters>",
+            r#"  <parameter id="cf_A_base" value="3" constant="true"/>
+                </listOfParameters>"#,
+        );
+        let model = transform(parse_str(&assigned).unwrap()).unwrap();
+        assert_eq!(model.all_reactants()["R1"][0].stoichiometry, Some(6.0));
+
+        // there is no stoichiometry to scale
+        let unset = sbml.replace(r#"species="B" stoichiometry="3""#, r#"species="B""#);
+        assert!(matches!(
+            transform(parse_str(&unset).unwrap()),
+            Err(SbmlError::MissingRequiredAttribute { ref attribute, ref location })
+                if attribute == "stoichiometry" && location.path.ends_with("speciesReference")
+        ));
+    }
+
+    #[test]
+    fn lookup_by_sid() {
+        let sbml = r#"<?xml version="1.0" encoding="UTF-8"?>
+            <sbml xmlns="http://www.sbml.org/sbml/level3/version2/core" level="3" version="2">
+              <model id="m">
+                <listOfCompartments>
+                  <compartment id="cell" size="2" constant="true"/>
+                </listOfCompartments>
+                <listOfSpecies>
+                  <species id="A" compartment="cell" initialAmount="1" hasOnlySubstanceUnits="true" boundaryCondition="false" constant="false"/>
+                </listOfSpecies>
+                <listOfParameters>
+                  <parameter id="k" value="0.1" constant="true"/>
+                </listOfParameters>
+                <listOfReactions>
+                  <reaction id="R1" reversible="false">
+                    <listOfReactants>
+                      <speciesReference id="A_ref" species="A" stoichiometry="1" constant="true"/>
+                    </listOfReactants>
+                  </reaction>
+                </listOfReactions>
+              </model>
+            </sbml>"#;
+        let mut model = parse_str(sbml).unwrap();
+        assert_eq!(model.get_species("A").unwrap().initial_amount, Some(1.0));
+        assert_eq!(model.get_compartment("cell").unwrap().size, Some(2.0));
+        assert_eq!(model.get_parameter("k").unwrap().value, Some(0.1));
+        assert!(model.get_reaction("R1").is_some());
+        assert!(matches!(
+            model.get_by_sid("A_ref"),
+            Some(Tag::SpeciesReference(_))
+        ));
+        // typed lookups only find elements of their type
+        assert!(model.get_species("k").is_none());
+        assert!(model.get_by_sid("missing").is_none());
+        assert_eq!(model.species()[0].compartment_size(&model), Ok(2.0));
+
+        // elements renamed or added in place are found under their new ids
+        for tag in model.nodes_mut().iter_mut() {
+            if let Tag::Parameter(parameter) = tag {
+                parameter.id = Some("k1".to_string());
+            }
+        }
+        assert!(model.get_parameter("k").is_none());
+        assert_eq!(model.get_parameter("k1").unwrap().value, Some(0.1));
+        model.nodes_mut().push(Tag::Parameter(Parameter {
+            id: Some("k3".to_string()),
+            value: Some(0.3),
+            ..Default::default()
+        }));
+        assert_eq!(model.get_parameter("k3").unwrap().value, Some(0.3));
+
+        let idx = model.add_node(Tag::Parameter(Parameter {
+            id: Some("k2".to_string()),
+            value: Some(0.2),
+            ..Default::default()
+        }));
+        assert!(matches!(&model.nodes()[idx], Tag::Parameter(_)));
+        assert_eq!(model.get_parameter("k2").unwrap().value, Some(0.2));
+    }
+
+    #[test]
+    fn borrowing_accessors() {
+        let sbml = r#"<?xml version="1.0" encoding="UTF-8"?>
+            <sbml xmlns="http://www.sbml.org/sbml/level3/version2/core" level="3" version="2">
+              <model id="m">
+                <listOfCompartments>
+                  <compartment id="cell" size="1" constant="true"/>
+                </listOfCompartments>
+                <listOfSpecies>
+                  <species id="A" compartment="cell" initialAmount="1" hasOnlySubstanceUnits="true" boundaryCondition="false" constant="false"/>
+                  <species id="B" compartment="cell" initialAmount="0" hasOnlySubstanceUnits="true" boundaryCondition="false" constant="false"/>
+                </listOfSpecies>
+                <listOfReactions>
+                  <reaction id="R1" reversible="false">
+                    <listOfReactants>
+                      <speciesReference species="A" stoichiometry="1" constant="true"/>
+                    </listOfReactants>
+                    <listOfProducts>
+                      <speciesReference species="B" stoichiometry="2" constant="true"/>
+                    </listOfProducts>
+                    <kineticLaw>
+                      <math xmlns="http://www.w3.org/1998/Math/MathML">
+                        <apply><times/><ci> k </ci><ci> A </ci></apply>
+                      </math>
+                      <listOfLocalParameters>
+                        <localParameter id="k" value="0.5"/>
+                      </listOfLocalParameters>
+                    </kineticLaw>
+                  </reaction>
+                </listOfReactions>
+              </model>
+            </sbml>"#;
+        let model = parse_str(sbml).unwrap();
+        let ids = model
+            .species_iter()
+            .filter_map(|species| species.id.as_deref())
+            .collect::<Vec<&str>>();
+        assert_eq!(ids, ["A", "B"]);
+        assert_eq!(model.compartments_iter().count(), 1);
+        assert_eq!(model.parameters_iter().count(), 0);
+
+        let reaction = model.reactions_iter().next().unwrap();
+        let reactant = reaction.reactants_iter(&model).next().unwrap();
+        assert_eq!(reactant.species.as_deref(), Some("A"));
+        let product = reaction.products_iter(&model).next().unwrap();
+        assert_eq!(product.stoichiometry, Some(2.0));
+
+        // the borrowed kinetic law is the one stored in the model
+        let kinetic_law = reaction.kinetic_law_ref(&model).unwrap();
+        assert_eq!(kinetic_law.to_infix(), "k * A");
+
+        let kinetic_law_tag = match &model.nodes[reaction.kinetic_law.unwrap()] {
+            Tag::KineticLaw(kinetic_law) => kinetic_law,
+            _ => panic!("expected a kinetic law"),
+        };
+        let local = kinetic_law_tag
+            .local_parameters_iter(&model)
+            .next()
+            .unwrap();
+        assert_eq!(local.value, Some(0.5));
+        let stored = model.math_tag_at(kinetic_law_tag.math).unwrap();
+        assert!(std::ptr::eq(kinetic_law, stored));
+    }
+
+    #[test]
+    fn build_model() {
+        let model = ModelBuilder::new("m")
+            .compartment(Compartment {
+                id: Some("cell".to_string()),
+                size: Some(1.0),
+                constant: Some(true),
+                ..Default::default()
+            })
+            .species(Species {
+                id: Some("A".to_string()),
+                compartment: Some("cell".to_string()),
+                initial_amount: Some(10.0),
+                has_only_substance_units: Some(true),
+                boundary_condition: Some(false),
+                constant: Some(false),
+                ..Default::default()
+            })
+            .species(Species {
+                id: Some("B".to_string()),
+                compartment: Some("cell".to_string()),
+                initial_amount: Some(0.0),
+                has_only_substance_units: Some(true),
+                boundary_condition: Some(false),
+                constant: Some(false),
+                ..Default::default()
+            })
+            .parameter(Parameter {
+                id: Some("k".to_string()),
+                value: Some(0.1),
+                units: Some("per_minute".to_string()),
+                constant: Some(true),
+                ..Default::default()
+            })
+            .parameter(Parameter {
+                id: Some("total".to_string()),
+                constant: Some(false),
+                ..Default::default()
+            })
+            .parameter(Parameter {
+                id: Some("x".to_string()),
+                constant: Some(false),
+                ..Default::default()
+            })
+            .parameter(Parameter {
+                id: Some("y".to_string()),
+                constant: Some(false),
+                ..Default::default()
+            })
+            .unit_definition(
+                "per_minute",
+                vec![Unit {
+                    kind: Some("second".to_string()),
+                    exponent: Some(-1.0),
+                    scale: Some(0),
+                    multiplier: Some(60.0),
+                    ..Default::default()
+                }],
+            )
+            .function_definition("double", "lambda(a, 2 * a)")
+            .initial_assignment("x", "double(k)")
+            .assignment_rule("total", "A + B")
+            .rate_rule("x", "-k * x")
+            .algebraic_rule("y - 2 * x")
+            .event("E1", "time > 10", |e| {
+                e.delay("1").priority("2").assignment("A", "0")
+            })
+            .constraint("A >= 0")
+            .reaction("R1", |r| {
+                r.reactant("A", 1.0).product("B", 2.0).kinetic_law("k * A")
+            })
+            .reaction("R2", |r| {
+                r.reactant("B", 1.0)
+                    .modifier("A")
+                    .kinetic_law("k2 * B")
+                    .local_parameter("k2", 0.5)
+            })
+            .build()
+            .unwrap();
+
+        assert_eq!(model.id.as_deref(), Some("m"));
+        assert!(model.validate().is_empty());
+        assert_eq!(model.species().len(), 2);
+        assert_eq!(model.all_reactant_ids()["R1"], ["A"]);
+        assert_eq!(model.all_product_ids()["R1"], ["B"]);
+        assert_eq!(model.reactions()[1].modifiers(&model), ["A"]);
+        assert_eq!(model.local_parameter_values()["R2"]["k2"], 0.5);
+        let r1 = model.get_reaction("R1").unwrap();
+        assert_eq!(r1.kinetic_law_ref(&model).unwrap().to_infix(), "k * A");
+        let per_minute = UnitSId::Second.derived().pow(-1.0).scaled(1.0 / 60.0);
+        assert!(model.units_of("k").unwrap().is_equivalent(&per_minute));
+        let functions = model.function_definition_math();
+        assert!(functions.contains_key("double"));
+        let initial_assignment = &model.initial_assignments()[0];
+        assert_eq!(initial_assignment.symbol.as_deref(), Some("x"));
+        let infix = |math_tag: Option<MathTag>| math_tag.unwrap().to_infix();
+        assert_eq!(infix(initial_assignment.math_tag(&model)), "double(k)");
+        assert_eq!(infix(model.assignment_rules()[0].math_tag(&model)), "A + B");
+        assert_eq!(infix(model.rate_rules()[0].math_tag(&model)), "-k * x");
+        assert_eq!(
+            infix(model.algebraic_rules()[0].math_tag(&model)),
+            "y - 2 * x"
+        );
+        let event = &model.events()[0];
+        assert_eq!(infix(event.trigger_math(&model)), "time > 10");
+        assert_eq!(infix(event.delay_math(&model)), "1");
+        assert_eq!(infix(event.priority_math(&model)), "2");
+        assert_eq!(
+            event.event_assignments(&model)[0].variable.as_deref(),
+            Some("A")
+        );
+        assert_eq!(infix(model.constraints()[0].math_tag(&model)), "A >= 0");
+        // every element points back at the list or element containing it
+        for (idx, tag) in model.nodes.iter().enumerate().skip(1) {
+            let parent = tag.parent().unwrap();
+            assert!(parent < idx);
+        }
+
+        let written = model.to_sbml_string().unwrap();
+        let reread = parse_str(&written).unwrap();
+        assert_eq!(reread.to_sbml_string().unwrap(), written);
+
+        let invalid = ModelBuilder::new("m")
+            .reaction("R1", |r| r.kinetic_law("k *"))
+            .build();
+        assert!(matches!(invalid, Err(SbmlError::InvalidMath { .. })));
+        let invalid = ModelBuilder::new("m").assignment_rule("x", "(x").build();
+        assert!(matches!(
+            invalid,
+            Err(SbmlError::InvalidMath { ref location, .. })
+                if location.path == "/sbml/model/listOfRules/assignmentRule"
+        ));
+    }
 }
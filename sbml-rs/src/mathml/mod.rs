@@ -0,0 +1,405 @@
+use std::collections::HashMap;
+use std::io::BufRead;
+use std::str;
+
+use mathml_rs::{
+    Apply, BVar, Ci, Cn, Constant, ConstantNode, Csymbol, Lambda, MathNode, NodeIndex, NumType,
+    Number, Op, OpNode, Otherwise, Piece, Piecewise, Root,
+};
+use quick_xml::events::Event;
+use quick_xml::Reader;
+
+mod write;
+pub(crate) use write::write_math;
+pub use write::MATHML_NS;
+
+// Reads the content of a <math> element into a flat vector of MathNodes.
+// The reader must be positioned just after the opening <math> tag and is
+// left just after the matching closing tag. Unlike mathml_rs::parse_fragment,
+// this works with any BufRead source and not just files.
+pub fn parse_fragment<R: BufRead>(reader: &mut Reader<R>) -> Result<Vec<MathNode>, String> {
+    parse_math(reader).map(|(nodes, _)| nodes)
+}
+
+// Like parse_fragment, but also returns the sbml:units of <cn> elements
+// keyed by the index of their node
+pub fn parse_math<R: BufRead>(
+    reader: &mut Reader<R>,
+) -> Result<(Vec<MathNode>, HashMap<NodeIndex, String>), String> {
+    let mut buf = Vec::new();
+    let mut number_units = HashMap::new();
+    let mut nodes = vec![MathNode::Root(Root::default())];
+    let mut stack: Vec<NodeIndex> = vec![0];
+    // text pieces of <cn> elements, split by <sep/>
+    let mut cn_parts: HashMap<NodeIndex, Vec<String>> = HashMap::new();
+
+    loop {
+        match reader.read_event(&mut buf) {
+            Ok(Event::Start(ref e)) => {
+                let current = *stack.last().unwrap();
+                let name = e.local_name();
+
+                // <sep/> splits the text of a <cn> and creates no node
+                if name == b"sep" {
+                    if let Some(parts) = cn_parts.get_mut(&current) {
+                        parts.push(String::new());
+                    }
+                    stack.push(current);
+                    buf.clear();
+                    continue;
+                }
+                // <logbase> and <degree> are transparent, their child becomes an operand
+                if name == b"logbase" || name == b"degree" {
+                    stack.push(current);
+                    buf.clear();
+                    continue;
+                }
+
+                let mut attributes = HashMap::new();
+                for attribute in e.attributes() {
+                    let attribute = attribute.map_err(|e| e.to_string())?;
+                    let key = str::from_utf8(attribute.key)
+                        .map_err(|e| e.to_string())?
+                        .to_string();
+                    let value = attribute
+                        .unescape_and_decode_value(reader)
+                        .map_err(|e| e.to_string())?;
+                    attributes.insert(key, value);
+                }
+
+                let parent = Some(current);
+                let node = match name {
+                    b"apply" => MathNode::Apply(Apply {
+                        parent,
+                        ..Default::default()
+                    }),
+                    b"ci" => MathNode::Ci(Ci {
+                        parent,
+                        ..Default::default()
+                    }),
+                    b"cn" => {
+                        let r#type = match attributes.get("type").map(|s| s.as_str()) {
+                            None | Some("real") => NumType::Real,
+                            Some("integer") => NumType::Integer,
+                            Some("rational") => NumType::Rational,
+                            Some("e-notation") => NumType::ENotation,
+                            Some(other) => return Err(format!("Unsupported cn type '{}'", other)),
+                        };
+                        cn_parts.insert(nodes.len(), vec![String::new()]);
+                        // the prefix is whatever the document bound to the SBML namespace
+                        if let Some((_, units)) =
+                            attributes.iter().find(|(key, _)| key.ends_with(":units"))
+                        {
+                            number_units.insert(nodes.len(), units.clone());
+                        }
+                        MathNode::Cn(Cn {
+                            r#type: Some(r#type),
+                            parent,
+                            ..Default::default()
+                        })
+                    }
+                    b"csymbol" => MathNode::Csymbol(Csymbol {
+                        definition_url: attributes.get("definitionURL").cloned(),
+                        encoding: attributes.get("encoding").cloned(),
+                        parent,
+                        ..Default::default()
+                    }),
+                    b"lambda" => MathNode::Lambda(Lambda {
+                        parent,
+                        ..Default::default()
+                    }),
+                    b"bvar" => MathNode::Bvar(BVar {
+                        parent,
+                        ..Default::default()
+                    }),
+                    b"piecewise" => MathNode::Piecewise(Piecewise {
+                        parent,
+                        ..Default::default()
+                    }),
+                    b"piece" => MathNode::Piece(Piece {
+                        parent,
+                        ..Default::default()
+                    }),
+                    b"otherwise" => MathNode::Otherwise(Otherwise {
+                        parent,
+                        ..Default::default()
+                    }),
+                    _ => {
+                        if let Some(constant) = constant_from_name(name) {
+                            MathNode::Constant(ConstantNode {
+                                constant: Some(constant),
+                                parent,
+                            })
+                        } else if let Some(op) = op_from_name(name) {
+                            MathNode::Op(OpNode {
+                                op: Some(op),
+                                parent,
+                            })
+                        } else {
+                            return Err(format!(
+                                "Unsupported MathML element <{}>",
+                                str::from_utf8(name).unwrap_or("?")
+                            ));
+                        }
+                    }
+                };
+
+                let idx = nodes.len();
+                link_child(&mut nodes, current, idx, &node);
+                nodes.push(node);
+                stack.push(idx);
+            }
+            Ok(Event::End(ref e)) => {
+                if e.local_name() == b"math" {
+                    break;
+                }
+                let current = stack.pop().unwrap();
+                if e.local_name() == b"cn" {
+                    if let Some(parts) = cn_parts.remove(&current) {
+                        if let MathNode::Cn(cn) = &mut nodes[current] {
+                            cn.value = Some(parse_number(cn.r#type.as_ref(), &parts)?);
+                        }
+                    }
+                }
+            }
+            Ok(Event::Text(ref e)) => {
+                let text = e.unescape_and_decode(reader).map_err(|e| e.to_string())?;
+                let text = text.trim().to_string();
+                let current = *stack.last().unwrap();
+                match &mut nodes[current] {
+                    MathNode::Ci(ci) => ci.name = Some(text),
+                    MathNode::Cn(_) => {
+                        if let Some(parts) = cn_parts.get_mut(&current) {
+                            parts.last_mut().unwrap().push_str(&text);
+                        }
+                    }
+                    // the text of a csymbol is only a display name
+                    MathNode::Csymbol(_) => {}
+                    _ => return Err(format!("Unexpected text '{}' in MathML", text)),
+                }
+            }
+            Ok(Event::Eof) => return Err("Unexpected end of file inside <math>".to_string()),
+            Err(e) => {
+                return Err(format!(
+                    "Error at position {}: {:?}",
+                    reader.buffer_position(),
+                    e
+                ))
+            }
+            _ => {}
+        }
+        buf.clear();
+    }
+
+    Ok((nodes, number_units))
+}
+
+// Records child in the index fields of its parent
+pub(crate) fn link_child(
+    nodes: &mut [MathNode],
+    parent: NodeIndex,
+    child: NodeIndex,
+    node: &MathNode,
+) {
+    match &mut nodes[parent] {
+        MathNode::Root(root) => root.children.push(child),
+        MathNode::Apply(apply) => {
+            apply.children.push(child);
+            if apply.operator.is_none() {
+                apply.operator = Some(child);
+            } else {
+                apply.operands.push(child);
+            }
+        }
+        MathNode::Lambda(lambda) => {
+            if let MathNode::Bvar(_) = node {
+                lambda.bindings.push(child);
+            } else {
+                lambda.expr = Some(child);
+            }
+        }
+        MathNode::Bvar(bvar) => bvar.children.push(child),
+        MathNode::Piecewise(piecewise) => {
+            piecewise.children.push(child);
+            if let MathNode::Otherwise(_) = node {
+                piecewise.otherwise = Some(child);
+            } else {
+                piecewise.pieces.push(child);
+            }
+        }
+        MathNode::Piece(piece) => {
+            piece.children.push(child);
+            if piece.expr.is_none() {
+                piece.expr = Some(child);
+            } else {
+                piece.condition = Some(child);
+            }
+        }
+        MathNode::Otherwise(otherwise) => {
+            otherwise.children.push(child);
+            otherwise.expr = Some(child);
+        }
+        _ => {}
+    }
+}
+
+fn parse_number(r#type: Option<&NumType>, parts: &[String]) -> Result<Number, String> {
+    let invalid = || format!("Invalid number '{}' in <cn>", parts.join(" "));
+    let number = match (r#type, parts) {
+        (Some(NumType::Integer), [value]) => {
+            Number::Integer(value.parse::<i32>().map_err(|_| invalid())?)
+        }
+        (Some(NumType::ENotation), [mantissa, exponent]) => Number::ENotation(
+            mantissa.parse::<f64>().map_err(|_| invalid())?,
+            exponent.parse::<i64>().map_err(|_| invalid())?,
+        ),
+        (Some(NumType::Rational), [numerator, denominator]) => Number::Rational(
+            numerator.parse::<i64>().map_err(|_| invalid())?,
+            denominator.parse::<i64>().map_err(|_| invalid())?,
+        ),
+        (_, [value]) => Number::Real(parse_real(value).ok_or_else(invalid)?),
+        _ => return Err(invalid()),
+    };
+    Ok(number)
+}
+
+// MathML spells out the special values, which f64::from_str does not accept
+fn parse_real(value: &str) -> Option<f64> {
+    match value {
+        "INF" => Some(f64::INFINITY),
+        "-INF" => Some(f64::NEG_INFINITY),
+        "NaN" => Some(f64::NAN),
+        _ => value.parse::<f64>().ok(),
+    }
+}
+
+fn constant_from_name(name: &[u8]) -> Option<Constant> {
+    let constant = match name {
+        b"true" => Constant::True,
+        b"false" => Constant::False,
+        b"infinity" => Constant::Infinity,
+        b"notanumber" => Constant::NotANumber,
+        b"pi" => Constant::Pi,
+        b"exponentiale" => Constant::ExponentialE,
+        _ => return None,
+    };
+    Some(constant)
+}
+
+pub fn op_from_name(name: &[u8]) -> Option<Op> {
+    let op = match name {
+        b"plus" => Op::Plus,
+        b"minus" => Op::Minus,
+        b"times" => Op::Times,
+        b"divide" => Op::Divide,
+        b"power" => Op::Power,
+        b"root" => Op::Root,
+        b"factorial" => Op::Factorial,
+        b"ceiling" => Op::Ceiling,
+        b"floor" => Op::Floor,
+        b"abs" => Op::Abs,
+        b"exp" => Op::Exp,
+        b"ln" => Op::Ln,
+        b"log" => Op::Log,
+        b"eq" => Op::Eq,
+        b"neq" => Op::Neq,
+        b"gt" => Op::Gt,
+        b"lt" => Op::Lt,
+        b"geq" => Op::Geq,
+        b"leq" => Op::Leq,
+        b"and" => Op::And,
+        b"or" => Op::Or,
+        b"xor" => Op::Xor,
+        b"not" => Op::Not,
+        b"sin" => Op::Sin,
+        b"cos" => Op::Cos,
+        b"tan" => Op::Tan,
+        b"sec" => Op::Sec,
+        b"csc" => Op::Csc,
+        b"cot" => Op::Cot,
+        b"sinh" => Op::Sinh,
+        b"cosh" => Op::Cosh,
+        b"tanh" => Op::Tanh,
+        b"sech" => Op::Sech,
+        b"csch" => Op::Csch,
+        b"coth" => Op::Coth,
+        b"arcsin" => Op::Arcsin,
+        b"arccos" => Op::Arccos,
+        b"arctan" => Op::Arctan,
+        b"arcsec" => Op::Arcsec,
+        b"arccsc" => Op::Arccsc,
+        b"arccot" => Op::Arccot,
+        b"arcsinh" => Op::Arcsinh,
+        b"arccosh" => Op::Arccosh,
+        b"arctanh" => Op::Arctanh,
+        b"arcsech" => Op::Arcsech,
+        b"arccsch" => Op::Arccsch,
+        b"arccoth" => Op::Arccoth,
+        _ => return None,
+    };
+    Some(op)
+}
+
+// Name of the MathML element for an operator, the inverse of op_from_name
+pub fn op_name(op: &Op) -> &'static str {
+    match op {
+        Op::Plus => "plus",
+        Op::Minus => "minus",
+        Op::Times => "times",
+        Op::Divide => "divide",
+        Op::Power => "power",
+        Op::Root => "root",
+        Op::Factorial => "factorial",
+        Op::Ceiling => "ceiling",
+        Op::Floor => "floor",
+        Op::Abs => "abs",
+        Op::Exp => "exp",
+        Op::Ln => "ln",
+        Op::Log => "log",
+        Op::Eq => "eq",
+        Op::Neq => "neq",
+        Op::Gt => "gt",
+        Op::Lt => "lt",
+        Op::Geq => "geq",
+        Op::Leq => "leq",
+        Op::And => "and",
+        Op::Or => "or",
+        Op::Xor => "xor",
+        Op::Not => "not",
+        Op::Sin => "sin",
+        Op::Cos => "cos",
+        Op::Tan => "tan",
+        Op::Sec => "sec",
+        Op::Csc => "csc",
+        Op::Cot => "cot",
+        Op::Sinh => "sinh",
+        Op::Cosh => "cosh",
+        Op::Tanh => "tanh",
+        Op::Sech => "sech",
+        Op::Csch => "csch",
+        Op::Coth => "coth",
+        Op::Arcsin => "arcsin",
+        Op::Arccos => "arccos",
+        Op::Arctan => "arctan",
+        Op::Arcsec => "arcsec",
+        Op::Arccsc => "arccsc",
+        Op::Arccot => "arccot",
+        Op::Arcsinh => "arcsinh",
+        Op::Arccosh => "arccosh",
+        Op::Arctanh => "arctanh",
+        Op::Arcsech => "arcsech",
+        Op::Arccsch => "arccsch",
+        Op::Arccoth => "arccoth",
+    }
+}
+
+pub(crate) fn constant_name(constant: &Constant) -> &'static str {
+    match constant {
+        Constant::True => "true",
+        Constant::False => "false",
+        Constant::Infinity => "infinity",
+        Constant::NotANumber => "notanumber",
+        Constant::Pi => "pi",
+        Constant::ExponentialE => "exponentiale",
+    }
+}
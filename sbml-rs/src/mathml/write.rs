@@ -0,0 +1,209 @@
+use std::collections::HashMap;
+use std::io::Write;
+
+use mathml_rs::{MathNode, NodeIndex, NumType, Number, Op};
+use quick_xml::events::{BytesEnd, BytesStart, BytesText, Event};
+use quick_xml::{Result, Writer};
+
+use super::{constant_name, op_name};
+use crate::writer::{format_double, SBML_L3V2_NS};
+
+pub const MATHML_NS: &str = "http://www.w3.org/1998/Math/MathML";
+
+// Writes the nodes of a MathTag as a <math> element
+pub(crate) fn write_math<W: Write>(
+    writer: &mut Writer<W>,
+    nodes: &[MathNode],
+    number_units: &HashMap<NodeIndex, String>,
+) -> Result<()> {
+    let mut math = BytesStart::borrowed_name(b"math");
+    math.push_attribute(("xmlns", MATHML_NS));
+    if !number_units.is_empty() {
+        math.push_attribute(("xmlns:sbml", SBML_L3V2_NS));
+    }
+    writer.write_event(Event::Start(math))?;
+    if let Some(MathNode::Root(root)) = nodes.first() {
+        for child in &root.children {
+            write_node(writer, nodes, number_units, *child)?;
+        }
+    }
+    writer.write_event(Event::End(BytesEnd::borrowed(b"math")))
+}
+
+fn write_node<W: Write>(
+    writer: &mut Writer<W>,
+    nodes: &[MathNode],
+    number_units: &HashMap<NodeIndex, String>,
+    idx: NodeIndex,
+) -> Result<()> {
+    match &nodes[idx] {
+        MathNode::Apply(apply) => {
+            start(writer, "apply")?;
+            if let Some(operator) = apply.operator {
+                write_node(writer, nodes, number_units, operator)?;
+            }
+            // the parser reads <logbase> and <degree> as a first operand
+            let qualifier = match apply.operator.map(|op| &nodes[op]) {
+                Some(MathNode::Op(op)) if apply.operands.len() == 2 => match op.op {
+                    Some(Op::Log) => Some("logbase"),
+                    Some(Op::Root) => Some("degree"),
+                    _ => None,
+                },
+                _ => None,
+            };
+            for (i, operand) in apply.operands.iter().enumerate() {
+                match qualifier {
+                    Some(qualifier) if i == 0 => {
+                        start(writer, qualifier)?;
+                        write_node(writer, nodes, number_units, *operand)?;
+                        end(writer, qualifier)?;
+                    }
+                    _ => write_node(writer, nodes, number_units, *operand)?,
+                }
+            }
+            end(writer, "apply")
+        }
+        MathNode::Op(op) => match &op.op {
+            Some(op) => empty(writer, op_name(op)),
+            None => Ok(()),
+        },
+        MathNode::Ci(ci) => text_element(
+            writer,
+            BytesStart::borrowed_name(b"ci"),
+            &[ci.name.as_deref().unwrap_or_default()],
+        ),
+        MathNode::Cn(cn) => {
+            let mut element = BytesStart::borrowed_name(b"cn");
+            let r#type = match cn.r#type {
+                Some(NumType::Integer) => Some("integer"),
+                Some(NumType::Rational) => Some("rational"),
+                Some(NumType::ENotation) => Some("e-notation"),
+                _ => None,
+            };
+            if let Some(r#type) = r#type {
+                element.push_attribute(("type", r#type));
+            }
+            if let Some(units) = number_units.get(&idx) {
+                element.push_attribute(("sbml:units", units.as_str()));
+            }
+            let parts = match &cn.value {
+                Some(Number::Integer(value)) => vec![value.to_string()],
+                Some(Number::Real(value)) => vec![format_double(*value)],
+                Some(Number::Rational(numerator, denominator)) => {
+                    vec![numerator.to_string(), denominator.to_string()]
+                }
+                Some(Number::ENotation(mantissa, exponent)) => {
+                    vec![format_double(*mantissa), exponent.to_string()]
+                }
+                Some(Number::ComplexCartesian(a, b)) | Some(Number::ComplexPolar(a, b)) => {
+                    vec![format_double(*a), format_double(*b)]
+                }
+                Some(Number::Constant(value)) => vec![value.clone()],
+                None => Vec::new(),
+            };
+            let parts: Vec<&str> = parts.iter().map(String::as_str).collect();
+            text_element(writer, element, &parts)
+        }
+        MathNode::Csymbol(csymbol) => {
+            let mut element = BytesStart::borrowed_name(b"csymbol");
+            if let Some(encoding) = &csymbol.encoding {
+                element.push_attribute(("encoding", encoding.as_str()));
+            }
+            if let Some(url) = &csymbol.definition_url {
+                element.push_attribute(("definitionURL", url.as_str()));
+            }
+            // the display name is not kept, the last part of the URL stands in for it
+            let name = csymbol
+                .definition_url
+                .as_deref()
+                .and_then(|url| url.rsplit('/').next())
+                .unwrap_or_default();
+            text_element(writer, element, &[name])
+        }
+        MathNode::Constant(constant) => match &constant.constant {
+            Some(constant) => empty(writer, constant_name(constant)),
+            None => Ok(()),
+        },
+        MathNode::Lambda(lambda) => {
+            start(writer, "lambda")?;
+            for binding in &lambda.bindings {
+                write_node(writer, nodes, number_units, *binding)?;
+            }
+            if let Some(expr) = lambda.expr {
+                write_node(writer, nodes, number_units, expr)?;
+            }
+            end(writer, "lambda")
+        }
+        MathNode::Bvar(bvar) => write_children(writer, nodes, number_units, "bvar", &bvar.children),
+        MathNode::Piecewise(piecewise) => write_children(
+            writer,
+            nodes,
+            number_units,
+            "piecewise",
+            &piecewise.children,
+        ),
+        MathNode::Piece(piece) => {
+            write_children(writer, nodes, number_units, "piece", &piece.children)
+        }
+        MathNode::Otherwise(otherwise) => write_children(
+            writer,
+            nodes,
+            number_units,
+            "otherwise",
+            &otherwise.children,
+        ),
+        MathNode::Root(root) => {
+            for child in &root.children {
+                write_node(writer, nodes, number_units, *child)?;
+            }
+            Ok(())
+        }
+        MathNode::Text(_) => Ok(()),
+    }
+}
+
+fn write_children<W: Write>(
+    writer: &mut Writer<W>,
+    nodes: &[MathNode],
+    number_units: &HashMap<NodeIndex, String>,
+    name: &str,
+    children: &[NodeIndex],
+) -> Result<()> {
+    start(writer, name)?;
+    for child in children {
+        write_node(writer, nodes, number_units, *child)?;
+    }
+    end(writer, name)
+}
+
+// An element holding text, with <sep/> between the parts
+fn text_element<W: Write>(
+    writer: &mut Writer<W>,
+    element: BytesStart,
+    parts: &[&str],
+) -> Result<()> {
+    let name = element.name().to_vec();
+    writer.write_event(Event::Start(element))?;
+    for (i, part) in parts.iter().enumerate() {
+        if i > 0 {
+            writer.write_event(Event::Empty(BytesStart::borrowed_name(b"sep")))?;
+        }
+        writer.write_event(Event::Text(BytesText::from_plain_str(&format!(
+            " {} ",
+            part
+        ))))?;
+    }
+    writer.write_event(Event::End(BytesEnd::owned(name)))
+}
+
+fn start<W: Write>(writer: &mut Writer<W>, name: &str) -> Result<()> {
+    writer.write_event(Event::Start(BytesStart::borrowed_name(name.as_bytes())))
+}
+
+fn end<W: Write>(writer: &mut Writer<W>, name: &str) -> Result<()> {
+    writer.write_event(Event::End(BytesEnd::borrowed(name.as_bytes())))
+}
+
+fn empty<W: Write>(writer: &mut Writer<W>, name: &str) -> Result<()> {
+    writer.write_event(Event::Empty(BytesStart::borrowed_name(name.as_bytes())))
+}
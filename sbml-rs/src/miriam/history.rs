@@ -0,0 +1,98 @@
+use quick_xml::escape::escape;
+
+// A person who contributed to the model, stored as a vCard in dc:creator
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ModelCreator {
+    pub given_name: Option<String>,
+    pub family_name: Option<String>,
+    pub email: Option<String>,
+    pub organisation: Option<String>,
+    // vCard elements not covered above, e.g. vCard:TEL, kept as written
+    pub other_fields: Vec<String>,
+}
+
+// Provenance of a model: its creators and W3CDTF timestamps,
+// e.g. 2005-02-02T14:56:11Z, kept as written in the annotation
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ModelHistory {
+    pub creators: Vec<ModelCreator>,
+    pub created: Option<String>,
+    pub modified: Vec<String>,
+}
+
+impl ModelCreator {
+    pub fn new(given_name: &str, family_name: &str) -> Self {
+        ModelCreator {
+            given_name: Some(given_name.to_string()),
+            family_name: Some(family_name.to_string()),
+            ..Default::default()
+        }
+    }
+}
+
+impl ModelHistory {
+    // The most recent modification date
+    pub fn last_modified(&self) -> Option<&String> {
+        self.modified.last()
+    }
+
+    // Records a modification, keeping the earlier ones
+    pub fn add_modified(&mut self, date: &str) {
+        self.modified.push(date.to_string());
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.creators.is_empty() && self.created.is_none() && self.modified.is_empty()
+    }
+
+    // Appends the dc:creator and dcterms elements of an rdf:Description
+    pub(crate) fn write(&self, rdf: &mut String) {
+        if !self.creators.is_empty() {
+            rdf.push_str("    <dc:creator>\n      <rdf:Bag>\n");
+            for creator in &self.creators {
+                rdf.push_str("        <rdf:li rdf:parseType=\"Resource\">\n");
+                if creator.family_name.is_some() || creator.given_name.is_some() {
+                    rdf.push_str("          <vCard:N rdf:parseType=\"Resource\">\n");
+                    write_text(rdf, 12, "vCard:Family", &creator.family_name);
+                    write_text(rdf, 12, "vCard:Given", &creator.given_name);
+                    rdf.push_str("          </vCard:N>\n");
+                }
+                write_text(rdf, 10, "vCard:EMAIL", &creator.email);
+                if creator.organisation.is_some() {
+                    rdf.push_str("          <vCard:ORG rdf:parseType=\"Resource\">\n");
+                    write_text(rdf, 12, "vCard:Orgname", &creator.organisation);
+                    rdf.push_str("          </vCard:ORG>\n");
+                }
+                for field in &creator.other_fields {
+                    rdf.push_str(&format!("          {}\n", field));
+                }
+                rdf.push_str("        </rdf:li>\n");
+            }
+            rdf.push_str("      </rdf:Bag>\n    </dc:creator>\n");
+        }
+        if let Some(created) = &self.created {
+            write_date(rdf, "dcterms:created", created);
+        }
+        for modified in &self.modified {
+            write_date(rdf, "dcterms:modified", modified);
+        }
+    }
+}
+
+fn write_text(rdf: &mut String, indent: usize, name: &str, value: &Option<String>) {
+    if let Some(value) = value {
+        rdf.push_str(&format!(
+            "{:indent$}<{name}>{}</{name}>\n",
+            "",
+            String::from_utf8_lossy(&escape(value.as_bytes())),
+            indent = indent,
+            name = name
+        ));
+    }
+}
+
+fn write_date(rdf: &mut String, name: &str, date: &str) {
+    rdf.push_str(&format!("    <{} rdf:parseType=\"Resource\">\n", name));
+    write_text(rdf, 6, "dcterms:W3CDTF", &Some(date.to_string()));
+    rdf.push_str(&format!("    </{}>\n", name));
+}
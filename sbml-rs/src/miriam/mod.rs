@@ -0,0 +1,669 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::str;
+
+use quick_xml::escape::escape;
+use quick_xml::events::{BytesEnd, BytesStart, BytesText, Event};
+use quick_xml::{Reader, Writer};
+
+use crate::{Location, SbmlError};
+
+pub mod history;
+pub use history::*;
+
+pub const RDF_NS: &str = "http://www.w3.org/1999/02/22-rdf-syntax-ns#";
+pub const BQBIOL_NS: &str = "http://biomodels.net/biology-qualifiers/";
+pub const BQMODEL_NS: &str = "http://biomodels.net/model-qualifiers/";
+pub const DC_NS: &str = "http://purl.org/dc/elements/1.1/";
+pub const DCTERMS_NS: &str = "http://purl.org/dc/terms/";
+pub const VCARD_NS: &str = "http://www.w3.org/2001/vcard-rdf/3.0#";
+
+// Relations of the biology-qualifiers namespace (bqbiol)
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum BiologicalQualifier {
+    Is,
+    HasPart,
+    IsPartOf,
+    IsVersionOf,
+    HasVersion,
+    IsHomologTo,
+    IsDescribedBy,
+    IsEncodedBy,
+    Encodes,
+    OccursIn,
+    HasProperty,
+    IsPropertyOf,
+    HasTaxon,
+}
+
+// Relations of the model-qualifiers namespace (bqmodel)
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ModelQualifier {
+    Is,
+    IsDescribedBy,
+    IsDerivedFrom,
+    IsInstanceOf,
+    HasInstance,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Qualifier {
+    Biological(BiologicalQualifier),
+    Model(ModelQualifier),
+}
+
+// A controlled vocabulary term: a qualifier relating the annotated element
+// to one or more resource URIs, e.g. bqbiol:is http://identifiers.org/CHEBI:17234
+#[derive(Clone, Debug, PartialEq)]
+pub struct CvTerm {
+    pub qualifier: Qualifier,
+    pub resources: Vec<String>,
+}
+
+impl CvTerm {
+    pub fn new(qualifier: Qualifier, resources: Vec<String>) -> Self {
+        CvTerm {
+            qualifier,
+            resources,
+        }
+    }
+
+    // true if any of the resources contains the pattern,
+    // e.g. "CHEBI:" for terms pointing into ChEBI
+    pub fn references(&self, pattern: &str) -> bool {
+        self.resources.iter().any(|r| r.contains(pattern))
+    }
+}
+
+impl BiologicalQualifier {
+    pub fn from_name(name: &str) -> Option<Self> {
+        Some(match name {
+            "is" => BiologicalQualifier::Is,
+            "hasPart" => BiologicalQualifier::HasPart,
+            "isPartOf" => BiologicalQualifier::IsPartOf,
+            "isVersionOf" => BiologicalQualifier::IsVersionOf,
+            "hasVersion" => BiologicalQualifier::HasVersion,
+            "isHomologTo" => BiologicalQualifier::IsHomologTo,
+            "isDescribedBy" => BiologicalQualifier::IsDescribedBy,
+            "isEncodedBy" => BiologicalQualifier::IsEncodedBy,
+            "encodes" => BiologicalQualifier::Encodes,
+            "occursIn" => BiologicalQualifier::OccursIn,
+            "hasProperty" => BiologicalQualifier::HasProperty,
+            "isPropertyOf" => BiologicalQualifier::IsPropertyOf,
+            "hasTaxon" => BiologicalQualifier::HasTaxon,
+            _ => return None,
+        })
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            BiologicalQualifier::Is => "is",
+            BiologicalQualifier::HasPart => "hasPart",
+            BiologicalQualifier::IsPartOf => "isPartOf",
+            BiologicalQualifier::IsVersionOf => "isVersionOf",
+            BiologicalQualifier::HasVersion => "hasVersion",
+            BiologicalQualifier::IsHomologTo => "isHomologTo",
+            BiologicalQualifier::IsDescribedBy => "isDescribedBy",
+            BiologicalQualifier::IsEncodedBy => "isEncodedBy",
+            BiologicalQualifier::Encodes => "encodes",
+            BiologicalQualifier::OccursIn => "occursIn",
+            BiologicalQualifier::HasProperty => "hasProperty",
+            BiologicalQualifier::IsPropertyOf => "isPropertyOf",
+            BiologicalQualifier::HasTaxon => "hasTaxon",
+        }
+    }
+}
+
+impl ModelQualifier {
+    pub fn from_name(name: &str) -> Option<Self> {
+        Some(match name {
+            "is" => ModelQualifier::Is,
+            "isDescribedBy" => ModelQualifier::IsDescribedBy,
+            "isDerivedFrom" => ModelQualifier::IsDerivedFrom,
+            "isInstanceOf" => ModelQualifier::IsInstanceOf,
+            "hasInstance" => ModelQualifier::HasInstance,
+            _ => return None,
+        })
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ModelQualifier::Is => "is",
+            ModelQualifier::IsDescribedBy => "isDescribedBy",
+            ModelQualifier::IsDerivedFrom => "isDerivedFrom",
+            ModelQualifier::IsInstanceOf => "isInstanceOf",
+            ModelQualifier::HasInstance => "hasInstance",
+        }
+    }
+}
+
+impl fmt::Display for Qualifier {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Qualifier::Biological(q) => write!(f, "bqbiol:{}", q.name()),
+            Qualifier::Model(q) => write!(f, "bqmodel:{}", q.name()),
+        }
+    }
+}
+
+// Errors in annotations carry no position, the content has been
+// detached from the document by the time it is interpreted
+fn annotation_error<E: fmt::Display>(error: E) -> SbmlError {
+    SbmlError::XmlSyntax {
+        message: error.to_string(),
+        location: Location::at("annotation".to_string()),
+    }
+}
+
+// Value of an attribute by local name, ignoring its prefix
+fn attribute(e: &BytesStart, local_name: &[u8]) -> Result<Option<String>, SbmlError> {
+    for attr in e.attributes() {
+        let attr = attr.map_err(annotation_error)?;
+        let key = attr.key;
+        let local = match key.iter().position(|&b| b == b':') {
+            Some(pos) => &key[pos + 1..],
+            None => key,
+        };
+        if local == local_name {
+            let value = attr.unescaped_value().map_err(annotation_error)?;
+            return Ok(Some(String::from_utf8_lossy(&value).to_string()));
+        }
+    }
+    Ok(None)
+}
+
+fn qualifier_from(namespace: Option<&[u8]>, local_name: &[u8]) -> Option<Qualifier> {
+    let name = str::from_utf8(local_name).ok()?;
+    match namespace {
+        Some(ns) if ns == BQBIOL_NS.as_bytes() => {
+            BiologicalQualifier::from_name(name).map(Qualifier::Biological)
+        }
+        Some(ns) if ns == BQMODEL_NS.as_bytes() => {
+            ModelQualifier::from_name(name).map(Qualifier::Model)
+        }
+        _ => None,
+    }
+}
+
+// Everything an rdf:Description says about one element
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Description {
+    pub cv_terms: Vec<CvTerm>,
+    pub history: Option<ModelHistory>,
+}
+
+// Elements of a model history whose text is being read
+enum HistoryField {
+    FamilyName,
+    GivenName,
+    Email,
+    Organisation,
+    Date,
+}
+
+// Parses the RDF inside the content of an <annotation> element into the
+// controlled vocabulary terms of each rdf:Description, keyed by the metaid
+// the description is about. Unknown qualifiers and non-RDF content are ignored.
+pub fn parse_cv_terms(annotation: &str) -> Result<HashMap<String, Vec<CvTerm>>, SbmlError> {
+    Ok(parse_rdf(annotation)?
+        .into_iter()
+        .filter(|(_, description)| !description.cv_terms.is_empty())
+        .map(|(metaid, description)| (metaid, description.cv_terms))
+        .collect())
+}
+
+// Parses the model history (dc:creator, dcterms:created and dcterms:modified)
+// in the description of the given metaid
+pub fn parse_history(annotation: &str, metaid: &str) -> Result<Option<ModelHistory>, SbmlError> {
+    Ok(parse_rdf(annotation)?
+        .remove(metaid)
+        .and_then(|description| description.history))
+}
+
+// An element on one line, without the indentation between its tags, so
+// that it can be written back at any depth
+fn compact(element: &str) -> String {
+    element.lines().map(str::trim).collect()
+}
+
+// Elements of a creator's vCard that ModelCreator has fields for
+fn is_creator_field(local_name: &[u8]) -> bool {
+    matches!(
+        local_name,
+        b"N" | b"EMAIL" | b"ORG" | b"hasName" | b"hasEmail" | b"organization-name"
+    )
+}
+
+// Parses every rdf:Description in the annotation, keyed by the metaid it is about
+pub fn parse_rdf(annotation: &str) -> Result<HashMap<String, Description>, SbmlError> {
+    let mut reader = Reader::from_str(annotation);
+    reader.trim_text(true);
+    let mut buf = Vec::new();
+    let mut ns_buf = Vec::new();
+    let mut result: HashMap<String, Description> = HashMap::new();
+    let mut about: Option<String> = None;
+    let mut description = Description::default();
+    let mut term: Option<CvTerm> = None;
+    let mut history = ModelHistory::default();
+    let mut in_creator = false;
+    let mut creator: Option<ModelCreator> = None;
+    // true for dcterms:created, false for dcterms:modified
+    let mut date_kind: Option<bool> = None;
+    let mut field: Option<HistoryField> = None;
+    // depth inside the rdf:li of a creator, and where an element of it that
+    // is not modelled starts
+    let mut creator_depth = 0;
+    let mut other_field: Option<usize> = None;
+
+    loop {
+        let position = reader.buffer_position();
+        let event = reader.read_namespaced_event(&mut buf, &mut ns_buf);
+        if let Some(creator) = creator.as_mut() {
+            match &event {
+                Ok((_, Event::Start(e)))
+                    if creator_depth == 0 && !is_creator_field(e.local_name()) =>
+                {
+                    other_field = Some(position);
+                }
+                Ok((_, Event::Empty(e)))
+                    if creator_depth == 0 && !is_creator_field(e.local_name()) =>
+                {
+                    let end = reader.buffer_position();
+                    creator
+                        .other_fields
+                        .push(compact(&annotation[position..end]));
+                }
+                Ok((_, Event::End(_))) if creator_depth == 1 && other_field.is_some() => {
+                    let start = other_field.take().unwrap_or_default();
+                    let end = reader.buffer_position();
+                    creator.other_fields.push(compact(&annotation[start..end]));
+                }
+                _ => {}
+            }
+            match &event {
+                Ok((_, Event::Start(_))) => creator_depth += 1,
+                Ok((_, Event::End(_))) if creator_depth > 0 => creator_depth -= 1,
+                _ => {}
+            }
+        }
+        match event {
+            Ok((ns, Event::Start(ref e))) | Ok((ns, Event::Empty(ref e))) => {
+                let is_rdf = ns == Some(RDF_NS.as_bytes());
+                match e.local_name() {
+                    b"Description" if is_rdf => {
+                        about = attribute(e, b"about")?
+                            .map(|about| about.trim_start_matches('#').to_string());
+                    }
+                    b"li" if is_rdf && in_creator => creator = Some(ModelCreator::default()),
+                    b"li" if is_rdf => {
+                        if let (Some(term), Some(resource)) =
+                            (term.as_mut(), attribute(e, b"resource")?)
+                        {
+                            term.resources.push(resource);
+                        }
+                    }
+                    b"creator" if ns == Some(DC_NS.as_bytes()) => in_creator = true,
+                    b"created" if ns == Some(DCTERMS_NS.as_bytes()) => date_kind = Some(true),
+                    b"modified" if ns == Some(DCTERMS_NS.as_bytes()) => date_kind = Some(false),
+                    b"W3CDTF" if date_kind.is_some() => field = Some(HistoryField::Date),
+                    // vCard 3.0 names first, then the vCard 4.0 ones used by L3V2
+                    b"Family" | b"family-name" if creator.is_some() => {
+                        field = Some(HistoryField::FamilyName)
+                    }
+                    b"Given" | b"given-name" if creator.is_some() => {
+                        field = Some(HistoryField::GivenName)
+                    }
+                    b"EMAIL" | b"hasEmail" if creator.is_some() => {
+                        field = Some(HistoryField::Email)
+                    }
+                    b"Orgname" | b"organization-name" if creator.is_some() => {
+                        field = Some(HistoryField::Organisation)
+                    }
+                    name => {
+                        if about.is_some() {
+                            if let Some(qualifier) = qualifier_from(ns, name) {
+                                term = Some(CvTerm::new(qualifier, Vec::new()));
+                            }
+                        }
+                    }
+                }
+            }
+            Ok((_, Event::Text(ref e))) => {
+                if let Some(field) = &field {
+                    let text = e.unescape_and_decode(&reader).map_err(annotation_error)?;
+                    match (field, creator.as_mut()) {
+                        (HistoryField::Date, _) => match date_kind {
+                            Some(true) => history.created = Some(text),
+                            _ => history.modified.push(text),
+                        },
+                        (HistoryField::FamilyName, Some(c)) => c.family_name = Some(text),
+                        (HistoryField::GivenName, Some(c)) => c.given_name = Some(text),
+                        (HistoryField::Email, Some(c)) => c.email = Some(text),
+                        (HistoryField::Organisation, Some(c)) => c.organisation = Some(text),
+                        _ => {}
+                    }
+                }
+            }
+            Ok((ns, Event::End(ref e))) => {
+                field = None;
+                let is_rdf = ns == Some(RDF_NS.as_bytes());
+                match e.local_name() {
+                    b"Description" if is_rdf => {
+                        if let Some(metaid) = about.take() {
+                            if !history.is_empty() {
+                                description.history = Some(history);
+                            }
+                            result.insert(metaid, description);
+                        }
+                        description = Description::default();
+                        history = ModelHistory::default();
+                    }
+                    b"li" if is_rdf && in_creator => {
+                        if let Some(creator) = creator.take() {
+                            history.creators.push(creator);
+                        }
+                    }
+                    b"creator" if ns == Some(DC_NS.as_bytes()) => in_creator = false,
+                    b"created" | b"modified" if ns == Some(DCTERMS_NS.as_bytes()) => {
+                        date_kind = None
+                    }
+                    name => {
+                        if let Some(qualifier) = qualifier_from(ns, name) {
+                            if let Some(term) = term.take() {
+                                if about.is_some() && term.qualifier == qualifier {
+                                    description.cv_terms.push(term);
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+            Ok((_, Event::Eof)) => break,
+            Err(e) => return Err(annotation_error(e)),
+            _ => {}
+        }
+        buf.clear();
+    }
+    Ok(result)
+}
+
+// Serializes the terms as an rdf:RDF block describing the element with the given metaid
+pub fn cv_terms_to_rdf(metaid: &str, cv_terms: &[CvTerm]) -> String {
+    let mut children = String::new();
+    write_cv_terms(&mut children, cv_terms);
+    write_rdf(&write_description(metaid, &children), CV_TERM_PREFIXES)
+}
+
+// Prefixes of the elements written for terms and for histories
+const CV_TERM_PREFIXES: &[(&str, &str)] = &[
+    ("rdf", RDF_NS),
+    ("bqbiol", BQBIOL_NS),
+    ("bqmodel", BQMODEL_NS),
+];
+const HISTORY_PREFIXES: &[(&str, &str)] = &[
+    ("rdf", RDF_NS),
+    ("dc", DC_NS),
+    ("dcterms", DCTERMS_NS),
+    ("vCard", VCARD_NS),
+];
+
+fn write_rdf(descriptions: &str, prefixes: &[(&str, &str)]) -> String {
+    let mut rdf = "<rdf:RDF".to_string();
+    for (prefix, namespace) in prefixes {
+        rdf.push_str(&format!(" xmlns:{}=\"{}\"", prefix, namespace));
+    }
+    rdf.push_str(">\n");
+    rdf.push_str(descriptions);
+    rdf.push_str("</rdf:RDF>");
+    rdf
+}
+
+fn write_description(metaid: &str, children: &str) -> String {
+    format!(
+        "  <rdf:Description rdf:about=\"#{}\">\n{}  </rdf:Description>\n",
+        String::from_utf8_lossy(&escape(metaid.as_bytes())),
+        children
+    )
+}
+
+fn write_cv_terms(rdf: &mut String, cv_terms: &[CvTerm]) {
+    for term in cv_terms {
+        rdf.push_str(&format!("    <{}>\n      <rdf:Bag>\n", term.qualifier));
+        for resource in &term.resources {
+            rdf.push_str(&format!(
+                "        <rdf:li rdf:resource=\"{}\"/>\n",
+                String::from_utf8_lossy(&escape(resource.as_bytes()))
+            ));
+        }
+        rdf.push_str(&format!("      </rdf:Bag>\n    </{}>\n", term.qualifier));
+    }
+}
+
+// Returns the annotation content with the terms describing metaid replaced.
+// Only the qualifiers parse_cv_terms knows are rewritten, everything else
+// in the annotation is copied as it is.
+pub fn set_cv_terms(
+    annotation: Option<&str>,
+    metaid: &str,
+    cv_terms: &[CvTerm],
+) -> Result<String, SbmlError> {
+    let mut children = String::new();
+    write_cv_terms(&mut children, cv_terms);
+    let is_term = |ns: Option<&[u8]>, name: &[u8]| qualifier_from(ns, name).is_some();
+    update_description(annotation, metaid, &is_term, &children, CV_TERM_PREFIXES)
+}
+
+// Returns the annotation content with the history of metaid replaced.
+// The dc:creator, dcterms:created and dcterms:modified elements are
+// rewritten, everything else is copied as it is.
+pub fn set_history(
+    annotation: Option<&str>,
+    metaid: &str,
+    history: Option<&ModelHistory>,
+) -> Result<String, SbmlError> {
+    let mut children = String::new();
+    if let Some(history) = history {
+        history.write(&mut children);
+    }
+    let is_history = |ns: Option<&[u8]>, name: &[u8]| match name {
+        b"creator" => ns == Some(DC_NS.as_bytes()),
+        b"created" | b"modified" => ns == Some(DCTERMS_NS.as_bytes()),
+        _ => false,
+    };
+    update_description(annotation, metaid, &is_history, &children, HISTORY_PREFIXES)
+}
+
+// Picks children of a description by namespace and local name
+type Selector = dyn Fn(Option<&[u8]>, &[u8]) -> bool;
+
+// Copies the annotation content, replacing the children of the rdf:Description
+// of metaid that are selected by `replaced` with the given ones. A description
+// or an rdf:RDF block is added if there is none yet.
+fn update_description(
+    annotation: Option<&str>,
+    metaid: &str,
+    replaced: &Selector,
+    children: &str,
+    prefixes: &[(&str, &str)],
+) -> Result<String, SbmlError> {
+    let mut reader = Reader::from_str(annotation.unwrap_or_default());
+    let mut writer = Writer::new(Vec::new());
+    let mut buf = Vec::new();
+    let mut ns_buf = Vec::new();
+    // number of open elements, and that of rdf:RDF and of the description
+    let mut depth = 0;
+    let mut rdf_depth = None;
+    let mut description_depth = None;
+    let mut found = false;
+    // depth inside a replaced element being dropped
+    let mut skipping = 0;
+    // whitespace between children of the description, dropped along with
+    // a replaced child
+    let mut pending: Option<BytesText<'static>> = None;
+
+    loop {
+        let (ns, event) = reader
+            .read_namespaced_event(&mut buf, &mut ns_buf)
+            .map_err(annotation_error)?;
+        if skipping > 0 {
+            match event {
+                Event::Start(_) => skipping += 1,
+                Event::End(_) => skipping -= 1,
+                Event::Eof => break,
+                _ => {}
+            }
+            buf.clear();
+            continue;
+        }
+        let is_rdf = ns == Some(RDF_NS.as_bytes());
+        let in_description = description_depth.is_some() && description_depth == Some(depth);
+        match event {
+            Event::Eof => break,
+            Event::Start(ref e) | Event::Empty(ref e)
+                if in_description && replaced(ns, e.local_name()) =>
+            {
+                pending = None;
+                if let Event::Start(_) = event {
+                    skipping = 1;
+                }
+            }
+            Event::Text(e) if in_description && e.iter().all(u8::is_ascii_whitespace) => {
+                write_pending(&mut writer, &mut pending)?;
+                pending = Some(e.into_owned());
+            }
+            Event::Start(e) => {
+                write_pending(&mut writer, &mut pending)?;
+                let e = if rdf_depth.is_none() && is_rdf && e.local_name() == b"RDF" {
+                    rdf_depth = Some(depth + 1);
+                    with_prefixes(&e, prefixes)?
+                } else {
+                    if rdf_depth == Some(depth) && is_description_of(is_rdf, &e, metaid)? {
+                        description_depth = Some(depth + 1);
+                        found = true;
+                    }
+                    e
+                };
+                write(&mut writer, Event::Start(e))?;
+                depth += 1;
+            }
+            Event::Empty(e) => {
+                write_pending(&mut writer, &mut pending)?;
+                if rdf_depth.is_none() && is_rdf && e.local_name() == b"RDF" {
+                    let end = BytesEnd::owned(e.name().to_vec());
+                    write(&mut writer, Event::Start(with_prefixes(&e, prefixes)?))?;
+                    let description = write_description(metaid, children);
+                    write_raw(&mut writer, &format!("\n{}", description))?;
+                    write(&mut writer, Event::End(end))?;
+                    rdf_depth = Some(depth + 1);
+                    found = true;
+                } else if rdf_depth == Some(depth) && is_description_of(is_rdf, &e, metaid)? {
+                    let end = BytesEnd::owned(e.name().to_vec());
+                    write(&mut writer, Event::Start(e))?;
+                    write_raw(&mut writer, &format!("\n{}  ", children))?;
+                    write(&mut writer, Event::End(end))?;
+                    found = true;
+                } else {
+                    write(&mut writer, Event::Empty(e))?;
+                }
+            }
+            Event::End(e) => {
+                if in_description {
+                    // the new children go last, indented like the closing tag
+                    let indent = pending_indent(pending.take());
+                    let children = indented(children, &indent, 2);
+                    write_raw(&mut writer, &format!("\n{}{}", children, indent))?;
+                    description_depth = None;
+                } else if rdf_depth == Some(depth) && !found && !children.is_empty() {
+                    let indent = pending_indent(pending.take());
+                    let description = indented(&write_description(metaid, children), &indent, 0);
+                    write_raw(&mut writer, &format!("\n{}{}", description, indent))?;
+                    found = true;
+                }
+                write_pending(&mut writer, &mut pending)?;
+                write(&mut writer, Event::End(e))?;
+                depth -= 1;
+            }
+            event => {
+                write_pending(&mut writer, &mut pending)?;
+                write(&mut writer, event)?;
+            }
+        }
+        buf.clear();
+    }
+
+    let mut content = String::from_utf8_lossy(&writer.into_inner())
+        .trim()
+        .to_string();
+    if !found && !children.is_empty() {
+        if !content.is_empty() {
+            content.push('\n');
+        }
+        content.push_str(&write_rdf(&write_description(metaid, children), prefixes));
+    }
+    Ok(content)
+}
+
+fn write<W: std::io::Write>(writer: &mut Writer<W>, event: Event) -> Result<(), SbmlError> {
+    writer.write_event(event).map_err(annotation_error)
+}
+
+// Writes text that is already escaped
+fn write_raw<W: std::io::Write>(writer: &mut Writer<W>, text: &str) -> Result<(), SbmlError> {
+    write(writer, Event::Text(BytesText::from_escaped_str(text)))
+}
+
+fn write_pending<W: std::io::Write>(
+    writer: &mut Writer<W>,
+    pending: &mut Option<BytesText<'static>>,
+) -> Result<(), SbmlError> {
+    match pending.take() {
+        Some(text) => write(writer, Event::Text(text)),
+        None => Ok(()),
+    }
+}
+
+// The indentation on the last line of whitespace
+fn pending_indent(pending: Option<BytesText>) -> String {
+    let text = pending.map(|text| text.to_vec()).unwrap_or_default();
+    let text = String::from_utf8_lossy(&text);
+    match text.rfind('\n') {
+        Some(pos) => text[pos + 1..].to_string(),
+        None => String::new(),
+    }
+}
+
+// Moves written lines to the indentation of the surrounding document, the
+// lines start with `written` spaces at the level of `indent`
+fn indented(lines: &str, indent: &str, written: usize) -> String {
+    lines
+        .lines()
+        .map(|line| format!("{}{}\n", indent, &line[written.min(line.len())..]))
+        .collect()
+}
+
+fn is_description_of(is_rdf: bool, e: &BytesStart, metaid: &str) -> Result<bool, SbmlError> {
+    if !is_rdf || e.local_name() != b"Description" {
+        return Ok(false);
+    }
+    let about = attribute(e, b"about")?;
+    Ok(about.as_deref().map(|about| about.trim_start_matches('#')) == Some(metaid))
+}
+
+// A copy of the rdf:RDF start tag declaring the prefixes it does not declare yet
+fn with_prefixes(
+    e: &BytesStart,
+    prefixes: &[(&str, &str)],
+) -> Result<BytesStart<'static>, SbmlError> {
+    let mut declared = Vec::new();
+    for attr in e.attributes() {
+        declared.push(attr.map_err(annotation_error)?.key.to_vec());
+    }
+    let mut start = e.to_owned();
+    for (prefix, namespace) in prefixes {
+        let key = format!("xmlns:{}", prefix);
+        if !declared.contains(&key.as_bytes().to_vec()) {
+            start.push_attribute((key.as_str(), *namespace));
+        }
+    }
+    Ok(start)
+}
@@ -1,8 +1,30 @@
 use super::tag::TagIndex;
 
+// Level 2 only, removed in Level 3
+#[derive(Clone, Debug, Default)]
+pub struct ListOfCompartmentTypes {
+    pub compartment_types: Vec<TagIndex>,
+    pub notes: Option<String>,
+    pub annotation: Option<String>,
+    pub parent: Option<TagIndex>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct CompartmentType {
+    pub id: Option<String>,
+    pub metaid: Option<String>,
+    pub name: Option<String>,
+    pub sbo_term: Option<String>,
+    pub notes: Option<String>,
+    pub annotation: Option<String>,
+    pub parent: Option<TagIndex>,
+}
+
 #[derive(Clone, Debug, Default)]
 pub struct ListOfCompartments {
     pub compartments: Vec<TagIndex>,
+    pub notes: Option<String>,
+    pub annotation: Option<String>,
     pub parent: Option<TagIndex>,
 }
 
@@ -10,10 +32,15 @@ pub struct ListOfCompartments {
 pub struct Compartment {
     pub units: Option<String>,
     pub id: Option<String>,
+    pub metaid: Option<String>,
     pub name: Option<String>,
     pub sbo_term: Option<String>,
     pub spatial_dimensions: Option<f64>,
     pub size: Option<f64>,
     pub constant: Option<bool>,
+    pub compartment_type: Option<String>,
+    pub outside: Option<String>,
+    pub notes: Option<String>,
+    pub annotation: Option<String>,
     pub parent: Option<TagIndex>,
 }
@@ -0,0 +1,44 @@
+use std::collections::HashMap;
+
+use crate::{MathTag, Model, TagIndex};
+
+#[derive(Clone, Debug, Default)]
+pub struct ListOfConstraints {
+    pub constraints: Vec<TagIndex>,
+    pub notes: Option<String>,
+    pub annotation: Option<String>,
+    pub parent: Option<TagIndex>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Constraint {
+    pub id: Option<String>,
+    pub metaid: Option<String>,
+    pub sbo_term: Option<String>,
+    pub math: Option<TagIndex>,
+    // XHTML content of the <message> element
+    pub message: Option<String>,
+    pub notes: Option<String>,
+    pub annotation: Option<String>,
+    pub parent: Option<TagIndex>,
+}
+
+impl Constraint {
+    pub fn math_tag(&self, model: &Model) -> Option<MathTag> {
+        self.math_tag_ref(model).cloned()
+    }
+
+    pub fn math_tag_ref<'a>(&self, model: &'a Model) -> Option<&'a MathTag> {
+        model.math_tag_at(self.math)
+    }
+
+    // Returns false if the model state in values violates the constraint
+    pub fn check(&self, model: &Model, values: &HashMap<String, f64>) -> Result<bool, String> {
+        let math_tag = self
+            .math_tag(model)
+            .ok_or_else(|| "Constraint has no math".to_string())?;
+        let functions = model.function_definition_math();
+        let result = math_tag.evaluate(values, &functions)?;
+        Ok(result != 0.0)
+    }
+}
@@ -0,0 +1,43 @@
+use std::collections::HashMap;
+
+use crate::{Diagnostic, Model};
+
+// An SBML document: the <sbml> root element and the model inside it
+#[derive(Clone, Debug, Default)]
+pub struct SbmlDocument {
+    pub level: Option<u32>,
+    pub version: Option<u32>,
+    // namespace URIs by prefix, the default namespace has an empty prefix
+    pub namespaces: HashMap<String, String>,
+    pub packages: Vec<Package>,
+    pub notes: Option<String>,
+    pub annotation: Option<String>,
+    pub model: Model,
+    // problems that were skipped while reading the document
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+// An SBML Level 3 package declared on the root element
+#[derive(Clone, Debug, PartialEq)]
+pub struct Package {
+    pub prefix: String,
+    pub uri: String,
+    // whether the package can change the meaning of core constructs
+    pub required: bool,
+}
+
+impl SbmlDocument {
+    // Level and version, assuming L3v2 when the document does not say
+    pub fn level_version(&self) -> (u32, u32) {
+        (self.level.unwrap_or(3), self.version.unwrap_or(2))
+    }
+
+    // Namespace of the elements without a prefix
+    pub fn core_namespace(&self) -> Option<&String> {
+        self.namespaces.get("")
+    }
+
+    pub fn package(&self, prefix: &str) -> Option<&Package> {
+        self.packages.iter().find(|p| p.prefix == prefix)
+    }
+}
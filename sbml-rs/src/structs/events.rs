@@ -0,0 +1,166 @@
+use crate::{MathTag, Model, Tag, TagIndex};
+
+#[derive(Clone, Debug, Default)]
+pub struct ListOfEvents {
+    pub events: Vec<TagIndex>,
+    pub notes: Option<String>,
+    pub annotation: Option<String>,
+    pub parent: Option<TagIndex>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Event {
+    pub id: Option<String>,
+    pub metaid: Option<String>,
+    pub name: Option<String>,
+    pub sbo_term: Option<String>,
+    pub use_values_from_trigger_time: Option<bool>,
+    pub trigger: Option<TagIndex>,
+    pub delay: Option<TagIndex>,
+    pub priority: Option<TagIndex>,
+    pub list_of_event_assignments: Option<TagIndex>,
+    pub notes: Option<String>,
+    pub annotation: Option<String>,
+    pub parent: Option<TagIndex>,
+}
+
+impl Event {
+    pub fn trigger(&self, model: &Model) -> Option<Trigger> {
+        let mut result = None;
+        if let Some(trigger_idx) = self.trigger {
+            if let Tag::Trigger(trigger) = &model.nodes[trigger_idx] {
+                result = Some(trigger.clone());
+            }
+        }
+        result
+    }
+
+    pub fn trigger_math(&self, model: &Model) -> Option<MathTag> {
+        self.trigger(model)?.math_tag(model)
+    }
+
+    pub fn delay_math(&self, model: &Model) -> Option<MathTag> {
+        let mut result = None;
+        if let Some(delay_idx) = self.delay {
+            if let Tag::Delay(delay) = &model.nodes[delay_idx] {
+                result = delay.math_tag(model);
+            }
+        }
+        result
+    }
+
+    pub fn priority_math(&self, model: &Model) -> Option<MathTag> {
+        let mut result = None;
+        if let Some(priority_idx) = self.priority {
+            if let Tag::Priority(priority) = &model.nodes[priority_idx] {
+                result = priority.math_tag(model);
+            }
+        }
+        result
+    }
+
+    pub fn event_assignments(&self, model: &Model) -> Vec<EventAssignment> {
+        let mut result = Vec::new();
+        if let Some(list_idx) = self.list_of_event_assignments {
+            if let Tag::ListOfEventAssignments(list) = &model.nodes[list_idx] {
+                for assignment_idx in &list.event_assignments {
+                    if let Tag::EventAssignment(assignment) = &model.nodes[*assignment_idx] {
+                        result.push(assignment.clone());
+                    }
+                }
+            }
+        }
+        result
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Trigger {
+    pub metaid: Option<String>,
+    pub initial_value: Option<bool>,
+    pub persistent: Option<bool>,
+    pub sbo_term: Option<String>,
+    pub math: Option<TagIndex>,
+    pub notes: Option<String>,
+    pub annotation: Option<String>,
+    pub parent: Option<TagIndex>,
+}
+
+impl Trigger {
+    pub fn math_tag(&self, model: &Model) -> Option<MathTag> {
+        self.math_tag_ref(model).cloned()
+    }
+
+    pub fn math_tag_ref<'a>(&self, model: &'a Model) -> Option<&'a MathTag> {
+        model.math_tag_at(self.math)
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Delay {
+    pub metaid: Option<String>,
+    pub sbo_term: Option<String>,
+    pub math: Option<TagIndex>,
+    pub notes: Option<String>,
+    pub annotation: Option<String>,
+    pub parent: Option<TagIndex>,
+}
+
+impl Delay {
+    pub fn math_tag(&self, model: &Model) -> Option<MathTag> {
+        self.math_tag_ref(model).cloned()
+    }
+
+    pub fn math_tag_ref<'a>(&self, model: &'a Model) -> Option<&'a MathTag> {
+        model.math_tag_at(self.math)
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Priority {
+    pub metaid: Option<String>,
+    pub sbo_term: Option<String>,
+    pub math: Option<TagIndex>,
+    pub notes: Option<String>,
+    pub annotation: Option<String>,
+    pub parent: Option<TagIndex>,
+}
+
+impl Priority {
+    pub fn math_tag(&self, model: &Model) -> Option<MathTag> {
+        self.math_tag_ref(model).cloned()
+    }
+
+    pub fn math_tag_ref<'a>(&self, model: &'a Model) -> Option<&'a MathTag> {
+        model.math_tag_at(self.math)
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct ListOfEventAssignments {
+    pub event_assignments: Vec<TagIndex>,
+    pub notes: Option<String>,
+    pub annotation: Option<String>,
+    pub parent: Option<TagIndex>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct EventAssignment {
+    pub variable: Option<String>,
+    pub metaid: Option<String>,
+    pub sbo_term: Option<String>,
+    pub math: Option<TagIndex>,
+    pub notes: Option<String>,
+    pub annotation: Option<String>,
+    pub parent: Option<TagIndex>,
+}
+
+impl EventAssignment {
+    pub fn math_tag(&self, model: &Model) -> Option<MathTag> {
+        self.math_tag_ref(model).cloned()
+    }
+
+    pub fn math_tag_ref<'a>(&self, model: &'a Model) -> Option<&'a MathTag> {
+        model.math_tag_at(self.math)
+    }
+}
@@ -2,33 +2,36 @@ use mathml_rs::{evaluate_lambda, MathNode};
 
 use super::math::MathTag;
 use super::model::Model;
-use super::tag::{Tag, TagIndex};
+use super::tag::TagIndex;
 use std::collections::HashMap;
 
 #[derive(Clone, Debug, Default)]
 pub struct ListOfFunctionDefinitions {
     pub function_definitions: Vec<TagIndex>,
+    pub notes: Option<String>,
+    pub annotation: Option<String>,
     pub parent: Option<TagIndex>,
 }
 
 #[derive(Clone, Debug, Default)]
 pub struct FunctionDefinition {
     pub id: Option<String>,
+    pub metaid: Option<String>,
     pub name: Option<String>,
     pub sbo_term: Option<String>,
     pub math: Option<TagIndex>,
+    pub notes: Option<String>,
+    pub annotation: Option<String>,
     pub parent: Option<TagIndex>,
 }
 
 impl FunctionDefinition {
     pub fn math_tag(&self, model: &Model) -> Option<MathTag> {
-        let mut result = None;
-        if let Some(math_tag_idx) = self.math {
-            if let Tag::MathTag(math_tag) = &model.nodes[math_tag_idx] {
-                result = Some(math_tag.clone());
-            }
-        }
-        result
+        self.math_tag_ref(model).cloned()
+    }
+
+    pub fn math_tag_ref<'a>(&self, model: &'a Model) -> Option<&'a MathTag> {
+        model.math_tag_at(self.math)
     }
 
     pub fn evaluate(
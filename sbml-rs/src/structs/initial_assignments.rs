@@ -1,28 +1,31 @@
-use crate::{MathTag, Model, Tag, TagIndex};
+use crate::{MathTag, Model, TagIndex};
 
 #[derive(Clone, Debug, Default)]
 pub struct ListOfInitialAssignments {
     pub initial_assignments: Vec<TagIndex>,
+    pub notes: Option<String>,
+    pub annotation: Option<String>,
     pub parent: Option<TagIndex>,
 }
 
 #[derive(Debug, Default, Clone)]
 pub struct InitialAssignment {
     pub id: Option<String>,
+    pub metaid: Option<String>,
     pub symbol: Option<String>,
     pub sbo_term: Option<String>,
     pub math: Option<TagIndex>,
+    pub notes: Option<String>,
+    pub annotation: Option<String>,
     pub parent: Option<TagIndex>,
 }
 
 impl InitialAssignment {
     pub fn math_tag(&self, model: &Model) -> Option<MathTag> {
-        let mut result = None;
-        if let Some(math_tag_idx) = self.math {
-            if let Tag::MathTag(math_tag) = &model.nodes[math_tag_idx] {
-                result = Some(math_tag.clone());
-            }
-        }
-        result
+        self.math_tag_ref(model).cloned()
+    }
+
+    pub fn math_tag_ref<'a>(&self, model: &'a Model) -> Option<&'a MathTag> {
+        model.math_tag_at(self.math)
     }
 }
@@ -1,6 +1,10 @@
 use super::tag::TagIndex;
-use mathml_rs::evaluate_node;
+use crate::formula::{parse_l3_formula, to_infix};
+use crate::mathml::write_math;
+use crate::Expr;
 pub use mathml_rs::MathNode;
+use mathml_rs::{evaluate_node, NodeIndex};
+use quick_xml::Writer;
 use std::collections::HashMap;
 use std::fmt;
 
@@ -8,6 +12,8 @@ use std::fmt;
 pub struct MathTag {
     pub nodes: Vec<MathNode>,
     pub parent: Option<TagIndex>,
+    // units of <cn> nodes, given by sbml:units or in a formula as in 2 mole
+    pub number_units: HashMap<NodeIndex, String>,
 }
 
 impl fmt::Display for MathTag {
@@ -23,6 +29,16 @@ impl fmt::Display for MathTag {
 
 #[allow(dead_code)]
 impl MathTag {
+    // Parses a Level 3 infix formula such as Vmax*S/(Km+S), see parse_l3_formula
+    pub fn from_formula(formula: &str) -> Result<Self, String> {
+        let (nodes, number_units) = parse_l3_formula(formula)?;
+        Ok(MathTag {
+            nodes,
+            parent: None,
+            number_units,
+        })
+    }
+
     pub fn with_nodes(mut self, nodes: Vec<MathNode>) -> Self {
         self.nodes = nodes;
         self
@@ -40,4 +56,37 @@ impl MathTag {
     ) -> Result<f64, String> {
         evaluate_node(&self.nodes, 0, assignments, functions)
     }
+
+    // The math as an expression tree, see Expr
+    pub fn to_expr(&self) -> Result<Expr, String> {
+        Expr::from_nodes(self, 0, None)
+    }
+
+    // The subtree below node idx as an expression tree
+    pub fn expr_at(&self, idx: NodeIndex) -> Result<Expr, String> {
+        Expr::from_nodes(self, idx, None)
+    }
+
+    // Substitutes the subtree below node idx with the expression.
+    // All nodes are renumbered, so earlier indices are no longer valid.
+    pub fn splice(&mut self, idx: NodeIndex, replacement: &Expr) -> Result<(), String> {
+        let expr = Expr::from_nodes(self, 0, Some((idx, replacement)))?;
+        let parent = self.parent;
+        *self = expr.to_math_tag();
+        self.parent = parent;
+        Ok(())
+    }
+
+    // Content MathML for the nodes, as an indented <math> element
+    pub fn to_mathml(&self) -> String {
+        let mut writer = Writer::new_with_indent(Vec::new(), b' ', 2);
+        // writing into memory cannot fail
+        write_math(&mut writer, &self.nodes, &self.number_units).unwrap();
+        String::from_utf8_lossy(&writer.into_inner()).to_string()
+    }
+
+    // The math as a Level 3 infix formula, e.g. k1 * S1 / compartment
+    pub fn to_infix(&self) -> String {
+        to_infix(&self.nodes, &self.number_units)
+    }
 }
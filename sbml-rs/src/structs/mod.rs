@@ -1,4 +1,7 @@
 pub mod compartments;
+pub mod constraints;
+pub mod document;
+pub mod events;
 pub mod function_definitions;
 pub mod initial_assignments;
 pub mod math;
@@ -1,8 +1,11 @@
 use std::collections::HashMap;
+use std::ops::{Deref, DerefMut};
 
+use crate::miriam::{self, CvTerm, ModelHistory};
 use crate::{
-    AssignmentRule, Compartment, FunctionDefinition, InitialAssignment, MathNode, MathTag,
-    Parameter, RateRule, Reaction, Species, SpeciesReference, Tag, UnitDefinition,
+    AlgebraicRule, AssignmentRule, Compartment, CompartmentType, Constraint, Event,
+    FunctionDefinition, InitialAssignment, Location, MathNode, MathTag, Parameter, RateRule,
+    Reaction, SbmlError, Species, SpeciesReference, SpeciesType, Tag, TagIndex, UnitDefinition,
 };
 
 // An SBML Model container
@@ -11,6 +14,7 @@ pub struct Model {
     pub id: Option<String>,
     pub name: Option<String>,
     pub meta_id: Option<String>,
+    pub sbo_term: Option<String>,
     pub substance_units: Option<String>,
     pub time_units: Option<String>,
     pub volume_units: Option<String>,
@@ -18,27 +22,38 @@ pub struct Model {
     pub length_units: Option<String>,
     pub extent_units: Option<String>,
     pub conversion_factor: Option<String>,
-    pub nodes: Vec<Tag>,
+    pub notes: Option<String>,
+    pub annotation: Option<String>,
+    // read through nodes() and changed through nodes_mut() or add_node,
+    // which keep the id index up to date
+    pub(crate) nodes: Vec<Tag>,
+    // Position of each element in the SId namespace, see get_by_sid
+    sid_index: HashMap<String, TagIndex>,
 }
 
+// Generates a borrowing iterator over the elements of a list and an accessor
+// returning clones of them
 macro_rules! objects_from_list {
-    ($parent_type: ident, $parent_field: ident, $node_type: ident, $node_field: ident) => {
-        pub fn $node_field(&self) -> Vec<$node_type> {
-            let mut result = Vec::new();
+    ($parent_type: ident, $parent_field: ident, $node_type: ident, $node_field: ident, $iter: ident) => {
+        pub fn $iter(&self) -> impl Iterator<Item = &$node_type> {
             let mut list_idx = None;
             if let Tag::Root(root) = &self.nodes[0] {
                 list_idx = root.$parent_field;
             }
-            if let Some(idx) = list_idx {
-                if let Tag::$parent_type(list_tag) = &self.nodes[idx] {
-                    for node_idx in &list_tag.$node_field {
-                        if let Tag::$node_type(node) = &self.nodes[node_idx.to_owned()] {
-                            result.push(node.clone());
-                        }
-                    }
-                }
-            }
-            result
+            let indices: &[TagIndex] = match list_idx.map(|idx| &self.nodes[idx]) {
+                Some(Tag::$parent_type(list_tag)) => &list_tag.$node_field,
+                _ => &[],
+            };
+            indices
+                .iter()
+                .filter_map(move |idx| match &self.nodes[*idx] {
+                    Tag::$node_type(node) => Some(node),
+                    _ => None,
+                })
+        }
+
+        pub fn $node_field(&self) -> Vec<$node_type> {
+            self.$iter().cloned().collect()
         }
     };
 }
@@ -49,6 +64,7 @@ impl Model {
             id: None,
             name: None,
             meta_id: None,
+            sbo_term: None,
             substance_units: None,
             volume_units: None,
             time_units: None,
@@ -56,59 +72,202 @@ impl Model {
             area_units: None,
             length_units: None,
             conversion_factor: None,
+            notes: None,
+            annotation: None,
             nodes,
+            sid_index: HashMap::new(),
         };
+        model.reindex();
         for (key, value) in attributes {
             match key.as_str() {
                 "id" => model.id = Some(value),
                 "name" => model.name = Some(value),
                 "metaid" => model.meta_id = Some(value),
+                "sboTerm" => model.sbo_term = Some(value),
                 "substanceUnits" => model.substance_units = Some(value),
                 "timeUnits" => model.time_units = Some(value),
                 "areaUnits" => model.area_units = Some(value),
                 "lengthUnits" => model.length_units = Some(value),
                 "extentUnits" => model.extent_units = Some(value),
                 "volumeUnits" => model.volume_units = Some(value),
-                "conversion_factor" => model.conversion_factor = Some(value),
-                _ => panic!("Invalid attribute {} for model.", key),
+                "conversionFactor" => model.conversion_factor = Some(value),
+                // the parser reports unknown attributes itself
+                _ => {}
             }
         }
         model
     }
 
-    objects_from_list!(ListOfSpecies, list_of_species, Species, species);
-    objects_from_list!(ListOfReactions, list_of_reactions, Reaction, reactions);
+    // Every element of the model, Root first. Elements refer to each other by
+    // their position here.
+    pub fn nodes(&self) -> &[Tag] {
+        &self.nodes
+    }
+
+    // Mutable access to the elements. The id index is rebuilt once the
+    // returned guard is dropped, so elements may be renamed or added freely.
+    pub fn nodes_mut(&mut self) -> NodesMut<'_> {
+        NodesMut { model: self }
+    }
+
+    fn reindex(&mut self) {
+        self.sid_index.clear();
+        for (idx, tag) in self.nodes.iter().enumerate() {
+            if let Some(id) = sid(tag) {
+                // the first element wins, as with duplicates in validate
+                self.sid_index.entry(id.clone()).or_insert(idx);
+            }
+        }
+    }
+
+    // Appends a tag to the arena and indexes its id
+    pub fn add_node(&mut self, tag: Tag) -> TagIndex {
+        let idx = self.nodes.len();
+        if let Some(id) = sid(&tag) {
+            self.sid_index.entry(id.clone()).or_insert(idx);
+        }
+        self.nodes.push(tag);
+        idx
+    }
+
+    // The element with this id in the SId namespace, i.e. anything but unit
+    // definitions and local parameters
+    pub fn get_by_sid(&self, id: &str) -> Option<&Tag> {
+        self.nodes.get(*self.sid_index.get(id)?)
+    }
+
+    // The math element at this index, if it is one
+    pub(crate) fn math_tag_at(&self, idx: Option<TagIndex>) -> Option<&MathTag> {
+        match &self.nodes[idx?] {
+            Tag::MathTag(math_tag) => Some(math_tag),
+            _ => None,
+        }
+    }
+
+    pub fn get_species(&self, id: &str) -> Option<&Species> {
+        match self.get_by_sid(id) {
+            Some(Tag::Species(species)) => Some(species),
+            _ => None,
+        }
+    }
+
+    pub fn get_compartment(&self, id: &str) -> Option<&Compartment> {
+        match self.get_by_sid(id) {
+            Some(Tag::Compartment(compartment)) => Some(compartment),
+            _ => None,
+        }
+    }
+
+    pub fn get_parameter(&self, id: &str) -> Option<&Parameter> {
+        match self.get_by_sid(id) {
+            Some(Tag::Parameter(parameter)) => Some(parameter),
+            _ => None,
+        }
+    }
+
+    pub fn get_reaction(&self, id: &str) -> Option<&Reaction> {
+        match self.get_by_sid(id) {
+            Some(Tag::Reaction(reaction)) => Some(reaction),
+            _ => None,
+        }
+    }
+
+    objects_from_list!(
+        ListOfSpecies,
+        list_of_species,
+        Species,
+        species,
+        species_iter
+    );
+    objects_from_list!(
+        ListOfReactions,
+        list_of_reactions,
+        Reaction,
+        reactions,
+        reactions_iter
+    );
     objects_from_list!(
         ListOfUnitDefinitions,
         list_of_unit_definitions,
         UnitDefinition,
-        unit_definitions
+        unit_definitions,
+        unit_definitions_iter
     );
     objects_from_list!(
         ListOfCompartments,
         list_of_compartments,
         Compartment,
-        compartments
+        compartments,
+        compartments_iter
+    );
+    objects_from_list!(
+        ListOfParameters,
+        list_of_parameters,
+        Parameter,
+        parameters,
+        parameters_iter
     );
-    objects_from_list!(ListOfParameters, list_of_parameters, Parameter, parameters);
     objects_from_list!(
         ListOfFunctionDefinitions,
         list_of_function_definitions,
         FunctionDefinition,
-        function_definitions
+        function_definitions,
+        function_definitions_iter
+    );
+    objects_from_list!(
+        ListOfRules,
+        list_of_rules,
+        AssignmentRule,
+        assignment_rules,
+        assignment_rules_iter
+    );
+    objects_from_list!(
+        ListOfRules,
+        list_of_rules,
+        RateRule,
+        rate_rules,
+        rate_rules_iter
+    );
+    objects_from_list!(
+        ListOfRules,
+        list_of_rules,
+        AlgebraicRule,
+        algebraic_rules,
+        algebraic_rules_iter
     );
-    objects_from_list!(ListOfRules, list_of_rules, AssignmentRule, assignment_rules);
-    objects_from_list!(ListOfRules, list_of_rules, RateRule, rate_rules);
     objects_from_list!(
         ListOfInitialAssignments,
         list_of_initial_assignments,
         InitialAssignment,
-        initial_assignments
+        initial_assignments,
+        initial_assignments_iter
+    );
+    objects_from_list!(ListOfEvents, list_of_events, Event, events, events_iter);
+    objects_from_list!(
+        ListOfConstraints,
+        list_of_constraints,
+        Constraint,
+        constraints,
+        constraints_iter
+    );
+    objects_from_list!(
+        ListOfCompartmentTypes,
+        list_of_compartment_types,
+        CompartmentType,
+        compartment_types,
+        compartment_types_iter
+    );
+    objects_from_list!(
+        ListOfSpeciesTypes,
+        list_of_species_types,
+        SpeciesType,
+        species_types,
+        species_types_iter
     );
 
     pub fn function_definition_math(&self) -> HashMap<String, Vec<MathNode>> {
         let mut tags = HashMap::new();
-        for function_definition in self.function_definitions() {
+        for function_definition in self.function_definitions_iter() {
             let id = function_definition.id.as_ref().unwrap().to_owned();
             if let Some(math_tag) = function_definition.math_tag(&self) {
                 tags.insert(id, math_tag.nodes);
@@ -119,7 +278,7 @@ impl Model {
 
     pub fn assignment_rule_math(&self) -> HashMap<String, Vec<MathNode>> {
         let mut tags = HashMap::new();
-        for assignment_rule in self.assignment_rules() {
+        for assignment_rule in self.assignment_rules_iter() {
             let variable = assignment_rule.variable.as_ref().unwrap().to_owned();
             let math_tag = assignment_rule.math_tag(&self).unwrap();
             tags.insert(variable, math_tag.nodes);
@@ -129,7 +288,7 @@ impl Model {
 
     pub fn all_reactants(&self) -> HashMap<String, Vec<SpeciesReference>> {
         let mut result = HashMap::new();
-        let reactions = self.reactions();
+        let reactions = self.reactions_iter();
         for reaction in reactions {
             let rxn_id = reaction.id.as_ref().unwrap().to_owned();
             result.insert(rxn_id, reaction.reactants(&self));
@@ -140,7 +299,7 @@ impl Model {
 
     pub fn all_reactant_ids(&self) -> HashMap<String, Vec<String>> {
         let mut result = HashMap::new();
-        let reactions = self.reactions();
+        let reactions = self.reactions_iter();
         for reaction in reactions {
             let rxn_id = reaction.id.as_ref().unwrap().to_owned();
             result.insert(rxn_id, reaction.reactant_ids(&self));
@@ -151,7 +310,7 @@ impl Model {
 
     pub fn all_products(&self) -> HashMap<String, Vec<SpeciesReference>> {
         let mut result = HashMap::new();
-        let reactions = self.reactions();
+        let reactions = self.reactions_iter();
         for reaction in reactions {
             let rxn_id = reaction.id.as_ref().unwrap().to_owned();
             result.insert(rxn_id, reaction.products(&self));
@@ -162,7 +321,7 @@ impl Model {
 
     pub fn all_product_ids(&self) -> HashMap<String, Vec<String>> {
         let mut result = HashMap::new();
-        let reactions = self.reactions();
+        let reactions = self.reactions_iter();
         for reaction in reactions {
             let rxn_id = reaction.id.as_ref().unwrap().to_owned();
             result.insert(rxn_id, reaction.product_ids(&self));
@@ -171,12 +330,15 @@ impl Model {
         result
     }
 
+    // Reactions without a kinetic law are left out
     pub fn all_kinetic_laws(&self) -> HashMap<String, MathTag> {
         let mut result = HashMap::new();
-        let reactions = self.reactions();
+        let reactions = self.reactions_iter();
         for reaction in reactions {
             let rxn_id = reaction.id.as_ref().unwrap().to_owned();
-            result.insert(rxn_id, reaction.kinetic_law(&self).unwrap());
+            if let Some(kinetic_law) = reaction.kinetic_law_ref(self) {
+                result.insert(rxn_id, kinetic_law.clone());
+            }
         }
 
         result
@@ -184,11 +346,109 @@ impl Model {
 
     pub fn local_parameter_values(&self) -> HashMap<String, HashMap<String, f64>> {
         let mut hm: HashMap<String, HashMap<String, f64>> = HashMap::new();
-        for reaction in self.reactions() {
+        for reaction in self.reactions_iter() {
             let rxn_id = reaction.id.as_ref().unwrap().to_owned();
             let local_parameters = reaction.local_parameter_values(self);
             hm.insert(rxn_id, local_parameters);
         }
         hm
     }
+
+    // Controlled vocabulary terms of the model and all of its elements, keyed by metaid
+    pub fn cv_terms(&self) -> Result<HashMap<String, Vec<CvTerm>>, SbmlError> {
+        let mut result = HashMap::new();
+        let annotated = self
+            .nodes
+            .iter()
+            .map(|tag| (tag.metaid(), tag.annotation()))
+            .chain(std::iter::once((
+                self.meta_id.as_ref(),
+                self.annotation.as_ref(),
+            )));
+        for (metaid, annotation) in annotated {
+            if let (Some(metaid), Some(annotation)) = (metaid, annotation) {
+                let mut terms = miriam::parse_cv_terms(annotation)?;
+                if let Some(terms) = terms.remove(metaid) {
+                    result.insert(metaid.to_owned(), terms);
+                }
+            }
+        }
+        Ok(result)
+    }
+
+    // Species with a controlled vocabulary term referencing a resource that
+    // contains the pattern, e.g. "CHEBI:" for all species annotated with a ChEBI id
+    pub fn species_with_resource(&self, pattern: &str) -> Result<Vec<Species>, SbmlError> {
+        let mut result = Vec::new();
+        for species in self.species_iter() {
+            if species
+                .cv_terms()?
+                .iter()
+                .any(|term| term.references(pattern))
+            {
+                result.push(species.clone());
+            }
+        }
+        Ok(result)
+    }
+
+    // Creators and creation/modification dates from the model's RDF annotation
+    pub fn history(&self) -> Result<Option<ModelHistory>, SbmlError> {
+        match (&self.meta_id, &self.annotation) {
+            (Some(metaid), Some(annotation)) => miriam::parse_history(annotation, metaid),
+            _ => Ok(None),
+        }
+    }
+
+    // Replaces the history in the model's annotation, None removes it.
+    // The model needs a metaid for the RDF to refer to.
+    pub fn set_history(&mut self, history: Option<&ModelHistory>) -> Result<(), SbmlError> {
+        let metaid = self
+            .meta_id
+            .as_ref()
+            .ok_or_else(|| SbmlError::MissingRequiredAttribute {
+                attribute: "metaid".to_string(),
+                location: Location::at("/sbml/model".to_string()),
+            })?;
+        let annotation = miriam::set_history(self.annotation.as_deref(), metaid, history)?;
+        self.annotation = if annotation.is_empty() {
+            None
+        } else {
+            Some(annotation)
+        };
+        Ok(())
+    }
+}
+
+// Mutable access to the elements of a model, from Model::nodes_mut. Rebuilds
+// the id index when dropped.
+pub struct NodesMut<'a> {
+    model: &'a mut Model,
+}
+
+impl Deref for NodesMut<'_> {
+    type Target = Vec<Tag>;
+
+    fn deref(&self) -> &Vec<Tag> {
+        &self.model.nodes
+    }
+}
+
+impl DerefMut for NodesMut<'_> {
+    fn deref_mut(&mut self) -> &mut Vec<Tag> {
+        &mut self.model.nodes
+    }
+}
+
+impl Drop for NodesMut<'_> {
+    fn drop(&mut self) {
+        self.model.reindex();
+    }
+}
+
+fn sid(tag: &Tag) -> Option<&String> {
+    match tag {
+        Tag::UnitDefinition(_) | Tag::LocalParameter(_) => None,
+        _ => tag.id(),
+    }
 }
@@ -3,6 +3,8 @@ use super::tag::TagIndex;
 #[derive(Clone, Debug, Default)]
 pub struct ListOfParameters {
     pub parameters: Vec<TagIndex>,
+    pub notes: Option<String>,
+    pub annotation: Option<String>,
     pub parent: Option<TagIndex>,
 }
 
@@ -15,5 +17,7 @@ pub struct Parameter {
     pub units: Option<String>,
     pub sbo_term: Option<String>,
     pub constant: Option<bool>,
+    pub notes: Option<String>,
+    pub annotation: Option<String>,
     pub parent: Option<TagIndex>,
 }
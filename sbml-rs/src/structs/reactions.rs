@@ -7,58 +7,64 @@ use std::collections::HashMap;
 #[derive(Clone, Debug, Default)]
 pub struct ListOfReactions {
     pub reactions: Vec<TagIndex>,
+    pub notes: Option<String>,
+    pub annotation: Option<String>,
     pub parent: Option<TagIndex>,
 }
 
 #[derive(Debug, Clone, Default)]
 pub struct Reaction {
     pub id: Option<String>,
+    pub metaid: Option<String>,
     pub list_of_reactants: Option<TagIndex>,
     pub list_of_products: Option<TagIndex>,
     pub list_of_modifiers: Option<TagIndex>,
     pub reversible: Option<bool>,
+    pub fast: Option<bool>,
     pub kinetic_law: Option<TagIndex>,
     pub compartment: Option<String>,
     pub name: Option<String>,
     pub sbo_term: Option<String>,
+    pub notes: Option<String>,
+    pub annotation: Option<String>,
     pub parent: Option<TagIndex>,
 }
 
 impl Reaction {
     pub fn reactants(&self, model: &Model) -> Vec<SpeciesReference> {
-        let mut result = Vec::new();
-        if let Some(reactants_idx) = self.list_of_reactants {
-            if let Tag::ListOfReactants(list_of_reactants) = &model.nodes[reactants_idx] {
-                for reactant_idx in &list_of_reactants.species_references {
-                    if let Tag::SpeciesReference(sp_ref) = &model.nodes[reactant_idx.to_owned()] {
-                        result.push(sp_ref.clone());
-                    }
-                }
-            }
-        }
-        result
+        self.reactants_iter(model).cloned().collect()
+    }
+
+    pub fn reactants_iter<'a>(
+        &self,
+        model: &'a Model,
+    ) -> impl Iterator<Item = &'a SpeciesReference> + 'a {
+        let indices: &[TagIndex] = match self.list_of_reactants.map(|idx| &model.nodes[idx]) {
+            Some(Tag::ListOfReactants(list_of_reactants)) => &list_of_reactants.species_references,
+            _ => &[],
+        };
+        species_references(model, indices)
     }
 
     pub fn reactant_ids(&self, model: &Model) -> Vec<String> {
-        let reactants = &self.reactants(&model);
-        reactants
-            .iter()
+        self.reactants_iter(model)
             .map(|r| r.species.as_ref().unwrap().to_owned())
             .collect::<Vec<String>>()
     }
 
     pub fn products(&self, model: &Model) -> Vec<SpeciesReference> {
-        let mut result = Vec::new();
-        if let Some(products_idx) = self.list_of_products {
-            if let Tag::ListOfProducts(list_of_products) = &model.nodes[products_idx] {
-                for products_idx in &list_of_products.species_references {
-                    if let Tag::SpeciesReference(sp_ref) = &model.nodes[products_idx.to_owned()] {
-                        result.push(sp_ref.clone());
-                    }
-                }
-            }
-        }
-        result
+        self.products_iter(model).cloned().collect()
+    }
+
+    pub fn products_iter<'a>(
+        &self,
+        model: &'a Model,
+    ) -> impl Iterator<Item = &'a SpeciesReference> + 'a {
+        let indices: &[TagIndex] = match self.list_of_products.map(|idx| &model.nodes[idx]) {
+            Some(Tag::ListOfProducts(list_of_products)) => &list_of_products.species_references,
+            _ => &[],
+        };
+        species_references(model, indices)
     }
 
     pub fn modifiers(&self, model: &Model) -> Vec<String> {
@@ -80,25 +86,20 @@ impl Reaction {
     }
 
     pub fn product_ids(&self, model: &Model) -> Vec<String> {
-        let products = &self.products(&model);
-        products
-            .iter()
+        self.products_iter(model)
             .map(|p| p.species.as_ref().unwrap().to_owned())
             .collect::<Vec<String>>()
     }
 
     pub fn kinetic_law(&self, model: &Model) -> Option<MathTag> {
-        let mut result = None;
-        if let Some(kinetic_law_idx) = self.kinetic_law {
-            if let Tag::KineticLaw(kinetic_law) = &model.nodes[kinetic_law_idx] {
-                if let Some(math_tag_idx) = kinetic_law.math {
-                    if let Tag::MathTag(math_tag) = &model.nodes[math_tag_idx] {
-                        result = Some(math_tag.clone());
-                    }
-                }
-            }
+        self.kinetic_law_ref(model).cloned()
+    }
+
+    pub fn kinetic_law_ref<'a>(&self, model: &'a Model) -> Option<&'a MathTag> {
+        match &model.nodes[self.kinetic_law?] {
+            Tag::KineticLaw(kinetic_law) => model.math_tag_at(kinetic_law.math),
+            _ => None,
         }
-        result
     }
 
     pub fn local_parameters(&self, model: &Model) -> Vec<LocalParameter> {
@@ -122,65 +123,136 @@ impl Reaction {
     }
 }
 
+fn species_references<'a>(
+    model: &'a Model,
+    indices: &'a [TagIndex],
+) -> impl Iterator<Item = &'a SpeciesReference> + 'a {
+    indices
+        .iter()
+        .filter_map(move |idx| match &model.nodes[*idx] {
+            Tag::SpeciesReference(sp_ref) => Some(sp_ref),
+            _ => None,
+        })
+}
+
 #[derive(Clone, Debug, Default)]
 pub struct ListOfReactants {
     pub species_references: Vec<TagIndex>,
+    pub notes: Option<String>,
+    pub annotation: Option<String>,
     pub parent: Option<TagIndex>,
 }
 
 #[derive(Clone, Debug, Default)]
 pub struct ListOfProducts {
     pub species_references: Vec<TagIndex>,
+    pub notes: Option<String>,
+    pub annotation: Option<String>,
     pub parent: Option<TagIndex>,
 }
 
 #[derive(Clone, Debug, Default)]
 pub struct SpeciesReference {
     pub id: Option<String>,
+    pub metaid: Option<String>,
     pub name: Option<String>,
     pub species: Option<String>,
     pub constant: Option<bool>,
     pub sbo_term: Option<String>,
     pub stoichiometry: Option<f64>,
+    pub stoichiometry_math: Option<TagIndex>, // Level 2 only
+    pub notes: Option<String>,
+    pub annotation: Option<String>,
     pub parent: Option<TagIndex>,
 }
 
+impl SpeciesReference {
+    pub fn stoichiometry_math(&self, model: &Model) -> Option<MathTag> {
+        let mut result = None;
+        if let Some(idx) = self.stoichiometry_math {
+            if let Tag::StoichiometryMath(stoichiometry_math) = &model.nodes[idx] {
+                result = stoichiometry_math.math_tag(model);
+            }
+        }
+        result
+    }
+}
+
+// Level 2 only, a stoichiometry given by a formula
+#[derive(Clone, Debug, Default)]
+pub struct StoichiometryMath {
+    pub metaid: Option<String>,
+    pub sbo_term: Option<String>,
+    pub math: Option<TagIndex>,
+    pub notes: Option<String>,
+    pub annotation: Option<String>,
+    pub parent: Option<TagIndex>,
+}
+
+impl StoichiometryMath {
+    pub fn math_tag(&self, model: &Model) -> Option<MathTag> {
+        self.math_tag_ref(model).cloned()
+    }
+
+    pub fn math_tag_ref<'a>(&self, model: &'a Model) -> Option<&'a MathTag> {
+        model.math_tag_at(self.math)
+    }
+}
+
 #[derive(Clone, Debug, Default)]
 pub struct ListOfModifiers {
     pub modifier_species_references: Vec<TagIndex>,
+    pub notes: Option<String>,
+    pub annotation: Option<String>,
     pub parent: Option<TagIndex>,
 }
 
 #[derive(Clone, Debug, Default)]
 pub struct ModifierSpeciesReference {
     pub id: Option<String>,
+    pub metaid: Option<String>,
     pub name: Option<String>,
     pub species: Option<String>,
     pub sbo_term: Option<String>,
+    pub notes: Option<String>,
+    pub annotation: Option<String>,
     pub parent: Option<TagIndex>,
 }
 
 #[derive(Clone, Debug, Default)]
 pub struct KineticLaw {
+    pub metaid: Option<String>,
     pub math: Option<TagIndex>,
     pub list_of_local_parameters: Option<TagIndex>,
+    pub notes: Option<String>,
+    pub annotation: Option<String>,
     pub parent: Option<TagIndex>,
     pub sbo_term: Option<String>,
+    // Level 1 and Level 2 Version 1 only
+    pub time_units: Option<String>,
+    pub substance_units: Option<String>,
 }
 
 impl KineticLaw {
     pub fn local_parameters(&self, model: &Model) -> Vec<LocalParameter> {
-        let mut result = Vec::<LocalParameter>::new();
-        if let Some(idx) = self.list_of_local_parameters {
-            if let Tag::ListOfLocalParameters(list_of_local_parameters) = &model.nodes[idx] {
-                for param_idx in &list_of_local_parameters.local_parameters {
-                    if let Tag::LocalParameter(param) = &model.nodes[param_idx.to_owned()] {
-                        result.push(param.clone());
-                    }
-                }
-            }
-        }
-        result
+        self.local_parameters_iter(model).cloned().collect()
+    }
+
+    pub fn local_parameters_iter<'a>(
+        &self,
+        model: &'a Model,
+    ) -> impl Iterator<Item = &'a LocalParameter> + 'a {
+        let indices: &[TagIndex] = match self.list_of_local_parameters.map(|idx| &model.nodes[idx])
+        {
+            Some(Tag::ListOfLocalParameters(list)) => &list.local_parameters,
+            _ => &[],
+        };
+        indices
+            .iter()
+            .filter_map(move |idx| match &model.nodes[*idx] {
+                Tag::LocalParameter(param) => Some(param),
+                _ => None,
+            })
     }
 
     pub fn local_parameter_values(&self, model: &Model) -> HashMap<String, f64> {
@@ -199,14 +271,20 @@ impl KineticLaw {
 #[derive(Clone, Debug, Default)]
 pub struct ListOfLocalParameters {
     pub local_parameters: Vec<TagIndex>,
+    pub notes: Option<String>,
+    pub annotation: Option<String>,
     pub parent: Option<TagIndex>,
 }
 
 #[derive(Clone, Debug, Default)]
 pub struct LocalParameter {
     pub id: Option<String>,
+    pub metaid: Option<String>,
+    pub name: Option<String>,
     pub value: Option<f64>,
     pub units: Option<String>,
     pub sbo_term: Option<String>,
+    pub notes: Option<String>,
+    pub annotation: Option<String>,
     pub parent: Option<TagIndex>,
 }
@@ -12,6 +12,10 @@ pub struct Root {
     pub list_of_function_definitions: Option<TagIndex>,
     pub list_of_initial_assignments: Option<TagIndex>,
     pub list_of_rules: Option<TagIndex>,
+    pub list_of_events: Option<TagIndex>,
+    pub list_of_constraints: Option<TagIndex>,
+    pub list_of_compartment_types: Option<TagIndex>,
+    pub list_of_species_types: Option<TagIndex>,
 }
 
 impl fmt::Display for Root {
@@ -1,9 +1,12 @@
-use crate::{MathTag, Model, Tag, TagIndex};
+use crate::{MathTag, Model, TagIndex};
 
 #[derive(Clone, Debug, Default)]
 pub struct ListOfRules {
     pub assignment_rules: Vec<TagIndex>,
     pub rate_rules: Vec<TagIndex>,
+    pub algebraic_rules: Vec<TagIndex>,
+    pub notes: Option<String>,
+    pub annotation: Option<String>,
     pub parent: Option<TagIndex>,
 }
 
@@ -14,18 +17,18 @@ pub struct AssignmentRule {
     pub variable: Option<String>,
     pub sbo_term: Option<String>,
     pub math: Option<TagIndex>,
+    pub notes: Option<String>,
+    pub annotation: Option<String>,
     pub parent: Option<TagIndex>,
 }
 
 impl AssignmentRule {
     pub fn math_tag(&self, model: &Model) -> Option<MathTag> {
-        let mut result = None;
-        if let Some(math_tag_idx) = self.math {
-            if let Tag::MathTag(math_tag) = &model.nodes[math_tag_idx] {
-                result = Some(math_tag.clone());
-            }
-        }
-        result
+        self.math_tag_ref(model).cloned()
+    }
+
+    pub fn math_tag_ref<'a>(&self, model: &'a Model) -> Option<&'a MathTag> {
+        model.math_tag_at(self.math)
     }
 }
 
@@ -36,17 +39,39 @@ pub struct RateRule {
     pub variable: Option<String>,
     pub sbo_term: Option<String>,
     pub math: Option<TagIndex>,
+    pub notes: Option<String>,
+    pub annotation: Option<String>,
     pub parent: Option<TagIndex>,
 }
 
 impl RateRule {
     pub fn math_tag(&self, model: &Model) -> Option<MathTag> {
-        let mut result = None;
-        if let Some(math_tag_idx) = self.math {
-            if let Tag::MathTag(math_tag) = &model.nodes[math_tag_idx] {
-                result = Some(math_tag.clone());
-            }
-        }
-        result
+        self.math_tag_ref(model).cloned()
+    }
+
+    pub fn math_tag_ref<'a>(&self, model: &'a Model) -> Option<&'a MathTag> {
+        model.math_tag_at(self.math)
+    }
+}
+
+// 0 = math, without a variable being determined by it
+#[derive(Debug, Default, Clone)]
+pub struct AlgebraicRule {
+    pub id: Option<String>,
+    pub metaid: Option<String>,
+    pub sbo_term: Option<String>,
+    pub math: Option<TagIndex>,
+    pub notes: Option<String>,
+    pub annotation: Option<String>,
+    pub parent: Option<TagIndex>,
+}
+
+impl AlgebraicRule {
+    pub fn math_tag(&self, model: &Model) -> Option<MathTag> {
+        self.math_tag_ref(model).cloned()
+    }
+
+    pub fn math_tag_ref<'a>(&self, model: &'a Model) -> Option<&'a MathTag> {
+        model.math_tag_at(self.math)
     }
 }
@@ -1,9 +1,33 @@
 use super::model::Model;
 use super::tag::TagIndex;
+use crate::miriam::{self, CvTerm};
+use crate::{Location, SbmlError};
+
+// Level 2 only, removed in Level 3
+#[derive(Clone, Debug, Default)]
+pub struct ListOfSpeciesTypes {
+    pub species_types: Vec<TagIndex>,
+    pub notes: Option<String>,
+    pub annotation: Option<String>,
+    pub parent: Option<TagIndex>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct SpeciesType {
+    pub id: Option<String>,
+    pub metaid: Option<String>,
+    pub name: Option<String>,
+    pub sbo_term: Option<String>,
+    pub notes: Option<String>,
+    pub annotation: Option<String>,
+    pub parent: Option<TagIndex>,
+}
 
 #[derive(Clone, Debug, Default)]
 pub struct ListOfSpecies {
     pub species: Vec<TagIndex>,
+    pub notes: Option<String>,
+    pub annotation: Option<String>,
     pub parent: Option<TagIndex>,
 }
 
@@ -21,6 +45,11 @@ pub struct Species {
     pub boundary_condition: Option<bool>,
     pub constant: Option<bool>,
     pub conversion_factor: Option<String>,
+    pub species_type: Option<String>,
+    pub charge: Option<i32>,
+    pub spatial_size_units: Option<String>,
+    pub notes: Option<String>,
+    pub annotation: Option<String>,
     pub parent: Option<TagIndex>,
 }
 
@@ -29,16 +58,54 @@ impl Species {
         self.id.to_owned().unwrap()
     }
 
-    pub fn compartment_size(&self, model: &Model) -> Result<f64, String> {
-        let compartments = model.compartments();
-        for compartment in compartments {
-            let compartment_id = self.compartment.as_ref().unwrap().to_owned();
-            if compartment_id == compartment.id.unwrap() {
-                if let Some(compartment_size) = compartment.size {
-                    return Ok(compartment_size);
-                }
+    // Controlled vocabulary terms from the RDF describing this species' metaid
+    pub fn cv_terms(&self) -> Result<Vec<CvTerm>, SbmlError> {
+        if let (Some(metaid), Some(annotation)) = (&self.meta_id, &self.annotation) {
+            if let Some(terms) = miriam::parse_cv_terms(annotation)?.remove(metaid) {
+                return Ok(terms);
             }
         }
-        Err("Not found".to_string())
+        Ok(Vec::new())
+    }
+
+    // Replaces the RDF in the annotation with one holding these terms
+    pub fn set_cv_terms(&mut self, cv_terms: &[CvTerm]) -> Result<(), SbmlError> {
+        let metaid = self
+            .meta_id
+            .as_ref()
+            .ok_or_else(|| SbmlError::MissingRequiredAttribute {
+                attribute: "metaid".to_string(),
+                location: Location::at(format!(
+                    "/sbml/model/listOfSpecies/species[@id='{}']",
+                    self.id.as_deref().unwrap_or_default()
+                )),
+            })?;
+        let annotation = miriam::set_cv_terms(self.annotation.as_deref(), metaid, cv_terms)?;
+        self.annotation = if annotation.is_empty() {
+            None
+        } else {
+            Some(annotation)
+        };
+        Ok(())
+    }
+
+    // The species' own conversion factor, or the model's if it has none
+    pub fn effective_conversion_factor(&self, model: &Model) -> Option<String> {
+        self.conversion_factor
+            .clone()
+            .or_else(|| model.conversion_factor.clone())
+    }
+
+    pub fn compartment_size(&self, model: &Model) -> Result<f64, String> {
+        let compartment_id = self
+            .compartment
+            .as_ref()
+            .ok_or_else(|| format!("Species '{}' has no compartment", self.id()))?;
+        let compartment = model
+            .get_compartment(compartment_id)
+            .ok_or_else(|| format!("Compartment '{}' does not exist", compartment_id))?;
+        compartment
+            .size
+            .ok_or_else(|| format!("Compartment '{}' has no size", compartment_id))
     }
 }
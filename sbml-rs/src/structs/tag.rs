@@ -1,6 +1,8 @@
 pub type TagIndex = usize;
 
 use super::compartments::*;
+use super::constraints::*;
+use super::events::*;
 use super::function_definitions::*;
 use super::initial_assignments::*;
 use super::math::*;
@@ -43,4 +45,147 @@ pub enum Tag {
     ListOfRules(ListOfRules),
     AssignmentRule(AssignmentRule),
     RateRule(RateRule),
+    AlgebraicRule(AlgebraicRule),
+    ListOfEvents(ListOfEvents),
+    Event(Event),
+    Trigger(Trigger),
+    Delay(Delay),
+    Priority(Priority),
+    ListOfEventAssignments(ListOfEventAssignments),
+    EventAssignment(EventAssignment),
+    ListOfConstraints(ListOfConstraints),
+    Constraint(Constraint),
+    ListOfCompartmentTypes(ListOfCompartmentTypes),
+    CompartmentType(CompartmentType),
+    ListOfSpeciesTypes(ListOfSpeciesTypes),
+    SpeciesType(SpeciesType),
+    StoichiometryMath(StoichiometryMath),
+}
+
+// Expands to a match that evaluates $body with $t bound to the element
+// inside every tag that carries the SBase fields
+macro_rules! with_sbase {
+    ($tag: expr, $t: ident => $body: expr, $root: expr) => {
+        match $tag {
+            Tag::Root(_) | Tag::MathTag(_) => $root,
+            Tag::ListOfUnitDefinitions($t) => $body,
+            Tag::UnitDefinition($t) => $body,
+            Tag::ListOfUnits($t) => $body,
+            Tag::Unit($t) => $body,
+            Tag::ListOfCompartments($t) => $body,
+            Tag::Compartment($t) => $body,
+            Tag::ListOfParameters($t) => $body,
+            Tag::Parameter($t) => $body,
+            Tag::ListOfSpecies($t) => $body,
+            Tag::Species($t) => $body,
+            Tag::ListOfReactions($t) => $body,
+            Tag::Reaction($t) => $body,
+            Tag::ListOfReactants($t) => $body,
+            Tag::ListOfProducts($t) => $body,
+            Tag::SpeciesReference($t) => $body,
+            Tag::ListOfModifiers($t) => $body,
+            Tag::ModifierSpeciesReference($t) => $body,
+            Tag::ListOfLocalParameters($t) => $body,
+            Tag::LocalParameter($t) => $body,
+            Tag::KineticLaw($t) => $body,
+            Tag::ListOfFunctionDefinitions($t) => $body,
+            Tag::FunctionDefinition($t) => $body,
+            Tag::ListOfInitialAssignments($t) => $body,
+            Tag::InitialAssignment($t) => $body,
+            Tag::ListOfRules($t) => $body,
+            Tag::AssignmentRule($t) => $body,
+            Tag::RateRule($t) => $body,
+            Tag::AlgebraicRule($t) => $body,
+            Tag::ListOfEvents($t) => $body,
+            Tag::Event($t) => $body,
+            Tag::Trigger($t) => $body,
+            Tag::Delay($t) => $body,
+            Tag::Priority($t) => $body,
+            Tag::ListOfEventAssignments($t) => $body,
+            Tag::EventAssignment($t) => $body,
+            Tag::ListOfConstraints($t) => $body,
+            Tag::Constraint($t) => $body,
+            Tag::ListOfCompartmentTypes($t) => $body,
+            Tag::CompartmentType($t) => $body,
+            Tag::ListOfSpeciesTypes($t) => $body,
+            Tag::SpeciesType($t) => $body,
+            Tag::StoichiometryMath($t) => $body,
+        }
+    };
+}
+
+impl Tag {
+    pub fn notes(&self) -> Option<&String> {
+        with_sbase!(self, t => t.notes.as_ref(), None)
+    }
+
+    pub fn parent(&self) -> Option<TagIndex> {
+        match self {
+            Tag::MathTag(t) => t.parent,
+            _ => with_sbase!(self, t => t.parent, None),
+        }
+    }
+
+    // Elements whose id is an SId, unit definitions included
+    pub fn id(&self) -> Option<&String> {
+        match self {
+            Tag::UnitDefinition(t) => t.id.as_ref(),
+            Tag::Compartment(t) => t.id.as_ref(),
+            Tag::Parameter(t) => t.id.as_ref(),
+            Tag::Species(t) => t.id.as_ref(),
+            Tag::Reaction(t) => t.id.as_ref(),
+            Tag::SpeciesReference(t) => t.id.as_ref(),
+            Tag::ModifierSpeciesReference(t) => t.id.as_ref(),
+            Tag::LocalParameter(t) => t.id.as_ref(),
+            Tag::FunctionDefinition(t) => t.id.as_ref(),
+            Tag::InitialAssignment(t) => t.id.as_ref(),
+            Tag::AssignmentRule(t) => t.id.as_ref(),
+            Tag::RateRule(t) => t.id.as_ref(),
+            Tag::AlgebraicRule(t) => t.id.as_ref(),
+            Tag::Event(t) => t.id.as_ref(),
+            Tag::Constraint(t) => t.id.as_ref(),
+            Tag::CompartmentType(t) => t.id.as_ref(),
+            Tag::SpeciesType(t) => t.id.as_ref(),
+            _ => None,
+        }
+    }
+
+    pub fn annotation(&self) -> Option<&String> {
+        with_sbase!(self, t => t.annotation.as_ref(), None)
+    }
+
+    pub fn set_annotation(&mut self, annotation: Option<String>) {
+        with_sbase!(self, t => t.annotation = annotation, ())
+    }
+
+    // ListOf* containers are not given a metaid by the parser
+    pub fn metaid(&self) -> Option<&String> {
+        match self {
+            Tag::UnitDefinition(t) => t.metaid.as_ref(),
+            Tag::Unit(t) => t.metaid.as_ref(),
+            Tag::Compartment(t) => t.metaid.as_ref(),
+            Tag::Parameter(t) => t.metaid.as_ref(),
+            Tag::Species(t) => t.meta_id.as_ref(),
+            Tag::Reaction(t) => t.metaid.as_ref(),
+            Tag::SpeciesReference(t) => t.metaid.as_ref(),
+            Tag::ModifierSpeciesReference(t) => t.metaid.as_ref(),
+            Tag::LocalParameter(t) => t.metaid.as_ref(),
+            Tag::KineticLaw(t) => t.metaid.as_ref(),
+            Tag::FunctionDefinition(t) => t.metaid.as_ref(),
+            Tag::InitialAssignment(t) => t.metaid.as_ref(),
+            Tag::AssignmentRule(t) => t.metaid.as_ref(),
+            Tag::RateRule(t) => t.metaid.as_ref(),
+            Tag::AlgebraicRule(t) => t.metaid.as_ref(),
+            Tag::Event(t) => t.metaid.as_ref(),
+            Tag::Trigger(t) => t.metaid.as_ref(),
+            Tag::Delay(t) => t.metaid.as_ref(),
+            Tag::Priority(t) => t.metaid.as_ref(),
+            Tag::EventAssignment(t) => t.metaid.as_ref(),
+            Tag::Constraint(t) => t.metaid.as_ref(),
+            Tag::CompartmentType(t) => t.metaid.as_ref(),
+            Tag::SpeciesType(t) => t.metaid.as_ref(),
+            Tag::StoichiometryMath(t) => t.metaid.as_ref(),
+            _ => None,
+        }
+    }
 }
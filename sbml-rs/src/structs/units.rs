@@ -3,19 +3,27 @@ use super::tag::TagIndex;
 #[derive(Clone, Debug, Default)]
 pub struct ListOfUnitDefinitions {
     pub unit_definitions: Vec<TagIndex>, // UnitDefinitions
+    pub notes: Option<String>,
+    pub annotation: Option<String>,
     pub parent: Option<TagIndex>,
 }
 
 #[derive(Clone, Debug, Default)]
 pub struct UnitDefinition {
     pub id: Option<String>,
+    pub metaid: Option<String>,
+    pub name: Option<String>,
     pub list_of_units: Option<TagIndex>, // ListOfUnits
+    pub notes: Option<String>,
+    pub annotation: Option<String>,
     pub parent: Option<TagIndex>,
 }
 
 #[derive(Clone, Debug, Default)]
 pub struct ListOfUnits {
     pub units: Vec<TagIndex>, // Units
+    pub notes: Option<String>,
+    pub annotation: Option<String>,
     pub parent: Option<TagIndex>,
 }
 
@@ -25,12 +33,15 @@ pub struct Unit {
     pub exponent: Option<f64>,
     pub scale: Option<i64>,
     pub multiplier: Option<f64>,
+    pub metaid: Option<String>,
+    pub notes: Option<String>,
+    pub annotation: Option<String>,
     pub parent: Option<TagIndex>,
 }
 
-#[derive(Clone, Debug)]
-#[allow(dead_code)]
-enum UnitSId {
+// The predefined unit kinds a Unit can be built from
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum UnitSId {
     Ampere,
     Avogadro,
     Coulomb,
@@ -65,3 +76,86 @@ enum UnitSId {
     Watt,
     Second,
 }
+
+impl UnitSId {
+    // Level 1 and 2 also spell litre and metre the American way
+    pub fn from_name(name: &str) -> Option<UnitSId> {
+        let kind = match name {
+            "ampere" => UnitSId::Ampere,
+            "avogadro" => UnitSId::Avogadro,
+            "coulomb" => UnitSId::Coulomb,
+            "gray" => UnitSId::Gray,
+            "joule" => UnitSId::Joule,
+            "litre" => UnitSId::Litre,
+            "mole" => UnitSId::Mole,
+            "radian" => UnitSId::Radian,
+            "steradian" => UnitSId::Steradian,
+            "weber" => UnitSId::Weber,
+            "dimensionless" => UnitSId::Dimensionless,
+            "henry" => UnitSId::Henry,
+            "katal" => UnitSId::Katal,
+            "lumen" => UnitSId::Lumen,
+            "newton" => UnitSId::Newton,
+            "tesla" => UnitSId::Tesla,
+            "becquerel" => UnitSId::Becquerel,
+            "farad" => UnitSId::Farad,
+            "hertz" => UnitSId::Hertz,
+            "kelvin" => UnitSId::Kelvin,
+            "lux" => UnitSId::Lux,
+            "ohm" => UnitSId::Ohm,
+            "siemens" => UnitSId::Siemens,
+            "volt" => UnitSId::Volt,
+            "candela" => UnitSId::Candela,
+            "gram" => UnitSId::Gram,
+            "item" => UnitSId::Item,
+            "kilogram" => UnitSId::Kilogram,
+            "metre" => UnitSId::Metre,
+            "pascal" => UnitSId::Pascal,
+            "sievert" => UnitSId::Sievert,
+            "watt" => UnitSId::Watt,
+            "second" => UnitSId::Second,
+            "liter" => UnitSId::Litre,
+            "meter" => UnitSId::Metre,
+            _ => return None,
+        };
+        Some(kind)
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            UnitSId::Ampere => "ampere",
+            UnitSId::Avogadro => "avogadro",
+            UnitSId::Coulomb => "coulomb",
+            UnitSId::Gray => "gray",
+            UnitSId::Joule => "joule",
+            UnitSId::Litre => "litre",
+            UnitSId::Mole => "mole",
+            UnitSId::Radian => "radian",
+            UnitSId::Steradian => "steradian",
+            UnitSId::Weber => "weber",
+            UnitSId::Dimensionless => "dimensionless",
+            UnitSId::Henry => "henry",
+            UnitSId::Katal => "katal",
+            UnitSId::Lumen => "lumen",
+            UnitSId::Newton => "newton",
+            UnitSId::Tesla => "tesla",
+            UnitSId::Becquerel => "becquerel",
+            UnitSId::Farad => "farad",
+            UnitSId::Hertz => "hertz",
+            UnitSId::Kelvin => "kelvin",
+            UnitSId::Lux => "lux",
+            UnitSId::Ohm => "ohm",
+            UnitSId::Siemens => "siemens",
+            UnitSId::Volt => "volt",
+            UnitSId::Candela => "candela",
+            UnitSId::Gram => "gram",
+            UnitSId::Item => "item",
+            UnitSId::Kilogram => "kilogram",
+            UnitSId::Metre => "metre",
+            UnitSId::Pascal => "pascal",
+            UnitSId::Sievert => "sievert",
+            UnitSId::Watt => "watt",
+            UnitSId::Second => "second",
+        }
+    }
+}
@@ -1,8 +1,11 @@
-use crate::{MathTag, Model, Tag};
-use mathml_rs::{self, Apply, Ci, MathNode, Op, OpNode};
-use std::collections::HashMap;
+use crate::{Expr, Location, MathTag, Model, SbmlError, Tag, TagIndex};
+use mathml_rs::{Apply, Ci, MathNode, Op, OpNode};
+use std::collections::{HashMap, HashSet};
 
-pub fn transform(mut model: Model) -> Result<Model, Vec<String>> {
+mod units;
+pub use units::{convert_units, UnitSystem};
+
+pub fn transform(mut model: Model) -> Result<Model, SbmlError> {
     // replace all S1 that have hasOnlySubstanceUnits = false with (S1 / C1)
     // so that species always refer to their amounts
     model = convert_species_to_amounts(model)?;
@@ -16,95 +19,73 @@ pub fn transform(mut model: Model) -> Result<Model, Vec<String>> {
     // modified speciesRateRule = C * speciesRateRule + (S/C) * compartmentRateRule
     model = transform_species_rate_rules(model)?;
 
+    // Reactions change a species with a conversion factor by the factor
+    // times its stoichiometry
+    model = apply_conversion_factors(model)?;
+
     Ok(model)
 }
 
-// Converts all S to S/C in all MathML elements
-pub fn convert_species_to_amounts(mut model: Model) -> Result<Model, Vec<String>> {
-    let species = model.species();
+// Converts all S to S/C in all MathML elements, i.e. in kinetic laws,
+// initial assignments, events and assignment, rate and algebraic rules
+pub fn convert_species_to_amounts(mut model: Model) -> Result<Model, SbmlError> {
+    let compartment_ids = model
+        .compartments_iter()
+        .filter_map(|c| c.id.as_ref())
+        .collect::<HashSet<&String>>();
     let mut species_compartment_id = HashMap::<String, String>::new();
-    for sp in species {
-        if let Some(species_id) = sp.id {
+    for sp in model.species_iter() {
+        if let Some(species_id) = &sp.id {
             if Some(false) == sp.has_only_substance_units {
-                if let Some(compartment_id) = sp.compartment {
-                    species_compartment_id.insert(species_id, compartment_id);
+                if let Some(compartment_id) = &sp.compartment {
+                    // S / C needs C to exist
+                    if !compartment_ids.contains(compartment_id) {
+                        return Err(SbmlError::DanglingReference {
+                            attribute: "compartment".to_string(),
+                            value: compartment_id.clone(),
+                            location: species_location(species_id),
+                        });
+                    }
+                    species_compartment_id.insert(species_id.clone(), compartment_id.clone());
                 }
             }
         }
     }
 
-    let mut new_nodes = model.nodes.clone();
-
-    for i in 0..model.nodes.len() {
-        match &model.nodes[i] {
-            // perform replacement in each MathTag
-            Tag::MathTag(math_tag) => {
-                for j in 0..math_tag.nodes.len() {
-                    match &math_tag.nodes[j] {
-                        // replace each Ci that refers to a species
-                        // that has hasOnlySubstanceUnits = false
-                        // with Species / Compartment
-                        MathNode::Ci(ci) => {
-                            if let Some(species_id) = &ci.name {
-                                // check if the species is in the hashmap made earlier
-                                if let Some(compartment) = species_compartment_id.get(species_id) {
-                                    // if it is, make changes to the copy
-                                    if let Tag::MathTag(math_tag_copy) = &mut new_nodes[i] {
-                                        // replace Species Ci node with an Apply node and insert
-                                        // Species Ci, Divide Op and Compartment Ci nodes at the end
-                                        // create nodes Apply, Divide and Compartment
-                                        let mut species_math_node = math_tag_copy.nodes[j].clone();
-                                        let mut apply = Apply::default();
-                                        let mut divide = OpNode::default();
-                                        divide.op = Some(Op::Divide);
-                                        let mut compartment = Ci::with_name(compartment.clone());
-
-                                        // set child and parent pointers
-                                        let length = math_tag_copy.nodes.len();
-                                        apply.parent = ci.parent;
-                                        apply.children = vec![length, length + 1, length + 2];
-                                        apply.operator = Some(length);
-                                        apply.operands = vec![length + 1, length + 2];
-                                        divide.parent = Some(j);
-                                        compartment.parent = Some(j);
-                                        if let MathNode::Ci(species) = &mut species_math_node {
-                                            species.parent = Some(j);
-                                        }
-
-                                        let apply_math_node = MathNode::Apply(apply);
-                                        let divide_math_node = MathNode::Op(divide);
-                                        let compartment_math_node =
-                                            MathNode::Ci(compartment.clone());
-                                        math_tag_copy.nodes[j] = apply_math_node;
-                                        math_tag_copy.nodes.push(divide_math_node);
-                                        math_tag_copy.nodes.push(species_math_node);
-                                        math_tag_copy.nodes.push(compartment_math_node);
-
-                                        //println!(
-                                        //"Changed {} to {}/{}",
-                                        //species_id, species_id, compartment
-                                        //);
-                                    }
-                                }
-                            }
-                        }
-                        _ => {}
+    let replace = |name: &str| {
+        species_compartment_id
+            .get(name)
+            .map(|compartment_id| Expr::ci(name).div(Expr::ci(compartment_id)))
+    };
+    for tag in model.nodes.iter_mut() {
+        // perform replacement in each MathTag that refers to such a species
+        if let Tag::MathTag(math_tag) = tag {
+            let refers_to_species = math_tag.nodes.iter().any(|node| match node {
+                MathNode::Ci(Ci {
+                    name: Some(name), ..
+                }) => species_compartment_id.contains_key(name),
+                _ => false,
+            });
+            if refers_to_species {
+                match math_tag.to_expr() {
+                    Ok(expr) => {
+                        let parent = math_tag.parent;
+                        *math_tag = expr.replace_ci(&replace).to_math_tag();
+                        math_tag.parent = parent;
                     }
+                    // math that is no expression tree is changed node by node
+                    Err(_) => divide_in_place(math_tag, &species_compartment_id),
                 }
             }
-            _ => {}
         }
     }
 
-    model.nodes = new_nodes;
-
     Ok(model)
 }
 
-pub fn transform_species_rate_rules(mut model: Model) -> Result<Model, Vec<String>> {
-    let species = model.species();
+pub fn transform_species_rate_rules(mut model: Model) -> Result<Model, SbmlError> {
     let mut species_ids = Vec::<String>::new();
-    for sp in &species {
+    for sp in model.species_iter() {
         if Some(false) == sp.has_only_substance_units {
             if let Some(id) = &sp.id {
                 species_ids.push(id.clone());
@@ -114,7 +95,7 @@ pub fn transform_species_rate_rules(mut model: Model) -> Result<Model, Vec<Strin
 
     let mut species_compartment_id = HashMap::<String, String>::new();
     let mut compartment_ids = Vec::<String>::new();
-    for sp in &species {
+    for sp in model.species_iter() {
         if let Some(species_id) = &sp.id {
             if Some(false) == sp.has_only_substance_units {
                 if let Some(compartment_id) = &sp.compartment {
@@ -125,11 +106,10 @@ pub fn transform_species_rate_rules(mut model: Model) -> Result<Model, Vec<Strin
         }
     }
 
-    let rate_rules = model.rate_rules();
     let mut species_rate_rules = HashMap::<String, MathTag>::new();
     let mut compartment_rate_rules = HashMap::<String, MathTag>::new();
 
-    for rate_rule in &rate_rules {
+    for rate_rule in model.rate_rules_iter() {
         // ensure valid variable and math_tag
         if let Some(var) = &rate_rule.variable {
             if let Some(math_tag) = rate_rule.math_tag(&model) {
@@ -145,26 +125,23 @@ pub fn transform_species_rate_rules(mut model: Model) -> Result<Model, Vec<Strin
     let mut transformed_species_rate_rules = HashMap::<String, MathTag>::new();
     for (species_id, species_rate_rule) in species_rate_rules {
         if let Some(compartment_id) = species_compartment_id.get(&species_id) {
-            let transformed_species_rate_rule;
-            if let Some(compartment_rate_rule) = compartment_rate_rules.get(compartment_id) {
-                transformed_species_rate_rule = transform_species_rate_rule(
-                    species_id.clone(),
-                    species_rate_rule,
-                    compartment_id.clone(),
-                    Some(compartment_rate_rule.to_owned()),
-                );
-            } else {
-                transformed_species_rate_rule = transform_species_rate_rule(
-                    species_id.clone(),
-                    species_rate_rule,
-                    compartment_id.clone(),
-                    None,
-                );
-            }
+            let transformed_species_rate_rule = transform_species_rate_rule(
+                species_id.clone(),
+                species_rate_rule,
+                compartment_id.clone(),
+                compartment_rate_rules.get(compartment_id).cloned(),
+            )
+            .map_err(|message| SbmlError::InvalidMath {
+                message,
+                location: rate_rule_location(&species_id),
+            })?;
             transformed_species_rate_rules
                 .insert(species_id.clone(), transformed_species_rate_rule);
         } else {
-            panic!("Species without compartment!");
+            return Err(SbmlError::MissingRequiredAttribute {
+                attribute: "compartment".to_string(),
+                location: species_location(&species_id),
+            });
         }
     }
 
@@ -181,7 +158,11 @@ pub fn transform_species_rate_rules(mut model: Model) -> Result<Model, Vec<Strin
                                 model.nodes[rate_rule_math_idx] =
                                     Tag::MathTag(transformed_math_tag.clone());
                             } else {
-                                panic!("Could not transform math tag for rate rule");
+                                return Err(SbmlError::InvalidMath {
+                                    message: "Could not transform math tag for rate rule"
+                                        .to_string(),
+                                    location: rate_rule_location(var),
+                                });
                             }
                         }
                     }
@@ -194,138 +175,190 @@ pub fn transform_species_rate_rules(mut model: Model) -> Result<Model, Vec<Strin
     Ok(model)
 }
 
-// Takes two MathTags for SpeciesRateRule and CompartmentRateRule and returns
-// modified speciesRateRule = C * speciesRateRule + (S/C) * compartmentRateRule
-pub fn transform_species_rate_rule(
-    species_id: String,
-    species_rate_rule: MathTag,
-    compartment_id: String,
-    compartment_rate_rule: Option<MathTag>,
-) -> MathTag {
-    let mut transformed_species_rate_rule_nodes = Vec::<MathNode>::new();
-    let mut root = mathml_rs::Root::default();
-    root.children = vec![1];
-    transformed_species_rate_rule_nodes.push(MathNode::Root(root));
-
-    let mut species_rate_rule_elements = species_rate_rule.nodes.clone();
-    species_rate_rule_elements.remove(0);
+// Multiplies the stoichiometry of every reactant and product by the effective
+// conversion factor of its species, so that reactions change the species in
+// its own substance units rather than in extent units. The conversion factors
+// are removed afterwards as they are accounted for.
+pub fn apply_conversion_factors(mut model: Model) -> Result<Model, SbmlError> {
+    // ids that math assigns to and ids that it refers to
+    let mut assigned = HashSet::<String>::new();
+    let mut referenced = HashSet::<String>::new();
+    let mut initial_assignments = HashMap::<String, TagIndex>::new();
+    for tag in &model.nodes {
+        let variable = match tag {
+            Tag::InitialAssignment(t) => {
+                if let (Some(symbol), Some(math)) = (&t.symbol, t.math) {
+                    initial_assignments.insert(symbol.clone(), math);
+                }
+                &t.symbol
+            }
+            Tag::AssignmentRule(t) => &t.variable,
+            Tag::RateRule(t) => &t.variable,
+            Tag::EventAssignment(t) => &t.variable,
+            Tag::MathTag(math_tag) => {
+                for node in &math_tag.nodes {
+                    if let MathNode::Ci(Ci {
+                        name: Some(name), ..
+                    }) = node
+                    {
+                        referenced.insert(name.clone());
+                    }
+                }
+                continue;
+            }
+            _ => continue,
+        };
+        if let Some(variable) = variable {
+            assigned.insert(variable.clone());
+        }
+    }
 
-    let a = species_rate_rule_elements.len();
+    // an initial assignment of a factor can use the values of the other
+    // constant parameters
+    let values = model
+        .parameters_iter()
+        .filter(|p| p.constant == Some(true))
+        .filter_map(|p| Some((p.id.clone()?, p.value?)))
+        .filter(|(id, _)| !initial_assignments.contains_key(id))
+        .collect::<HashMap<String, f64>>();
+    let functions = model.function_definition_math();
+
+    let mut factors = HashMap::<String, f64>::new();
+    for sp in model.species_iter() {
+        let (species_id, factor_id) = match (&sp.id, sp.effective_conversion_factor(&model)) {
+            (Some(species_id), Some(factor_id)) => (species_id.clone(), factor_id),
+            _ => continue,
+        };
+        // stoichiometries are numbers, so the factor has to be one as well
+        let parameter = model
+            .get_parameter(&factor_id)
+            .filter(|p| p.constant == Some(true));
+        let factor = match (parameter, initial_assignments.get(&factor_id)) {
+            (Some(_), Some(math)) => model
+                .math_tag_at(Some(*math))
+                .and_then(|math_tag| math_tag.evaluate(&values, &functions).ok()),
+            (Some(parameter), None) => parameter.value,
+            (None, _) => None,
+        };
+        let factor = factor.ok_or_else(|| SbmlError::InvalidAttributeValue {
+            attribute: "conversionFactor".to_string(),
+            value: factor_id.clone(),
+            expected: "a constant parameter with a value or a constant initial assignment",
+            location: species_location(&species_id),
+        })?;
+        factors.insert(species_id, factor);
+    }
 
-    // modified speciesRateRule = C * speciesRateRule + (S/C) * compartmentRateRule
-    if compartment_rate_rule.is_some() {
-        // shift indices in species math node
-        for math_node in &mut species_rate_rule_elements {
-            math_node.shift_indices(5);
+    // the factor can only be folded into stoichiometries that are fixed numbers
+    for (idx, tag) in model.nodes.iter().enumerate() {
+        let sp_ref = match tag {
+            Tag::SpeciesReference(sp_ref) => sp_ref,
+            _ => continue,
+        };
+        let species_id = match &sp_ref.species {
+            Some(species_id) if factors.contains_key(species_id) => species_id,
+            _ => continue,
+        };
+        let used_by_math =
+            matches!(&sp_ref.id, Some(id) if assigned.contains(id) || referenced.contains(id));
+        if used_by_math || sp_ref.stoichiometry_math.is_some() {
+            return Err(SbmlError::InvalidAttributeValue {
+                attribute: "species".to_string(),
+                value: species_id.clone(),
+                expected: "a species with a conversion factor to have fixed stoichiometries",
+                location: Location::at(model.element_path(idx)),
+            });
         }
-
-        // shift indices in compartment math node
-        let mut compartment_rate_rule_elements = compartment_rate_rule.unwrap().nodes.clone();
-        compartment_rate_rule_elements.remove(0);
-        for math_node in &mut compartment_rate_rule_elements {
-            math_node.shift_indices(5 + (a as i32));
+        if sp_ref.stoichiometry.is_none() {
+            return Err(SbmlError::MissingRequiredAttribute {
+                attribute: "stoichiometry".to_string(),
+                location: Location::at(model.element_path(idx)),
+            });
         }
-        // replace Species Ci node with an Apply node and insert
-        // Species Ci, Divide Op and Compartment Ci nodes at the end
-        // create nodes Apply, Divide and Compartment
-        let mut apply_1 = Apply::default();
-        apply_1.parent = Some(0);
-        apply_1.children = vec![2, 3, a + 6];
-        apply_1.operator = Some(apply_1.children[0]);
-        apply_1.operands = vec![3, a + 6];
-        transformed_species_rate_rule_nodes.push(MathNode::Apply(apply_1));
-
-        let mut plus_2 = OpNode::default();
-        plus_2.op = Some(Op::Plus);
-        plus_2.parent = Some(1);
-        transformed_species_rate_rule_nodes.push(MathNode::Op(plus_2));
-
-        let mut apply_3 = Apply::default();
-        apply_3.parent = Some(1);
-        apply_3.children = vec![4, 5, 6];
-        apply_3.operator = Some(4);
-        apply_3.operands = vec![5, 6];
-        transformed_species_rate_rule_nodes.push(MathNode::Apply(apply_3));
-
-        let mut times_4 = OpNode::default();
-        times_4.op = Some(Op::Times);
-        times_4.parent = Some(3);
-        transformed_species_rate_rule_nodes.push(MathNode::Op(times_4));
-
-        let mut compartment_5 = Ci::with_name(compartment_id.clone());
-        compartment_5.parent = Some(3);
-        transformed_species_rate_rule_nodes.push(MathNode::Ci(compartment_5));
+    }
 
-        // nodes 6 to a + 5 are speciesRateRule
-        for element in species_rate_rule_elements {
-            transformed_species_rate_rule_nodes.push(element);
+    for tag in model.nodes.iter_mut() {
+        match tag {
+            Tag::SpeciesReference(sp_ref) => {
+                let factor = sp_ref.species.as_ref().and_then(|id| factors.get(id));
+                if let (Some(factor), Some(stoichiometry)) = (factor, sp_ref.stoichiometry) {
+                    sp_ref.stoichiometry = Some(stoichiometry * factor);
+                }
+            }
+            Tag::Species(sp) => sp.conversion_factor = None,
+            _ => {}
         }
+    }
+    model.conversion_factor = None;
 
-        let mut apply_a_plus_6 = Apply::default();
-        apply_a_plus_6.parent = Some(1);
-        apply_a_plus_6.children = vec![a + 7, a + 8, a + 12];
-        apply_a_plus_6.operator = Some(a + 7);
-        apply_a_plus_6.operands = vec![a + 8, a + 12];
-        transformed_species_rate_rule_nodes.push(MathNode::Apply(apply_a_plus_6));
-
-        let mut times_a_plus_7 = OpNode::default();
-        times_a_plus_7.op = Some(Op::Times);
-        times_a_plus_7.parent = Some(a + 6);
-        transformed_species_rate_rule_nodes.push(MathNode::Op(times_a_plus_7));
-
-        let mut apply_a_plus_8 = Apply::default();
-        apply_a_plus_8.parent = Some(1);
-        apply_a_plus_8.children = vec![a + 9, a + 10, a + 11];
-        apply_a_plus_8.operator = Some(a + 9);
-        apply_a_plus_8.operands = vec![a + 10, a + 11];
-        transformed_species_rate_rule_nodes.push(MathNode::Apply(apply_a_plus_8));
-
-        let mut divide_a_plus_9 = OpNode::default();
-        divide_a_plus_9.op = Some(Op::Divide);
-        divide_a_plus_9.parent = Some(a + 8);
-        transformed_species_rate_rule_nodes.push(MathNode::Op(divide_a_plus_9));
-
-        let mut species_a_plus_10 = Ci::with_name(species_id.clone());
-        species_a_plus_10.parent = Some(a + 8);
-        transformed_species_rate_rule_nodes.push(MathNode::Ci(species_a_plus_10));
-
-        let mut compartment_a_plus_11 = Ci::with_name(compartment_id.clone());
-        compartment_a_plus_11.parent = Some(a + 8);
-        transformed_species_rate_rule_nodes.push(MathNode::Ci(compartment_a_plus_11));
+    Ok(model)
+}
 
-        // a + 12 wil be compartmentRateRule
-        for element in compartment_rate_rule_elements {
-            transformed_species_rate_rule_nodes.push(element);
-        }
+// Replaces each Ci of a species with an Apply of S / C. The Apply takes the
+// place of the Ci and its operator and operands are appended to the nodes.
+fn divide_in_place(math_tag: &mut MathTag, species_compartment_id: &HashMap<String, String>) {
+    for idx in 0..math_tag.nodes.len() {
+        let (ci, compartment_id) = match &math_tag.nodes[idx] {
+            MathNode::Ci(ci) => {
+                match ci.name.as_ref().and_then(|n| species_compartment_id.get(n)) {
+                    Some(compartment_id) => (ci.clone(), compartment_id),
+                    None => continue,
+                }
+            }
+            _ => continue,
+        };
+        let length = math_tag.nodes.len();
+        math_tag.nodes[idx] = MathNode::Apply(Apply {
+            parent: ci.parent,
+            children: vec![length, length + 1, length + 2],
+            operator: Some(length),
+            operands: vec![length + 1, length + 2],
+        });
+        math_tag.nodes.push(MathNode::Op(OpNode {
+            op: Some(Op::Divide),
+            parent: Some(idx),
+        }));
+        math_tag.nodes.push(MathNode::Ci(Ci {
+            parent: Some(idx),
+            ..ci
+        }));
+        math_tag.nodes.push(MathNode::Ci(Ci {
+            name: Some(compartment_id.clone()),
+            parent: Some(idx),
+            ..Default::default()
+        }));
     }
-    // modified speciesRateRule = C * speciesRateRule
-    else {
-        dbg!(species_id, &compartment_id);
-        for math_node in &mut species_rate_rule_elements {
-            math_node.shift_indices(3);
-        }
-
-        let mut apply_1 = Apply::default();
-        apply_1.parent = Some(0);
-        apply_1.children = vec![2, 3, 4];
-        apply_1.operator = Some(2);
-        apply_1.operands = vec![3, 4];
-        transformed_species_rate_rule_nodes.push(MathNode::Apply(apply_1));
+}
 
-        let mut times_2 = OpNode::default();
-        times_2.op = Some(Op::Times);
-        times_2.parent = Some(1);
-        transformed_species_rate_rule_nodes.push(MathNode::Op(times_2));
+fn species_location(species_id: &str) -> Location {
+    Location::at(format!(
+        "/sbml/model/listOfSpecies/species[@id='{}']",
+        species_id
+    ))
+}
 
-        let mut compartment_3 = Ci::with_name(compartment_id.clone());
-        compartment_3.parent = Some(1);
-        transformed_species_rate_rule_nodes.push(MathNode::Ci(compartment_3));
+fn rate_rule_location(variable: &str) -> Location {
+    Location::at(format!(
+        "/sbml/model/listOfRules/rateRule[@variable='{}']",
+        variable
+    ))
+}
 
-        // nodes 6 to a + 5 are speciesRateRule
-        for element in species_rate_rule_elements {
-            transformed_species_rate_rule_nodes.push(element);
-        }
+// Takes two MathTags for SpeciesRateRule and CompartmentRateRule and returns
+// modified speciesRateRule = C * speciesRateRule + (S/C) * compartmentRateRule
+pub fn transform_species_rate_rule(
+    species_id: String,
+    species_rate_rule: MathTag,
+    compartment_id: String,
+    compartment_rate_rule: Option<MathTag>,
+) -> Result<MathTag, String> {
+    let compartment = Expr::ci(&compartment_id);
+    let mut rate = compartment.clone().times(species_rate_rule.to_expr()?);
+    if let Some(compartment_rate_rule) = compartment_rate_rule {
+        let concentration = Expr::ci(&species_id).div(compartment);
+        rate = rate.plus(concentration.times(compartment_rate_rule.to_expr()?));
     }
-
-    MathTag::default().with_nodes(transformed_species_rate_rule_nodes)
+    let mut math_tag = rate.to_math_tag();
+    math_tag.parent = species_rate_rule.parent;
+    Ok(math_tag)
 }
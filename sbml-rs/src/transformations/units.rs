@@ -0,0 +1,477 @@
+use std::collections::HashMap;
+
+use mathml_rs::{Ci, Csymbol, MathNode};
+
+use crate::expr::SYMBOLS_URL;
+use crate::writer::format_double;
+use crate::{
+    BaseUnit, DerivedUnit, Expr, ListOfUnitDefinitions, ListOfUnits, Location, Model, SbmlError,
+    Tag, Unit, UnitDefinition,
+};
+
+// The units convert_units expresses a model in, as unit kinds or ids of
+// unit definitions in the model
+#[derive(Clone, Debug)]
+pub struct UnitSystem {
+    pub substance: String,
+    pub volume: String,
+    pub time: String,
+}
+
+impl UnitSystem {
+    pub fn new(substance: &str, volume: &str, time: &str) -> Self {
+        UnitSystem {
+            substance: substance.to_string(),
+            volume: volume.to_string(),
+            time: time.to_string(),
+        }
+    }
+}
+
+// mole, litre and second
+impl Default for UnitSystem {
+    fn default() -> Self {
+        UnitSystem::new("mole", "litre", "second")
+    }
+}
+
+// Rescales the model so that every quantity is expressed in the substance,
+// volume and time units of the target system: initial values, compartment
+// sizes, parameter values and the units they declare. Math keeps its meaning,
+// references are scaled back to the old units and results to the new ones,
+// e.g. k * S becomes 60 * (k * (1000 * S)) / 1000 for mmol and minutes.
+// Quantities whose units are not declared are left as they are. Without
+// extentUnits, reactions are taken to be in the substance units.
+pub fn convert_units(mut model: Model, target: &UnitSystem) -> Result<Model, SbmlError> {
+    let scales = Scales::new(&model, target)?;
+    if model.extent_units.is_none() {
+        model.extent_units = model.substance_units.clone();
+    }
+
+    // (old units, new units) of every quantity math can refer to
+    let mut symbols = HashMap::<String, (DerivedUnit, DerivedUnit)>::new();
+    for tag in &model.nodes {
+        let id = match tag {
+            Tag::Species(_) | Tag::Compartment(_) | Tag::Parameter(_) => tag.id(),
+            _ => None,
+        };
+        if let Some(id) = id {
+            if let Some(old) = model.units_of(id) {
+                symbols.insert(id.clone(), scales.convert(&old));
+            }
+        }
+    }
+    let time = resolve(&model, &model.time_units).map(|old| scales.convert(&old));
+    let rate = match (resolve(&model, &model.extent_units), &time) {
+        (Some(extent), Some((time, _))) => Some(scales.convert(&(extent / time.clone()))),
+        _ => None,
+    };
+    if let Some(rate) = &rate {
+        for reaction in model.reactions_iter() {
+            if let Some(id) = &reaction.id {
+                symbols.insert(id.clone(), rate.clone());
+            }
+        }
+    }
+
+    convert_math(&mut model, &scales, &symbols, &time)?;
+    convert_values(&mut model, &scales);
+
+    let mut units = UnitIds::default();
+    let declared = [
+        model.substance_units.take(),
+        model.extent_units.take(),
+        model.time_units.take(),
+        model.volume_units.take(),
+        model.area_units.take(),
+        model.length_units.take(),
+    ];
+    let converted: Vec<Option<String>> = declared
+        .iter()
+        .map(|declared| units.convert(&mut model, &scales, declared))
+        .collect();
+    let mut converted = converted.into_iter();
+    model.substance_units = converted.next().flatten();
+    model.extent_units = converted.next().flatten();
+    model.time_units = converted.next().flatten();
+    model.volume_units = converted.next().flatten();
+    model.area_units = converted.next().flatten();
+    model.length_units = converted.next().flatten();
+    for idx in 0..model.nodes.len() {
+        let declared = match &model.nodes[idx] {
+            Tag::Species(t) => t.substance_units.clone(),
+            Tag::Compartment(t) => t.units.clone(),
+            Tag::Parameter(t) => t.units.clone(),
+            Tag::LocalParameter(t) => t.units.clone(),
+            _ => continue,
+        };
+        let converted = units.convert(&mut model, &scales, &declared);
+        match &mut model.nodes[idx] {
+            Tag::Species(t) => t.substance_units = converted,
+            Tag::Compartment(t) => t.units = converted,
+            Tag::Parameter(t) => t.units = converted,
+            Tag::LocalParameter(t) => t.units = converted,
+            _ => {}
+        }
+    }
+
+    Ok(model)
+}
+
+fn resolve(model: &Model, units: &Option<String>) -> Option<DerivedUnit> {
+    model.resolve_units(units.as_deref()?)
+}
+
+// How large the unit of each base unit is in the target system, e.g. a
+// tenth of a metre for litres
+struct Scales {
+    target: UnitSystem,
+    factors: HashMap<BaseUnit, f64>,
+}
+
+impl Scales {
+    fn new(model: &Model, target: &UnitSystem) -> Result<Self, SbmlError> {
+        let check = |units: &str, attribute: &str, expected: &'static str, base, exponent| {
+            let invalid = || SbmlError::InvalidAttributeValue {
+                attribute: attribute.to_string(),
+                value: units.to_string(),
+                expected,
+                location: Location::at("/sbml/model".to_string()),
+            };
+            let derived = model.resolve_units(units).ok_or_else(invalid)?;
+            if derived.same_dimensions(&DerivedUnit::base(base).pow(exponent)) {
+                Ok(derived.factor.powf(1.0 / exponent))
+            } else {
+                Err(invalid())
+            }
+        };
+        let mut factors = HashMap::new();
+        let substance = &target.substance;
+        match check(
+            substance,
+            "substanceUnits",
+            "a unit of substance",
+            BaseUnit::Mole,
+            1.0,
+        ) {
+            Ok(factor) => factors.insert(BaseUnit::Mole, factor),
+            Err(_) => factors.insert(
+                BaseUnit::Item,
+                check(
+                    substance,
+                    "substanceUnits",
+                    "a unit of substance",
+                    BaseUnit::Item,
+                    1.0,
+                )?,
+            ),
+        };
+        let metre = check(
+            &target.volume,
+            "volumeUnits",
+            "a unit of volume",
+            BaseUnit::Metre,
+            3.0,
+        )?;
+        factors.insert(BaseUnit::Metre, metre);
+        let second = check(
+            &target.time,
+            "timeUnits",
+            "a unit of time",
+            BaseUnit::Second,
+            1.0,
+        )?;
+        factors.insert(BaseUnit::Second, second);
+        Ok(Scales {
+            target: target.clone(),
+            factors,
+        })
+    }
+
+    // The old units and what they become in the target system
+    fn convert(&self, old: &DerivedUnit) -> (DerivedUnit, DerivedUnit) {
+        let factor = old
+            .exponents
+            .iter()
+            .map(|(base, e)| self.factors.get(base).unwrap_or(&1.0).powf(*e))
+            .product();
+        let new = DerivedUnit {
+            factor,
+            exponents: old.exponents.clone(),
+        };
+        (old.clone(), new)
+    }
+
+    // An id for new units, such as mole_per_litre
+    fn name(&self, units: &DerivedUnit) -> String {
+        let mut positive = Vec::new();
+        let mut negative = Vec::new();
+        for (base, e) in &units.exponents {
+            let (name, e) = match base {
+                BaseUnit::Mole | BaseUnit::Item => (self.target.substance.clone(), *e),
+                BaseUnit::Metre if (e / 3.0).fract() == 0.0 => {
+                    (self.target.volume.clone(), e / 3.0)
+                }
+                BaseUnit::Metre => (format!("{}_length", self.target.volume), *e),
+                BaseUnit::Second => (self.target.time.clone(), *e),
+                _ => (base.name().to_string(), *e),
+            };
+            let part = if e.abs() == 1.0 {
+                name
+            } else {
+                format!("{}{}", name, format_double(e.abs()).replace('.', "_"))
+            };
+            if e > 0.0 {
+                positive.push(part);
+            } else {
+                negative.push(format!("per_{}", part));
+            }
+        }
+        positive.extend(negative);
+        positive.join("_")
+    }
+}
+
+// Rewrites math so that it works on the converted values: every reference is
+// scaled back to the old units, and the result is scaled to the new units of
+// what the math determines
+fn convert_math(
+    model: &mut Model,
+    scales: &Scales,
+    symbols: &HashMap<String, (DerivedUnit, DerivedUnit)>,
+    time: &Option<(DerivedUnit, DerivedUnit)>,
+) -> Result<(), SbmlError> {
+    let mut converted = Vec::new();
+    for (idx, tag) in model.nodes.iter().enumerate() {
+        let math = match tag {
+            Tag::MathTag(math) => math,
+            _ => continue,
+        };
+        let parent = match math.parent {
+            Some(parent) => parent,
+            None => continue,
+        };
+        // local parameters hide global quantities of the same name
+        let mut local = HashMap::new();
+        if let Tag::KineticLaw(kinetic_law) = &model.nodes[parent] {
+            for parameter in kinetic_law.local_parameters(model) {
+                if let Some(id) = parameter.id {
+                    let units = resolve(model, &parameter.units).map(|old| scales.convert(&old));
+                    local.insert(id, units);
+                }
+            }
+        }
+        let result = match &model.nodes[parent] {
+            Tag::Delay(_) => time.as_ref().map(|(old, _)| old.clone()),
+            _ => model.expected_units(parent).map(|(_, units, _)| units),
+        }
+        .map(|old| scales.convert(&old));
+
+        let to_old = |(old, new): &(DerivedUnit, DerivedUnit)| new.factor / old.factor;
+        let scale_of = |name: &str| match local.get(name) {
+            Some(units) => units.as_ref().map(to_old),
+            None => symbols.get(name).map(to_old),
+        };
+        let time_scale = time.as_ref().map_or(1.0, to_old);
+        let time_url = format!("{}time", SYMBOLS_URL);
+        let refers_to_converted = math.nodes.iter().any(|node| match node {
+            MathNode::Ci(Ci {
+                name: Some(name), ..
+            }) => matches!(scale_of(name), Some(scale) if scale != 1.0),
+            MathNode::Csymbol(Csymbol {
+                definition_url: Some(url),
+                ..
+            }) => *url == time_url && time_scale != 1.0,
+            _ => false,
+        });
+        let result_scale = result
+            .as_ref()
+            .map_or(1.0, |(old, new)| old.factor / new.factor);
+        if !refers_to_converted && result_scale == 1.0 {
+            continue;
+        }
+
+        let location = || Location::at(model.element_path(parent));
+        let mut expr = math.to_expr().map_err(|message| SbmlError::InvalidMath {
+            message,
+            location: location(),
+        })?;
+        expr = expr.replace_ci(&|name| match scale_of(name) {
+            Some(scale) if scale != 1.0 => Some(Expr::real(scale).times(Expr::ci(name))),
+            _ => None,
+        });
+        if time_scale != 1.0 {
+            let scaled_time = Expr::real(time_scale).times(Expr::symbol("time"));
+            expr = expr.replace_symbol("time", &scaled_time);
+        }
+        if result_scale != 1.0 {
+            expr = Expr::real(result_scale).times(expr);
+        }
+        let mut math = expr.to_math_tag();
+        math.parent = Some(parent);
+        converted.push((idx, math));
+    }
+    for (idx, math) in converted {
+        model.nodes[idx] = Tag::MathTag(math);
+    }
+    Ok(())
+}
+
+// Rescales initial values, sizes and parameter values
+fn convert_values(model: &mut Model, scales: &Scales) {
+    let convert = |units: Option<DerivedUnit>| {
+        units.map(|old| {
+            let (old, new) = scales.convert(&old);
+            old.factor / new.factor
+        })
+    };
+    let mut scaled = Vec::new();
+    for (idx, tag) in model.nodes.iter().enumerate() {
+        let factors = match tag {
+            Tag::Species(species) => {
+                let substance = species
+                    .substance_units
+                    .as_ref()
+                    .or(model.substance_units.as_ref())
+                    .and_then(|units| model.resolve_units(units));
+                // per spatialSizeUnits if set, as in Model::units_of
+                let size = model.species_size_units(species);
+                let concentration = match (&substance, size) {
+                    (Some(substance), Some(size)) => Some(substance.clone() / size),
+                    _ => None,
+                };
+                (convert(substance), convert(concentration))
+            }
+            Tag::Compartment(t) => (
+                convert(model.units_of(t.id.as_deref().unwrap_or_default())),
+                None,
+            ),
+            Tag::Parameter(t) => (convert(resolve(model, &t.units)), None),
+            Tag::LocalParameter(t) => (convert(resolve(model, &t.units)), None),
+            _ => continue,
+        };
+        scaled.push((idx, factors));
+    }
+    let scale = |value: &mut Option<f64>, factor: Option<f64>| {
+        if let (Some(value), Some(factor)) = (value, factor) {
+            *value *= factor;
+        }
+    };
+    for (idx, (factor, concentration_factor)) in scaled {
+        match &mut model.nodes[idx] {
+            Tag::Species(t) => {
+                scale(&mut t.initial_amount, factor);
+                scale(&mut t.initial_concentration, concentration_factor);
+            }
+            Tag::Compartment(t) => scale(&mut t.size, factor),
+            Tag::Parameter(t) => scale(&mut t.value, factor),
+            Tag::LocalParameter(t) => scale(&mut t.value, factor),
+            _ => {}
+        }
+    }
+}
+
+// Ids of the converted units, adding unit definitions where the target
+// system has no unit of that name
+#[derive(Default)]
+struct UnitIds {
+    converted: HashMap<String, Option<String>>,
+}
+
+impl UnitIds {
+    fn convert(
+        &mut self,
+        model: &mut Model,
+        scales: &Scales,
+        units: &Option<String>,
+    ) -> Option<String> {
+        let units = units.as_ref()?;
+        if let Some(converted) = self.converted.get(units) {
+            return converted.clone();
+        }
+        let converted = match model.resolve_units(units) {
+            Some(old) => Some(unit_id(model, scales, &scales.convert(&old).1)),
+            // units that cannot be resolved stay as they are
+            None => Some(units.clone()),
+        };
+        self.converted.insert(units.clone(), converted.clone());
+        converted
+    }
+}
+
+fn unit_id(model: &mut Model, scales: &Scales, units: &DerivedUnit) -> String {
+    if units.is_dimensionless() && (units.factor - 1.0).abs() < 1e-12 {
+        return "dimensionless".to_string();
+    }
+    let target = &scales.target;
+    for id in [&target.substance, &target.volume, &target.time].iter() {
+        if matches!(model.resolve_units(id), Some(existing) if existing.is_equivalent(units)) {
+            return id.to_string();
+        }
+    }
+    let name = scales.name(units);
+    let mut id = name.clone();
+    let mut n = 1;
+    loop {
+        match model.resolve_units(&id) {
+            Some(existing) if existing.is_equivalent(units) => return id,
+            None => break,
+            Some(_) => {
+                n += 1;
+                id = format!("{}_{}", name, n);
+            }
+        }
+    }
+    add_unit_definition(model, &id, scales, units);
+    id
+}
+
+// Appends a unit definition made of base units, each scaled to the target system
+fn add_unit_definition(model: &mut Model, id: &str, scales: &Scales, units: &DerivedUnit) {
+    let list_idx = match &model.nodes[0] {
+        Tag::Root(root) => root.list_of_unit_definitions,
+        _ => None,
+    };
+    let list_idx = match list_idx {
+        Some(idx) => idx,
+        None => {
+            let idx = model.add_node(Tag::ListOfUnitDefinitions(ListOfUnitDefinitions {
+                parent: Some(0),
+                ..Default::default()
+            }));
+            if let Tag::Root(root) = &mut model.nodes[0] {
+                root.list_of_unit_definitions = Some(idx);
+            }
+            idx
+        }
+    };
+    let definition_idx = model.nodes.len();
+    let units_idx = definition_idx + 1;
+    model.add_node(Tag::UnitDefinition(UnitDefinition {
+        id: Some(id.to_string()),
+        list_of_units: Some(units_idx),
+        parent: Some(list_idx),
+        ..Default::default()
+    }));
+    model.add_node(Tag::ListOfUnits(ListOfUnits {
+        parent: Some(definition_idx),
+        ..Default::default()
+    }));
+    let mut unit_indices = Vec::new();
+    for (base, e) in &units.exponents {
+        unit_indices.push(model.add_node(Tag::Unit(Unit {
+            kind: Some(base.name().to_string()),
+            exponent: Some(*e),
+            scale: Some(0),
+            multiplier: Some(*scales.factors.get(base).unwrap_or(&1.0)),
+            parent: Some(units_idx),
+            ..Default::default()
+        })));
+    }
+    if let Tag::ListOfUnits(list) = &mut model.nodes[units_idx] {
+        list.units = unit_indices;
+    }
+    if let Tag::ListOfUnitDefinitions(list) = &mut model.nodes[list_idx] {
+        list.unit_definitions.push(definition_idx);
+    }
+}
@@ -0,0 +1,379 @@
+use mathml_rs::{Constant, MathNode, NodeIndex, Number, Op};
+
+use super::DerivedUnit;
+use crate::expr::SYMBOLS_URL;
+use crate::mathml::op_name;
+use crate::{Diagnostic, Location, MathTag, Model, Severity, Tag, TagIndex, UnitSId};
+
+// What unit inference found out about a math element
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct UnitInference {
+    // units of the whole expression, None if they depend on something whose
+    // units are not declared, such as a number without sbml:units
+    pub units: Option<DerivedUnit>,
+    // subexpressions combining operands of incompatible units
+    pub mismatches: Vec<String>,
+}
+
+impl MathTag {
+    // Derives the units of the expression from the units of the species,
+    // compartments, parameters and local parameters it refers to and the
+    // units given on numbers. Operands whose units are not known are
+    // assumed to fit, as SBML does for numbers without units.
+    pub fn infer_units(&self, model: &Model) -> UnitInference {
+        let mut inference = Inference {
+            model,
+            math: self,
+            local_parameters: local_parameters(model, self.parent),
+            mismatches: Vec::new(),
+        };
+        let units = match self.nodes.first() {
+            Some(MathNode::Root(root)) => root.children.first().and_then(|c| inference.units(*c)),
+            Some(_) => inference.units(0),
+            None => None,
+        };
+        UnitInference {
+            units,
+            mismatches: inference.mismatches,
+        }
+    }
+}
+
+impl Model {
+    // Checks that the math of the model is dimensionally consistent: the
+    // operands of sums and comparisons agree, kinetic laws are in extent per
+    // time and rules and assignments match the units of their variable.
+    // Like libSBML, unit problems are reported as warnings.
+    pub fn check_units(&self) -> Vec<Diagnostic> {
+        let mut diagnostics = Vec::new();
+        for tag in &self.nodes {
+            let math = match tag {
+                Tag::MathTag(math) => math,
+                _ => continue,
+            };
+            let parent = match math.parent {
+                Some(parent) => parent,
+                None => continue,
+            };
+            let location = Location::at(self.element_path(parent));
+            let inference = math.infer_units(self);
+            for mismatch in inference.mismatches {
+                diagnostics.push(warning("sbml-10501", mismatch, &location));
+            }
+            let found = match inference.units {
+                Some(found) => found,
+                None => continue,
+            };
+            if let Some((rule, expected, what)) = self.expected_units(parent) {
+                if !found.is_equivalent(&expected) {
+                    let message = format!(
+                        "The units of {} should be {}, but are {}",
+                        what, expected, found
+                    );
+                    diagnostics.push(warning(rule, message, &location));
+                }
+            }
+        }
+        diagnostics
+    }
+
+    // The units the math of an element has to be in, with the libSBML rule
+    // that requires it
+    pub(crate) fn expected_units(
+        &self,
+        idx: TagIndex,
+    ) -> Option<(&'static str, DerivedUnit, String)> {
+        let (rules, variable, per_time, what) = match &self.nodes[idx] {
+            Tag::KineticLaw(_) => {
+                let extent = self.resolve_units(self.extent_units.as_deref()?)?;
+                let time = self.resolve_units(self.time_units.as_deref()?)?;
+                let what = "the kinetic law".to_string();
+                return Some(("sbml-10541", extent / time, what));
+            }
+            Tag::AssignmentRule(t) => (
+                ["sbml-10511", "sbml-10512", "sbml-10513"],
+                t.variable.as_ref()?,
+                false,
+                "the assignment rule",
+            ),
+            Tag::InitialAssignment(t) => (
+                ["sbml-10521", "sbml-10522", "sbml-10523"],
+                t.symbol.as_ref()?,
+                false,
+                "the initial assignment",
+            ),
+            Tag::RateRule(t) => (
+                ["sbml-10531", "sbml-10532", "sbml-10533"],
+                t.variable.as_ref()?,
+                true,
+                "the rate rule",
+            ),
+            Tag::EventAssignment(t) => (
+                ["sbml-10561", "sbml-10562", "sbml-10563"],
+                t.variable.as_ref()?,
+                false,
+                "the event assignment",
+            ),
+            _ => return None,
+        };
+        // the rules are numbered for compartments, species and parameters
+        let rule = match self.get_by_sid(variable)? {
+            Tag::Compartment(_) => rules[0],
+            Tag::Species(_) => rules[1],
+            Tag::Parameter(_) => rules[2],
+            _ => return None,
+        };
+        let mut expected = self.units_of(variable)?;
+        if per_time {
+            expected = expected / self.resolve_units(self.time_units.as_deref()?)?;
+        }
+        Some((rule, expected, format!("{} for '{}'", what, variable)))
+    }
+}
+
+fn warning(rule: &'static str, message: String, location: &Location) -> Diagnostic {
+    Diagnostic {
+        severity: Severity::Warning,
+        rule: Some(rule),
+        message,
+        location: location.clone(),
+    }
+}
+
+// Units of the local parameters of the kinetic law the math belongs to
+fn local_parameters(model: &Model, parent: Option<TagIndex>) -> Vec<(String, Option<String>)> {
+    match parent.map(|idx| &model.nodes[idx]) {
+        Some(Tag::KineticLaw(kinetic_law)) => kinetic_law
+            .local_parameters(model)
+            .into_iter()
+            .filter_map(|parameter| Some((parameter.id?, parameter.units)))
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+struct Inference<'a> {
+    model: &'a Model,
+    math: &'a MathTag,
+    local_parameters: Vec<(String, Option<String>)>,
+    mismatches: Vec<String>,
+}
+
+impl<'a> Inference<'a> {
+    fn units(&mut self, idx: NodeIndex) -> Option<DerivedUnit> {
+        let math = self.math;
+        let nodes = &math.nodes;
+        match &nodes[idx] {
+            MathNode::Ci(ci) => self.ci_units(ci.name.as_deref()?),
+            MathNode::Cn(_) => {
+                let units = math.number_units.get(&idx)?;
+                self.model.resolve_units(units)
+            }
+            MathNode::Constant(constant) => match constant.constant {
+                Some(Constant::Pi) | Some(Constant::ExponentialE) => {
+                    Some(DerivedUnit::dimensionless())
+                }
+                _ => None,
+            },
+            MathNode::Csymbol(csymbol) => match symbol_name(csymbol.definition_url.as_deref()) {
+                Some("time") => self.time_units(),
+                Some("avogadro") => Some(UnitSId::Mole.derived().pow(-1.0)),
+                _ => None,
+            },
+            MathNode::Apply(apply) => {
+                let operands = &apply.operands;
+                match apply.operator.map(|operator| &nodes[operator]) {
+                    Some(MathNode::Op(op)) => match &op.op {
+                        Some(op) => self.apply_units(idx, op, operands),
+                        None => None,
+                    },
+                    Some(MathNode::Csymbol(csymbol)) => {
+                        let name = symbol_name(csymbol.definition_url.as_deref());
+                        let units = self.all_units(operands);
+                        match (name, units.first()) {
+                            (Some("delay"), Some(units)) => units.clone(),
+                            (Some("rateOf"), Some(Some(units))) => {
+                                Some(units.clone() / self.time_units()?)
+                            }
+                            _ => None,
+                        }
+                    }
+                    // calls of function definitions are not looked into
+                    _ => {
+                        self.all_units(operands);
+                        None
+                    }
+                }
+            }
+            MathNode::Piecewise(piecewise) => {
+                let mut values = Vec::new();
+                for piece in &piecewise.pieces {
+                    if let MathNode::Piece(piece) = &nodes[*piece] {
+                        let (value, condition) = (piece.expr, piece.condition);
+                        if let Some(condition) = condition {
+                            self.units(condition);
+                        }
+                        values.extend(value);
+                    }
+                }
+                if let Some(MathNode::Otherwise(otherwise)) =
+                    piecewise.otherwise.map(|idx| &nodes[idx])
+                {
+                    values.extend(otherwise.expr);
+                }
+                let units = self.all_units(&values);
+                self.same_units(idx, "piecewise values", &units)
+            }
+            MathNode::Root(root) => {
+                let child = *root.children.first()?;
+                self.units(child)
+            }
+            _ => None,
+        }
+    }
+
+    fn ci_units(&self, name: &str) -> Option<DerivedUnit> {
+        if let Some((_, units)) = self.local_parameters.iter().find(|(id, _)| id == name) {
+            return self.model.resolve_units(units.as_deref()?);
+        }
+        match self.model.get_by_sid(name)? {
+            Tag::Species(_) | Tag::Compartment(_) | Tag::Parameter(_) => self.model.units_of(name),
+            Tag::SpeciesReference(_) => Some(DerivedUnit::dimensionless()),
+            // a reaction id stands for its rate
+            Tag::Reaction(_) => {
+                let extent = self
+                    .model
+                    .resolve_units(self.model.extent_units.as_deref()?)?;
+                Some(extent / self.time_units()?)
+            }
+            _ => None,
+        }
+    }
+
+    fn time_units(&self) -> Option<DerivedUnit> {
+        self.model.resolve_units(self.model.time_units.as_deref()?)
+    }
+
+    fn all_units(&mut self, operands: &[NodeIndex]) -> Vec<Option<DerivedUnit>> {
+        operands
+            .iter()
+            .map(|operand| self.units(*operand))
+            .collect()
+    }
+
+    fn apply_units(
+        &mut self,
+        idx: NodeIndex,
+        op: &Op,
+        operands: &[NodeIndex],
+    ) -> Option<DerivedUnit> {
+        let units = self.all_units(operands);
+        match op {
+            Op::Plus | Op::Minus if units.len() > 1 => self.same_units(idx, "operands", &units),
+            Op::Plus | Op::Minus | Op::Abs | Op::Floor | Op::Ceiling => units.first()?.clone(),
+            Op::Times => units
+                .into_iter()
+                .try_fold(DerivedUnit::dimensionless(), |product, units| {
+                    Some(product * units?)
+                }),
+            Op::Divide => {
+                let mut units = units.into_iter();
+                let numerator = units.next()??;
+                let denominator = units.next()??;
+                Some(numerator / denominator)
+            }
+            Op::Power => {
+                let base = units.first()?.clone()?;
+                if base.is_dimensionless() {
+                    return Some(base);
+                }
+                let exponent = self.number(*operands.get(1)?)?;
+                Some(base.pow(exponent))
+            }
+            // a single operand is the square root, a degree comes first
+            Op::Root => match (units.as_slice(), operands) {
+                ([Some(base)], _) => Some(base.pow(0.5)),
+                ([_, Some(base)], [degree, _]) => {
+                    let degree = self.number(*degree)?;
+                    Some(base.pow(1.0 / degree))
+                }
+                _ => None,
+            },
+            Op::Eq | Op::Neq | Op::Gt | Op::Lt | Op::Geq | Op::Leq => {
+                self.same_units(idx, "compared values", &units);
+                None
+            }
+            Op::And | Op::Or | Op::Xor | Op::Not => None,
+            // exponentials, logarithms and trigonometric functions
+            // only make sense of dimensionless numbers
+            _ => {
+                for (operand, units) in operands.iter().zip(&units) {
+                    if let Some(units) = units {
+                        if !units.is_dimensionless() {
+                            let message = format!(
+                                "{} needs a dimensionless argument, {} is in {}",
+                                op_name(op),
+                                self.describe(*operand),
+                                units
+                            );
+                            self.mismatches.push(message);
+                        }
+                    }
+                }
+                Some(DerivedUnit::dimensionless())
+            }
+        }
+    }
+
+    // The units of values that have to agree, e.g. the terms of a sum
+    fn same_units(
+        &mut self,
+        idx: NodeIndex,
+        what: &str,
+        units: &[Option<DerivedUnit>],
+    ) -> Option<DerivedUnit> {
+        let known: Vec<&DerivedUnit> = units.iter().flatten().collect();
+        let first = *known.first()?;
+        if let Some(other) = known.iter().find(|units| !units.is_equivalent(first)) {
+            let message = format!(
+                "The {} of {} have different units: {} and {}",
+                what,
+                self.describe(idx),
+                first,
+                other
+            );
+            self.mismatches.push(message);
+        }
+        Some(first.clone())
+    }
+
+    // The value of a number, for exponents and degrees
+    fn number(&self, idx: NodeIndex) -> Option<f64> {
+        match &self.math.nodes[idx] {
+            MathNode::Cn(cn) => match cn.value.as_ref()? {
+                Number::Integer(value) => Some(*value as f64),
+                Number::Real(value) => Some(*value),
+                Number::ENotation(mantissa, exponent) => {
+                    Some(mantissa * 10f64.powi(*exponent as i32))
+                }
+                Number::Rational(numerator, denominator) => {
+                    Some(*numerator as f64 / *denominator as f64)
+                }
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    fn describe(&self, idx: NodeIndex) -> String {
+        match self.math.expr_at(idx) {
+            Ok(expr) => format!("'{}'", expr),
+            Err(_) => format!("node {}", idx),
+        }
+    }
+}
+
+// The name of an SBML csymbol such as time, from its definitionURL
+fn symbol_name(url: Option<&str>) -> Option<&str> {
+    url?.strip_prefix(SYMBOLS_URL)
+}
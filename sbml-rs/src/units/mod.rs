@@ -0,0 +1,378 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::ops;
+
+mod check;
+pub use check::UnitInference;
+
+use crate::{Compartment, Model, Species, Tag, Unit, UnitDefinition, UnitSId};
+
+// Relative tolerance when comparing factors and exponents
+const TOLERANCE: f64 = 1e-9;
+
+const AVOGADRO: f64 = 6.022_140_76e23;
+
+// The units everything is expanded into. Item counts entities and is kept
+// apart from mole, radian and steradian are dimensionless.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum BaseUnit {
+    Metre,
+    Kilogram,
+    Second,
+    Ampere,
+    Kelvin,
+    Mole,
+    Candela,
+    Item,
+}
+
+impl BaseUnit {
+    pub fn name(&self) -> &'static str {
+        match self {
+            BaseUnit::Metre => "metre",
+            BaseUnit::Kilogram => "kilogram",
+            BaseUnit::Second => "second",
+            BaseUnit::Ampere => "ampere",
+            BaseUnit::Kelvin => "kelvin",
+            BaseUnit::Mole => "mole",
+            BaseUnit::Candela => "candela",
+            BaseUnit::Item => "item",
+        }
+    }
+}
+
+// A unit as a factor times a product of powers of base units. A millimole
+// per litre is 1e-3 mole / 1e-3 metre^3, i.e. a factor of 1 and mole metre^-3.
+//
+//     let millimole = UnitSId::Mole.derived().scaled(1e-3);
+//     let per_minute = DerivedUnit::base(BaseUnit::Second).scaled(60.0).pow(-1.0);
+#[derive(Clone, Debug, PartialEq)]
+pub struct DerivedUnit {
+    pub factor: f64,
+    // only base units with a non-zero exponent are kept
+    pub exponents: BTreeMap<BaseUnit, f64>,
+}
+
+impl DerivedUnit {
+    pub fn dimensionless() -> Self {
+        DerivedUnit {
+            factor: 1.0,
+            exponents: BTreeMap::new(),
+        }
+    }
+
+    pub fn base(base: BaseUnit) -> Self {
+        let mut unit = DerivedUnit::dimensionless();
+        unit.exponents.insert(base, 1.0);
+        unit
+    }
+
+    pub fn scaled(mut self, factor: f64) -> Self {
+        self.factor *= factor;
+        self
+    }
+
+    pub fn pow(&self, exponent: f64) -> Self {
+        DerivedUnit {
+            factor: self.factor.powf(exponent),
+            exponents: self
+                .exponents
+                .iter()
+                .map(|(base, e)| (*base, e * exponent))
+                .filter(|(_, e)| e.abs() > TOLERANCE)
+                .collect(),
+        }
+    }
+
+    pub fn is_dimensionless(&self) -> bool {
+        self.exponents.is_empty()
+    }
+
+    // Whether both measure the same quantity, e.g. litre and metre^3
+    pub fn same_dimensions(&self, other: &DerivedUnit) -> bool {
+        self.exponents.len() == other.exponents.len()
+            && self.exponents.iter().all(|(base, e)| {
+                matches!(other.exponents.get(base), Some(other) if (e - other).abs() <= TOLERANCE)
+            })
+    }
+
+    // Whether both are the same unit, e.g. litre and decimetre^3
+    pub fn is_equivalent(&self, other: &DerivedUnit) -> bool {
+        self.same_dimensions(other) && close(self.factor, other.factor)
+    }
+
+    // What a value in these units is multiplied by to express it in the
+    // other units, None if they measure different quantities
+    pub fn conversion_factor_to(&self, other: &DerivedUnit) -> Option<f64> {
+        if self.same_dimensions(other) {
+            Some(self.factor / other.factor)
+        } else {
+            None
+        }
+    }
+}
+
+fn close(a: f64, b: f64) -> bool {
+    (a - b).abs() <= TOLERANCE * a.abs().max(b.abs())
+}
+
+impl ops::Mul for DerivedUnit {
+    type Output = DerivedUnit;
+
+    fn mul(mut self, rhs: DerivedUnit) -> DerivedUnit {
+        self.factor *= rhs.factor;
+        for (base, e) in rhs.exponents {
+            let exponent = self.exponents.entry(base).or_insert(0.0);
+            *exponent += e;
+            if exponent.abs() <= TOLERANCE {
+                self.exponents.remove(&base);
+            }
+        }
+        self
+    }
+}
+
+impl ops::Div for DerivedUnit {
+    type Output = DerivedUnit;
+
+    fn div(self, rhs: DerivedUnit) -> DerivedUnit {
+        self * rhs.pow(-1.0)
+    }
+}
+
+// e.g. 0.001 mole metre^-3, or dimensionless
+impl fmt::Display for DerivedUnit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.exponents.is_empty() && close(self.factor, 1.0) {
+            return write!(f, "dimensionless");
+        }
+        let mut parts = Vec::new();
+        if !close(self.factor, 1.0) || self.exponents.is_empty() {
+            parts.push(format_number(self.factor));
+        }
+        // mole second^-1 rather than second^-1 mole
+        let (positive, negative): (Vec<_>, Vec<_>) =
+            self.exponents.iter().partition(|(_, e)| **e > 0.0);
+        for (base, e) in positive.into_iter().chain(negative) {
+            if close(*e, 1.0) {
+                parts.push(base.name().to_string());
+            } else {
+                parts.push(format!("{}^{}", base.name(), format_number(*e)));
+            }
+        }
+        write!(f, "{}", parts.join(" "))
+    }
+}
+
+// Rounded to 12 significant digits, so that scales multiplied together
+// print as 1000 rather than 999.9999999999999
+fn format_number(value: f64) -> String {
+    let rounded: f64 = format!("{:.11e}", value).parse().unwrap_or(value);
+    format!("{}", rounded)
+}
+
+impl UnitSId {
+    pub fn derived(&self) -> DerivedUnit {
+        use BaseUnit::*;
+        let (factor, exponents): (f64, &[(BaseUnit, f64)]) = match self {
+            UnitSId::Ampere => (1.0, &[(Ampere, 1.0)]),
+            UnitSId::Avogadro => (AVOGADRO, &[]),
+            UnitSId::Becquerel | UnitSId::Hertz => (1.0, &[(Second, -1.0)]),
+            UnitSId::Candela | UnitSId::Lumen => (1.0, &[(Candela, 1.0)]),
+            UnitSId::Coulomb => (1.0, &[(Ampere, 1.0), (Second, 1.0)]),
+            UnitSId::Dimensionless | UnitSId::Radian | UnitSId::Steradian => (1.0, &[]),
+            UnitSId::Farad => (
+                1.0,
+                &[
+                    (Metre, -2.0),
+                    (Kilogram, -1.0),
+                    (Second, 4.0),
+                    (Ampere, 2.0),
+                ],
+            ),
+            UnitSId::Gram => (1e-3, &[(Kilogram, 1.0)]),
+            UnitSId::Gray | UnitSId::Sievert => (1.0, &[(Metre, 2.0), (Second, -2.0)]),
+            UnitSId::Henry => (
+                1.0,
+                &[
+                    (Metre, 2.0),
+                    (Kilogram, 1.0),
+                    (Second, -2.0),
+                    (Ampere, -2.0),
+                ],
+            ),
+            UnitSId::Item => (1.0, &[(Item, 1.0)]),
+            UnitSId::Joule => (1.0, &[(Metre, 2.0), (Kilogram, 1.0), (Second, -2.0)]),
+            UnitSId::Katal => (1.0, &[(Mole, 1.0), (Second, -1.0)]),
+            UnitSId::Kelvin => (1.0, &[(Kelvin, 1.0)]),
+            UnitSId::Kilogram => (1.0, &[(Kilogram, 1.0)]),
+            UnitSId::Litre => (1e-3, &[(Metre, 3.0)]),
+            UnitSId::Lux => (1.0, &[(Candela, 1.0), (Metre, -2.0)]),
+            UnitSId::Metre => (1.0, &[(Metre, 1.0)]),
+            UnitSId::Mole => (1.0, &[(Mole, 1.0)]),
+            UnitSId::Newton => (1.0, &[(Metre, 1.0), (Kilogram, 1.0), (Second, -2.0)]),
+            UnitSId::Ohm => (
+                1.0,
+                &[
+                    (Metre, 2.0),
+                    (Kilogram, 1.0),
+                    (Second, -3.0),
+                    (Ampere, -2.0),
+                ],
+            ),
+            UnitSId::Pascal => (1.0, &[(Metre, -1.0), (Kilogram, 1.0), (Second, -2.0)]),
+            UnitSId::Second => (1.0, &[(Second, 1.0)]),
+            UnitSId::Siemens => (
+                1.0,
+                &[
+                    (Metre, -2.0),
+                    (Kilogram, -1.0),
+                    (Second, 3.0),
+                    (Ampere, 2.0),
+                ],
+            ),
+            UnitSId::Tesla => (1.0, &[(Kilogram, 1.0), (Second, -2.0), (Ampere, -1.0)]),
+            UnitSId::Volt => (
+                1.0,
+                &[
+                    (Metre, 2.0),
+                    (Kilogram, 1.0),
+                    (Second, -3.0),
+                    (Ampere, -1.0),
+                ],
+            ),
+            UnitSId::Watt => (1.0, &[(Metre, 2.0), (Kilogram, 1.0), (Second, -3.0)]),
+            UnitSId::Weber => (
+                1.0,
+                &[
+                    (Metre, 2.0),
+                    (Kilogram, 1.0),
+                    (Second, -2.0),
+                    (Ampere, -1.0),
+                ],
+            ),
+        };
+        DerivedUnit {
+            factor,
+            exponents: exponents.iter().copied().collect(),
+        }
+    }
+}
+
+impl Unit {
+    // (multiplier * 10^scale * kind)^exponent, None for an unknown kind
+    pub fn derived(&self) -> Option<DerivedUnit> {
+        let kind = UnitSId::from_name(self.kind.as_deref()?)?;
+        let factor = self.multiplier.unwrap_or(1.0) * 10f64.powi(self.scale.unwrap_or(0) as i32);
+        Some(
+            kind.derived()
+                .scaled(factor)
+                .pow(self.exponent.unwrap_or(1.0)),
+        )
+    }
+}
+
+impl UnitDefinition {
+    // The product of its units, None if any of them has an unknown kind
+    pub fn derived(&self, model: &Model) -> Option<DerivedUnit> {
+        let mut result = DerivedUnit::dimensionless();
+        if let Some(Tag::ListOfUnits(list_of_units)) =
+            self.list_of_units.map(|idx| &model.nodes[idx])
+        {
+            for idx in &list_of_units.units {
+                if let Tag::Unit(unit) = &model.nodes[*idx] {
+                    result = result * unit.derived()?;
+                }
+            }
+        }
+        Some(result)
+    }
+}
+
+impl Model {
+    // Expands a unit definition id or a unit kind into base units. The
+    // Level 2 built-in units substance, volume, area, length and time
+    // have their default meaning unless the model redefines them.
+    pub fn resolve_units(&self, units: &str) -> Option<DerivedUnit> {
+        let definition = self.nodes.iter().find_map(|tag| match tag {
+            Tag::UnitDefinition(definition) if definition.id.as_deref() == Some(units) => {
+                Some(definition)
+            }
+            _ => None,
+        });
+        if let Some(definition) = definition {
+            return definition.derived(self);
+        }
+        if let Some(kind) = UnitSId::from_name(units) {
+            return Some(kind.derived());
+        }
+        let built_in = match units {
+            "substance" => UnitSId::Mole.derived(),
+            "volume" => UnitSId::Litre.derived(),
+            "area" => UnitSId::Metre.derived().pow(2.0),
+            "length" => UnitSId::Metre.derived(),
+            "time" => UnitSId::Second.derived(),
+            _ => return None,
+        };
+        Some(built_in)
+    }
+
+    // Units of the value a species, compartment or parameter id stands for
+    // in math. None if the id is unknown or its units are not declared.
+    pub fn units_of(&self, id: &str) -> Option<DerivedUnit> {
+        let tag = self.get_by_sid(id)?;
+        match tag {
+            Tag::Species(species) => self.species_units(species),
+            Tag::Compartment(compartment) => self.compartment_units(compartment),
+            Tag::Parameter(parameter) => self.resolve_units(parameter.units.as_deref()?),
+            _ => None,
+        }
+    }
+
+    // A species stands for its amount if it has only substance units,
+    // otherwise for its concentration
+    fn species_units(&self, species: &Species) -> Option<DerivedUnit> {
+        let substance_units = species
+            .substance_units
+            .as_ref()
+            .or(self.substance_units.as_ref())?;
+        let substance = self.resolve_units(substance_units)?;
+        if species.has_only_substance_units == Some(true) {
+            return Some(substance);
+        }
+        Some(substance / self.species_size_units(species)?)
+    }
+
+    // The spatialSizeUnits of a species, or else the units of its compartment
+    pub(crate) fn species_size_units(&self, species: &Species) -> Option<DerivedUnit> {
+        match &species.spatial_size_units {
+            Some(units) => self.resolve_units(units),
+            None => {
+                let compartment_id = species.compartment.as_deref()?;
+                let compartment = self.get_compartment(compartment_id)?;
+                self.compartment_units(compartment)
+            }
+        }
+    }
+
+    // Compartments without units of their own take the model's volume,
+    // area or length units, depending on their spatial dimensions
+    fn compartment_units(&self, compartment: &Compartment) -> Option<DerivedUnit> {
+        if let Some(units) = &compartment.units {
+            return self.resolve_units(units);
+        }
+        let dimensions = compartment.spatial_dimensions?;
+        let units = if dimensions == 3.0 {
+            self.volume_units.as_ref()
+        } else if dimensions == 2.0 {
+            self.area_units.as_ref()
+        } else if dimensions == 1.0 {
+            self.length_units.as_ref()
+        } else if dimensions == 0.0 {
+            return Some(DerivedUnit::dimensionless());
+        } else {
+            None
+        }?;
+        self.resolve_units(units)
+    }
+}
@@ -0,0 +1,362 @@
+use std::collections::HashMap;
+
+use crate::writer::element_name;
+use crate::{Diagnostic, Location, Model, SbmlError, Severity, Tag, TagIndex};
+
+impl Model {
+    // Checks that the model is internally consistent: ids are unique, required
+    // attributes are present, references resolve and constant elements are not
+    // changed by rules or events. Problems are reported with the identifiers
+    // libSBML uses for the same validation rules, e.g. sbml-10301.
+    pub fn validate(&self) -> Vec<Diagnostic> {
+        let mut validator = Validator {
+            model: self,
+            diagnostics: Vec::new(),
+        };
+        validator.check_unique_ids();
+        validator.check_required_attributes();
+        validator.check_references();
+        validator.check_conversion_factors();
+        validator.check_variables();
+        validator.diagnostics
+    }
+
+    // Path of a tag with the ids of the elements on the way,
+    // e.g. /sbml/model/listOfSpecies/species[@id='S1']
+    pub(crate) fn element_path(&self, idx: TagIndex) -> String {
+        let mut segments = Vec::new();
+        let mut current = Some(idx);
+        while let Some(idx) = current {
+            let tag = &self.nodes[idx];
+            if let Some(name) = element_name(tag) {
+                segments.push(match tag.id() {
+                    Some(id) => format!("{}[@id='{}']", name, id),
+                    None => name.to_string(),
+                });
+            }
+            current = tag.parent();
+        }
+        segments.push("/sbml/model".to_string());
+        segments.reverse();
+        segments.join("/")
+    }
+}
+
+struct Validator<'a> {
+    model: &'a Model,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl<'a> Validator<'a> {
+    fn report(&mut self, rule: &'static str, message: String, idx: TagIndex) {
+        self.diagnostics.push(Diagnostic {
+            severity: Severity::Error,
+            rule: Some(rule),
+            message,
+            location: self.location(idx),
+        });
+    }
+
+    fn report_error(&mut self, rule: &'static str, error: SbmlError) {
+        let mut diagnostic = Diagnostic::from_error(Severity::Error, &error);
+        diagnostic.rule = Some(rule);
+        self.diagnostics.push(diagnostic);
+    }
+
+    fn location(&self, idx: TagIndex) -> Location {
+        Location::at(self.model.element_path(idx))
+    }
+
+    // Unit definitions have a namespace of their own and local parameters
+    // only need to be unique within their kinetic law
+    fn check_unique_ids(&mut self) {
+        // None stands for the model itself
+        let mut ids: HashMap<&str, Option<TagIndex>> = HashMap::new();
+        let mut unit_ids: HashMap<&str, Option<TagIndex>> = HashMap::new();
+        let model = self.model;
+        if let Some(id) = &model.id {
+            ids.insert(id.as_str(), None);
+        }
+        for (idx, tag) in model.nodes.iter().enumerate() {
+            let id = match tag.id() {
+                Some(id) => id.as_str(),
+                None => continue,
+            };
+            let (rule, seen) = match tag {
+                Tag::LocalParameter(_) => continue,
+                Tag::UnitDefinition(_) => ("sbml-10302", &mut unit_ids),
+                _ => ("sbml-10301", &mut ids),
+            };
+            match seen.get(id) {
+                Some(first) => {
+                    let first = match first {
+                        Some(first) => model.element_path(*first),
+                        None => "/sbml/model".to_string(),
+                    };
+                    let message = format!("Duplicate id '{}', already used at {}", id, first);
+                    self.report(rule, message, idx);
+                }
+                None => {
+                    seen.insert(id, Some(idx));
+                }
+            }
+        }
+    }
+
+    fn check_required_attributes(&mut self) {
+        for (idx, tag) in self.model.nodes.iter().enumerate() {
+            let (rule, attributes) = match required_attributes(tag) {
+                Some(required) => required,
+                None => continue,
+            };
+            for (attribute, present) in attributes {
+                if !present {
+                    self.report_error(
+                        rule,
+                        SbmlError::MissingRequiredAttribute {
+                            attribute: attribute.to_string(),
+                            location: self.location(idx),
+                        },
+                    );
+                }
+            }
+        }
+    }
+
+    fn check_references(&mut self) {
+        let model = self.model;
+        let ids = ids_by_kind(model);
+        for (idx, tag) in model.nodes.iter().enumerate() {
+            let (rule, attribute, value, kinds): (_, _, _, &[Kind]) = match tag {
+                Tag::Species(t) => (
+                    "sbml-20601",
+                    "compartment",
+                    &t.compartment,
+                    &[Kind::Compartment],
+                ),
+                Tag::SpeciesReference(t) => ("sbml-21111", "species", &t.species, &[Kind::Species]),
+                Tag::ModifierSpeciesReference(t) => {
+                    ("sbml-21111", "species", &t.species, &[Kind::Species])
+                }
+                Tag::AssignmentRule(t) => ("sbml-20901", "variable", &t.variable, &Kind::VARIABLE),
+                Tag::RateRule(t) => ("sbml-20902", "variable", &t.variable, &Kind::VARIABLE),
+                Tag::InitialAssignment(t) => ("sbml-20801", "symbol", &t.symbol, &Kind::VARIABLE),
+                Tag::EventAssignment(t) => ("sbml-21211", "variable", &t.variable, &Kind::VARIABLE),
+                _ => continue,
+            };
+            // a missing attribute has been reported already
+            if let Some(value) = value {
+                let resolves = ids
+                    .get(value.as_str())
+                    .map(|(kind, _)| kinds.contains(kind));
+                if resolves != Some(true) {
+                    self.report_error(
+                        rule,
+                        SbmlError::DanglingReference {
+                            attribute: attribute.to_string(),
+                            value: value.clone(),
+                            location: self.location(idx),
+                        },
+                    );
+                }
+            }
+        }
+    }
+
+    // Conversion factors of the model and of species must be constant
+    // parameters
+    fn check_conversion_factors(&mut self) {
+        let model = self.model;
+        let ids = ids_by_kind(model);
+        let mut factors = vec![("sbml-20705", &model.conversion_factor, None)];
+        for (idx, tag) in model.nodes.iter().enumerate() {
+            if let Tag::Species(t) = tag {
+                factors.push(("sbml-20617", &t.conversion_factor, Some(idx)));
+            }
+        }
+        for (rule, factor, idx) in factors {
+            let factor = match factor {
+                Some(factor) => factor,
+                None => continue,
+            };
+            let location = match idx {
+                Some(idx) => self.location(idx),
+                None => Location::at("/sbml/model".to_string()),
+            };
+            match ids.get(factor.as_str()) {
+                Some((Kind::Parameter, Some(true))) => {}
+                Some((Kind::Parameter, _)) => self.diagnostics.push(Diagnostic {
+                    severity: Severity::Error,
+                    rule: Some(rule),
+                    message: format!("Conversion factor '{}' is not a constant parameter", factor),
+                    location,
+                }),
+                _ => self.report_error(
+                    rule,
+                    SbmlError::DanglingReference {
+                        attribute: "conversionFactor".to_string(),
+                        value: factor.clone(),
+                        location,
+                    },
+                ),
+            }
+        }
+    }
+
+    // Rules and events may not change constant elements, and at most one
+    // assignment or rate rule may determine each variable
+    fn check_variables(&mut self) {
+        let model = self.model;
+        let ids = ids_by_kind(model);
+        let mut ruled: HashMap<&str, TagIndex> = HashMap::new();
+        for (idx, tag) in model.nodes.iter().enumerate() {
+            let (rule, variable, changed_by) = match tag {
+                Tag::AssignmentRule(t) => ("sbml-20903", &t.variable, "an assignment rule"),
+                Tag::RateRule(t) => ("sbml-20904", &t.variable, "a rate rule"),
+                Tag::EventAssignment(t) => ("sbml-21212", &t.variable, "an event assignment"),
+                _ => continue,
+            };
+            let variable = match variable {
+                Some(variable) => variable.as_str(),
+                None => continue,
+            };
+            if let Some((_, Some(true))) = ids.get(variable) {
+                let message = format!(
+                    "'{}' is constant and cannot be changed by {}",
+                    variable, changed_by
+                );
+                self.report(rule, message, idx);
+            }
+            if let Tag::AssignmentRule(_) | Tag::RateRule(_) = tag {
+                match ruled.get(variable) {
+                    Some(first) => {
+                        let message = format!(
+                            "'{}' is already determined by the rule at {}",
+                            variable,
+                            model.element_path(*first)
+                        );
+                        self.report("sbml-10304", message, idx);
+                    }
+                    None => {
+                        ruled.insert(variable, idx);
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Kind {
+    Compartment,
+    Species,
+    Parameter,
+    SpeciesReference,
+    Other,
+}
+
+impl Kind {
+    // What rules, initial assignments and event assignments may refer to
+    const VARIABLE: [Kind; 4] = [
+        Kind::Compartment,
+        Kind::Species,
+        Kind::Parameter,
+        Kind::SpeciesReference,
+    ];
+}
+
+// Every id in the SId namespace with the kind of element it belongs to and
+// whether that element is constant
+fn ids_by_kind(model: &Model) -> HashMap<&str, (Kind, Option<bool>)> {
+    let mut result = HashMap::new();
+    for tag in &model.nodes {
+        let kind = match tag {
+            Tag::Compartment(t) => (Kind::Compartment, t.constant),
+            Tag::Species(t) => (Kind::Species, t.constant),
+            Tag::Parameter(t) => (Kind::Parameter, t.constant),
+            Tag::SpeciesReference(t) => (Kind::SpeciesReference, t.constant),
+            Tag::LocalParameter(_) | Tag::UnitDefinition(_) => continue,
+            _ => (Kind::Other, None),
+        };
+        if let Some(id) = tag.id() {
+            // the first element wins, duplicates are reported separately
+            result.entry(id.as_str()).or_insert(kind);
+        }
+    }
+    result
+}
+
+// The rule requiring the attributes of an element in Level 3 Version 2 and
+// whether each of them is present
+fn required_attributes(tag: &Tag) -> Option<(&'static str, Vec<(&'static str, bool)>)> {
+    let required = match tag {
+        Tag::FunctionDefinition(t) => ("sbml-20306", vec![("id", t.id.is_some())]),
+        Tag::UnitDefinition(t) => ("sbml-20419", vec![("id", t.id.is_some())]),
+        Tag::Unit(t) => (
+            "sbml-20421",
+            vec![
+                ("kind", t.kind.is_some()),
+                ("exponent", t.exponent.is_some()),
+                ("scale", t.scale.is_some()),
+                ("multiplier", t.multiplier.is_some()),
+            ],
+        ),
+        Tag::Compartment(t) => (
+            "sbml-20517",
+            vec![("id", t.id.is_some()), ("constant", t.constant.is_some())],
+        ),
+        Tag::Species(t) => (
+            "sbml-20623",
+            vec![
+                ("id", t.id.is_some()),
+                ("compartment", t.compartment.is_some()),
+                (
+                    "hasOnlySubstanceUnits",
+                    t.has_only_substance_units.is_some(),
+                ),
+                ("boundaryCondition", t.boundary_condition.is_some()),
+                ("constant", t.constant.is_some()),
+            ],
+        ),
+        Tag::Parameter(t) => (
+            "sbml-20706",
+            vec![("id", t.id.is_some()), ("constant", t.constant.is_some())],
+        ),
+        Tag::InitialAssignment(t) => ("sbml-20805", vec![("symbol", t.symbol.is_some())]),
+        Tag::AssignmentRule(t) => ("sbml-20908", vec![("variable", t.variable.is_some())]),
+        Tag::RateRule(t) => ("sbml-20909", vec![("variable", t.variable.is_some())]),
+        Tag::Reaction(t) => (
+            "sbml-21110",
+            vec![
+                ("id", t.id.is_some()),
+                ("reversible", t.reversible.is_some()),
+            ],
+        ),
+        Tag::SpeciesReference(t) => (
+            "sbml-21116",
+            vec![
+                ("species", t.species.is_some()),
+                ("constant", t.constant.is_some()),
+            ],
+        ),
+        Tag::ModifierSpeciesReference(t) => ("sbml-21117", vec![("species", t.species.is_some())]),
+        Tag::LocalParameter(t) => ("sbml-21172", vec![("id", t.id.is_some())]),
+        Tag::EventAssignment(t) => ("sbml-21214", vec![("variable", t.variable.is_some())]),
+        Tag::Event(t) => (
+            "sbml-21225",
+            vec![(
+                "useValuesFromTriggerTime",
+                t.use_values_from_trigger_time.is_some(),
+            )],
+        ),
+        Tag::Trigger(t) => (
+            "sbml-21226",
+            vec![
+                ("initialValue", t.initial_value.is_some()),
+                ("persistent", t.persistent.is_some()),
+            ],
+        ),
+        _ => return None,
+    };
+    Some(required)
+}
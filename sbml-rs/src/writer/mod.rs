@@ -0,0 +1,512 @@
+use std::io::Write;
+
+use quick_xml::events::{BytesDecl, BytesEnd, BytesStart, BytesText, Event};
+use quick_xml::{Result, Writer};
+
+use crate::mathml::write_math;
+use crate::{levels, Model, SbmlDocument, SbmlError, Tag, TagIndex};
+
+pub const SBML_L3V2_NS: &str = "http://www.sbml.org/sbml/level3/version2/core";
+
+impl Model {
+    // Serializes the model as an SBML Level 3 Version 2 document
+    pub fn to_sbml_string(&self) -> std::result::Result<String, SbmlError> {
+        let mut out = Vec::new();
+        self.write_sbml(&mut out)?;
+        Ok(String::from_utf8_lossy(&out).to_string())
+    }
+
+    // Writes the model as an SBML Level 3 Version 2 document. Attributes are
+    // written in a fixed order and nested elements are indented by two spaces.
+    // Level 2 stoichiometryMath becomes an assignment rule to the species
+    // reference, other Level 2 only constructs are left out.
+    pub fn write_sbml<W: Write>(&self, out: W) -> std::result::Result<(), SbmlError> {
+        write_sbml(out, self, None)
+    }
+}
+
+impl SbmlDocument {
+    // Serializes the document as SBML Level 3 Version 2, see Model::write_sbml
+    pub fn to_sbml_string(&self) -> std::result::Result<String, SbmlError> {
+        let mut out = Vec::new();
+        self.write_sbml(&mut out)?;
+        Ok(String::from_utf8_lossy(&out).to_string())
+    }
+
+    // Writes the model like Model::write_sbml, keeping the namespaces, package
+    // flags, notes and annotation of the <sbml> element
+    pub fn write_sbml<W: Write>(&self, out: W) -> std::result::Result<(), SbmlError> {
+        write_sbml(out, &self.model, Some(self))
+    }
+}
+
+fn write_sbml<W: Write>(
+    out: W,
+    model: &Model,
+    document: Option<&SbmlDocument>,
+) -> std::result::Result<(), SbmlError> {
+    let converted;
+    let model = if model
+        .nodes
+        .iter()
+        .any(|tag| matches!(tag, Tag::StoichiometryMath(_)))
+    {
+        converted = levels::stoichiometry_math_to_rules(model);
+        &converted
+    } else {
+        model
+    };
+    let mut writer = Writer::new_with_indent(out, b' ', 2);
+    write_document(&mut writer, model, document).map_err(|e| SbmlError::Write {
+        message: e.to_string(),
+    })
+}
+
+trait AttributeValue {
+    fn to_attribute(&self) -> String;
+}
+
+impl AttributeValue for String {
+    fn to_attribute(&self) -> String {
+        self.clone()
+    }
+}
+
+impl AttributeValue for bool {
+    fn to_attribute(&self) -> String {
+        self.to_string()
+    }
+}
+
+impl AttributeValue for i32 {
+    fn to_attribute(&self) -> String {
+        self.to_string()
+    }
+}
+
+impl AttributeValue for i64 {
+    fn to_attribute(&self) -> String {
+        self.to_string()
+    }
+}
+
+impl AttributeValue for f64 {
+    fn to_attribute(&self) -> String {
+        format_double(*self)
+    }
+}
+
+// Shortest representation that reads back to the same value,
+// with the spelling SBML uses for the special values
+pub(crate) fn format_double(value: f64) -> String {
+    if value.is_nan() {
+        "NaN".to_string()
+    } else if value == f64::INFINITY {
+        "INF".to_string()
+    } else if value == f64::NEG_INFINITY {
+        "-INF".to_string()
+    } else {
+        format!("{:?}", value)
+    }
+}
+
+// Attributes in the order they are written, unset ones are left out
+#[derive(Default)]
+struct Attributes(Vec<(&'static str, String)>);
+
+impl Attributes {
+    fn add<T: AttributeValue>(mut self, key: &'static str, value: &Option<T>) -> Self {
+        if let Some(value) = value {
+            self.0.push((key, value.to_attribute()));
+        }
+        self
+    }
+}
+
+fn write_document<W: Write>(
+    writer: &mut Writer<W>,
+    model: &Model,
+    document: Option<&SbmlDocument>,
+) -> Result<()> {
+    writer.write_event(Event::Decl(BytesDecl::new(b"1.0", Some(b"UTF-8"), None)))?;
+    let mut sbml = BytesStart::borrowed_name(b"sbml");
+    sbml.push_attribute(("xmlns", SBML_L3V2_NS));
+    sbml.push_attribute(("level", "3"));
+    sbml.push_attribute(("version", "2"));
+    if let Some(document) = document {
+        // the core namespace is replaced, the others are kept for annotations
+        // and packages that use them
+        let mut prefixes: Vec<&String> = document
+            .namespaces
+            .keys()
+            .filter(|prefix| !prefix.is_empty())
+            .collect();
+        prefixes.sort();
+        for prefix in prefixes {
+            let key = format!("xmlns:{}", prefix);
+            sbml.push_attribute((key.as_str(), document.namespaces[prefix].as_str()));
+        }
+        for package in &document.packages {
+            let key = format!("{}:required", package.prefix);
+            sbml.push_attribute((key.as_str(), package.required.to_string().as_str()));
+        }
+    }
+    writer.write_event(Event::Start(sbml))?;
+    if let Some(document) = document {
+        write_raw(writer, "notes", document.notes.as_ref())?;
+        write_raw(writer, "annotation", document.annotation.as_ref())?;
+    }
+
+    let attributes = Attributes::default()
+        .add("id", &model.id)
+        .add("metaid", &model.meta_id)
+        .add("name", &model.name)
+        .add("sboTerm", &model.sbo_term)
+        .add("substanceUnits", &model.substance_units)
+        .add("timeUnits", &model.time_units)
+        .add("volumeUnits", &model.volume_units)
+        .add("areaUnits", &model.area_units)
+        .add("lengthUnits", &model.length_units)
+        .add("extentUnits", &model.extent_units)
+        .add("conversionFactor", &model.conversion_factor);
+    // the lists in the order the specification gives them
+    let children = match &model.nodes[0] {
+        Tag::Root(root) => vec![
+            root.list_of_function_definitions,
+            root.list_of_unit_definitions,
+            root.list_of_compartments,
+            root.list_of_species,
+            root.list_of_parameters,
+            root.list_of_initial_assignments,
+            root.list_of_rules,
+            root.list_of_constraints,
+            root.list_of_reactions,
+            root.list_of_events,
+        ],
+        _ => Vec::new(),
+    };
+    let children: Vec<TagIndex> = children.into_iter().flatten().collect();
+    write_element(
+        writer,
+        model,
+        "model",
+        attributes,
+        model.notes.as_ref(),
+        model.annotation.as_ref(),
+        &children,
+        None,
+    )?;
+
+    writer.write_event(Event::End(BytesEnd::borrowed(b"sbml")))?;
+    Ok(())
+}
+
+// Name of the element a tag stands for, Level 2 only elements included.
+// None for the root and math, which are not SBML elements of their own.
+pub(crate) fn element_name(tag: &Tag) -> Option<&'static str> {
+    match tag {
+        Tag::ListOfCompartmentTypes(_) => Some("listOfCompartmentTypes"),
+        Tag::CompartmentType(_) => Some("compartmentType"),
+        Tag::ListOfSpeciesTypes(_) => Some("listOfSpeciesTypes"),
+        Tag::SpeciesType(_) => Some("speciesType"),
+        Tag::StoichiometryMath(_) => Some("stoichiometryMath"),
+        _ => describe(tag).map(|(name, _, _)| name),
+    }
+}
+
+// Element name, attributes and children of a tag, in document order.
+// Returns None for tags that do not exist in Level 3.
+fn describe(tag: &Tag) -> Option<(&'static str, Attributes, Vec<Option<TagIndex>>)> {
+    let list = |indices: &Vec<TagIndex>| indices.iter().map(|idx| Some(*idx)).collect();
+    let a = Attributes::default();
+    let description = match tag {
+        Tag::ListOfUnitDefinitions(t) => ("listOfUnitDefinitions", a, list(&t.unit_definitions)),
+        Tag::UnitDefinition(t) => (
+            "unitDefinition",
+            a.add("id", &t.id)
+                .add("metaid", &t.metaid)
+                .add("name", &t.name),
+            vec![t.list_of_units],
+        ),
+        Tag::ListOfUnits(t) => ("listOfUnits", a, list(&t.units)),
+        Tag::Unit(t) => (
+            "unit",
+            a.add("metaid", &t.metaid)
+                .add("kind", &t.kind)
+                .add("exponent", &t.exponent)
+                .add("scale", &t.scale)
+                .add("multiplier", &t.multiplier),
+            Vec::new(),
+        ),
+        Tag::ListOfCompartments(t) => ("listOfCompartments", a, list(&t.compartments)),
+        Tag::Compartment(t) => (
+            "compartment",
+            a.add("id", &t.id)
+                .add("metaid", &t.metaid)
+                .add("name", &t.name)
+                .add("sboTerm", &t.sbo_term)
+                .add("spatialDimensions", &t.spatial_dimensions)
+                .add("size", &t.size)
+                .add("units", &t.units)
+                .add("constant", &t.constant),
+            Vec::new(),
+        ),
+        Tag::ListOfParameters(t) => ("listOfParameters", a, list(&t.parameters)),
+        Tag::Parameter(t) => (
+            "parameter",
+            a.add("id", &t.id)
+                .add("metaid", &t.metaid)
+                .add("name", &t.name)
+                .add("sboTerm", &t.sbo_term)
+                .add("value", &t.value)
+                .add("units", &t.units)
+                .add("constant", &t.constant),
+            Vec::new(),
+        ),
+        Tag::ListOfSpecies(t) => ("listOfSpecies", a, list(&t.species)),
+        Tag::Species(t) => (
+            "species",
+            a.add("id", &t.id)
+                .add("metaid", &t.meta_id)
+                .add("name", &t.name)
+                .add("sboTerm", &t.sbo_term)
+                .add("compartment", &t.compartment)
+                .add("initialAmount", &t.initial_amount)
+                .add("initialConcentration", &t.initial_concentration)
+                .add("substanceUnits", &t.substance_units)
+                .add("hasOnlySubstanceUnits", &t.has_only_substance_units)
+                .add("boundaryCondition", &t.boundary_condition)
+                .add("constant", &t.constant)
+                .add("conversionFactor", &t.conversion_factor),
+            Vec::new(),
+        ),
+        Tag::ListOfReactions(t) => ("listOfReactions", a, list(&t.reactions)),
+        Tag::Reaction(t) => (
+            "reaction",
+            a.add("id", &t.id)
+                .add("metaid", &t.metaid)
+                .add("name", &t.name)
+                .add("sboTerm", &t.sbo_term)
+                .add("reversible", &t.reversible)
+                .add("compartment", &t.compartment),
+            vec![
+                t.list_of_reactants,
+                t.list_of_products,
+                t.list_of_modifiers,
+                t.kinetic_law,
+            ],
+        ),
+        Tag::ListOfReactants(t) => ("listOfReactants", a, list(&t.species_references)),
+        Tag::ListOfProducts(t) => ("listOfProducts", a, list(&t.species_references)),
+        Tag::SpeciesReference(t) => (
+            "speciesReference",
+            a.add("id", &t.id)
+                .add("metaid", &t.metaid)
+                .add("name", &t.name)
+                .add("sboTerm", &t.sbo_term)
+                .add("species", &t.species)
+                .add("stoichiometry", &t.stoichiometry)
+                .add("constant", &t.constant),
+            Vec::new(),
+        ),
+        Tag::ListOfModifiers(t) => ("listOfModifiers", a, list(&t.modifier_species_references)),
+        Tag::ModifierSpeciesReference(t) => (
+            "modifierSpeciesReference",
+            a.add("id", &t.id)
+                .add("metaid", &t.metaid)
+                .add("name", &t.name)
+                .add("sboTerm", &t.sbo_term)
+                .add("species", &t.species),
+            Vec::new(),
+        ),
+        Tag::KineticLaw(t) => (
+            "kineticLaw",
+            a.add("metaid", &t.metaid).add("sboTerm", &t.sbo_term),
+            vec![t.math, t.list_of_local_parameters],
+        ),
+        Tag::ListOfLocalParameters(t) => ("listOfLocalParameters", a, list(&t.local_parameters)),
+        Tag::LocalParameter(t) => (
+            "localParameter",
+            a.add("id", &t.id)
+                .add("metaid", &t.metaid)
+                .add("name", &t.name)
+                .add("sboTerm", &t.sbo_term)
+                .add("value", &t.value)
+                .add("units", &t.units),
+            Vec::new(),
+        ),
+        Tag::ListOfFunctionDefinitions(t) => (
+            "listOfFunctionDefinitions",
+            a,
+            list(&t.function_definitions),
+        ),
+        Tag::FunctionDefinition(t) => (
+            "functionDefinition",
+            a.add("id", &t.id)
+                .add("metaid", &t.metaid)
+                .add("name", &t.name)
+                .add("sboTerm", &t.sbo_term),
+            vec![t.math],
+        ),
+        Tag::ListOfInitialAssignments(t) => {
+            ("listOfInitialAssignments", a, list(&t.initial_assignments))
+        }
+        Tag::InitialAssignment(t) => (
+            "initialAssignment",
+            a.add("id", &t.id)
+                .add("metaid", &t.metaid)
+                .add("sboTerm", &t.sbo_term)
+                .add("symbol", &t.symbol),
+            vec![t.math],
+        ),
+        Tag::ListOfRules(t) => {
+            // each kind of rule has its own list, indices follow document order
+            let mut rules: Vec<TagIndex> = t.assignment_rules.clone();
+            rules.extend(&t.rate_rules);
+            rules.extend(&t.algebraic_rules);
+            rules.sort_unstable();
+            ("listOfRules", a, list(&rules))
+        }
+        Tag::AssignmentRule(t) => (
+            "assignmentRule",
+            a.add("id", &t.id)
+                .add("metaid", &t.metaid)
+                .add("sboTerm", &t.sbo_term)
+                .add("variable", &t.variable),
+            vec![t.math],
+        ),
+        Tag::RateRule(t) => (
+            "rateRule",
+            a.add("id", &t.id)
+                .add("metaid", &t.metaid)
+                .add("sboTerm", &t.sbo_term)
+                .add("variable", &t.variable),
+            vec![t.math],
+        ),
+        Tag::AlgebraicRule(t) => (
+            "algebraicRule",
+            a.add("id", &t.id)
+                .add("metaid", &t.metaid)
+                .add("sboTerm", &t.sbo_term),
+            vec![t.math],
+        ),
+        Tag::ListOfEvents(t) => ("listOfEvents", a, list(&t.events)),
+        Tag::Event(t) => (
+            "event",
+            a.add("id", &t.id)
+                .add("metaid", &t.metaid)
+                .add("name", &t.name)
+                .add("sboTerm", &t.sbo_term)
+                .add("useValuesFromTriggerTime", &t.use_values_from_trigger_time),
+            vec![t.trigger, t.priority, t.delay, t.list_of_event_assignments],
+        ),
+        Tag::Trigger(t) => (
+            "trigger",
+            a.add("metaid", &t.metaid)
+                .add("sboTerm", &t.sbo_term)
+                .add("initialValue", &t.initial_value)
+                .add("persistent", &t.persistent),
+            vec![t.math],
+        ),
+        Tag::Delay(t) => (
+            "delay",
+            a.add("metaid", &t.metaid).add("sboTerm", &t.sbo_term),
+            vec![t.math],
+        ),
+        Tag::Priority(t) => (
+            "priority",
+            a.add("metaid", &t.metaid).add("sboTerm", &t.sbo_term),
+            vec![t.math],
+        ),
+        Tag::ListOfEventAssignments(t) => ("listOfEventAssignments", a, list(&t.event_assignments)),
+        Tag::EventAssignment(t) => (
+            "eventAssignment",
+            a.add("metaid", &t.metaid)
+                .add("sboTerm", &t.sbo_term)
+                .add("variable", &t.variable),
+            vec![t.math],
+        ),
+        Tag::ListOfConstraints(t) => ("listOfConstraints", a, list(&t.constraints)),
+        Tag::Constraint(t) => (
+            "constraint",
+            a.add("id", &t.id)
+                .add("metaid", &t.metaid)
+                .add("sboTerm", &t.sbo_term),
+            vec![t.math],
+        ),
+        Tag::Root(_)
+        | Tag::MathTag(_)
+        | Tag::ListOfCompartmentTypes(_)
+        | Tag::CompartmentType(_)
+        | Tag::ListOfSpeciesTypes(_)
+        | Tag::SpeciesType(_)
+        | Tag::StoichiometryMath(_) => return None,
+    };
+    Some(description)
+}
+
+fn write_tag<W: Write>(writer: &mut Writer<W>, model: &Model, idx: TagIndex) -> Result<()> {
+    let tag = &model.nodes[idx];
+    if let Tag::MathTag(math_tag) = tag {
+        return write_math(writer, &math_tag.nodes, &math_tag.number_units);
+    }
+    let (name, attributes, children) = match describe(tag) {
+        Some(description) => description,
+        None => return Ok(()),
+    };
+    let children: Vec<TagIndex> = children.into_iter().flatten().collect();
+    let message = match tag {
+        Tag::Constraint(constraint) => constraint.message.as_ref(),
+        _ => None,
+    };
+    write_element(
+        writer,
+        model,
+        name,
+        attributes,
+        tag.notes(),
+        tag.annotation(),
+        &children,
+        message,
+    )
+}
+
+// Writes an element with its notes and annotation first and any
+// constraint message last, as an empty element if it has no content
+#[allow(clippy::too_many_arguments)]
+fn write_element<W: Write>(
+    writer: &mut Writer<W>,
+    model: &Model,
+    name: &str,
+    attributes: Attributes,
+    notes: Option<&String>,
+    annotation: Option<&String>,
+    children: &[TagIndex],
+    message: Option<&String>,
+) -> Result<()> {
+    let mut start = BytesStart::borrowed_name(name.as_bytes());
+    for (key, value) in &attributes.0 {
+        start.push_attribute((*key, value.as_str()));
+    }
+    if notes.is_none() && annotation.is_none() && children.is_empty() && message.is_none() {
+        return writer.write_event(Event::Empty(start));
+    }
+    writer.write_event(Event::Start(start))?;
+    write_raw(writer, "notes", notes)?;
+    write_raw(writer, "annotation", annotation)?;
+    for child in children {
+        write_tag(writer, model, *child)?;
+    }
+    write_raw(writer, "message", message)?;
+    writer.write_event(Event::End(BytesEnd::borrowed(name.as_bytes())))
+}
+
+// Notes, annotations and messages are kept as XML text and written back verbatim
+fn write_raw<W: Write>(writer: &mut Writer<W>, name: &str, content: Option<&String>) -> Result<()> {
+    if let Some(content) = content {
+        writer.write_event(Event::Start(BytesStart::borrowed_name(name.as_bytes())))?;
+        writer.write_event(Event::Text(BytesText::from_escaped_str(content.as_str())))?;
+        writer.write_event(Event::End(BytesEnd::borrowed(name.as_bytes())))?;
+    }
+    Ok(())
+}